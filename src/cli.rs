@@ -0,0 +1,187 @@
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use clap::{Args, Parser, Subcommand};
+use rushttp::config::{self, Config, Listener, Tls};
+use rushttp::config::error::ConfigError;
+
+#[derive(Debug, Parser)]
+#[command(name = "rushttp", version, about = "HTTP server written in Rust")]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+    #[command(flatten)]
+    pub opts: Options,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Subcommand)]
+pub enum Command {
+    /// Start the server (default)
+    Serve,
+    /// Load and validate the configuration, then exit
+    CheckConfig,
+    /// Print the routes the server would serve
+    Routes,
+}
+
+/// Settings given on the command line or by environment variables.
+/// They take precedence over the config file.
+#[derive(Debug, Default, Args)]
+pub struct Options {
+    /// Config file [default: /etc/rushttp/rushttp.toml when it exists]
+    #[arg(short, long, global = true, env = "RUSHTTP_CONFIG", value_name = "FILE")]
+    pub config: Option<PathBuf>,
+    /// Address to listen on, with or without a port, or `unix:PATH`. Replaces the configured listeners
+    #[arg(short, long, global = true, env = "RUSHTTP_BIND", value_name = "ADDR")]
+    pub bind: Option<String>,
+    /// Port to listen on
+    #[arg(short, long, global = true, env = "RUSHTTP_PORT")]
+    pub port: Option<u16>,
+    /// Document root served at `/`
+    #[arg(short, long, global = true, env = "RUSHTTP_ROOT", value_name = "DIR")]
+    pub root: Option<PathBuf>,
+    /// Number of worker threads
+    #[arg(short, long, global = true, env = "RUSHTTP_THREADS", value_name = "N")]
+    pub threads: Option<usize>,
+    /// Diagnostic log level, e.g. `debug` or `info,rushttp::server=trace`
+    #[arg(long, global = true, env = "RUSHTTP_LOG_LEVEL", value_name = "LEVEL")]
+    pub log_level: Option<String>,
+    /// TLS certificate chain in PEM format
+    #[arg(long, global = true, env = "RUSHTTP_TLS_CERT", value_name = "FILE", requires = "tls_key")]
+    pub tls_cert: Option<PathBuf>,
+    /// TLS private key in PEM format
+    #[arg(long, global = true, env = "RUSHTTP_TLS_KEY", value_name = "FILE", requires = "tls_cert")]
+    pub tls_key: Option<PathBuf>,
+}
+
+impl Options {
+    /// Where the configuration is read from, or None when running on defaults.
+    pub fn config_path(&self) -> Option<PathBuf> {
+        match &self.config {
+            Some(path) => Some(path.clone()),
+            None => {
+                let path = Path::new(config::DEFAULT_PATH);
+                if path.exists() { Some(path.to_path_buf()) } else { None }
+            },
+        }
+    }
+
+    /// Load the config file, apply the overrides and validate the result.
+    pub fn load(&self) -> Result<Config, ConfigError> {
+        let mut config = match self.config_path() {
+            Some(path) => Config::read(&path)?,
+            None => Config::default(),
+        };
+        self.apply(&mut config);
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn apply(&self, config: &mut Config) {
+        if let Some(root) = &self.root {
+            config.root = root.clone();
+        }
+        if let Some(threads) = self.threads {
+            config.threads = threads;
+        }
+        if let Some(level) = &self.log_level {
+            config.log.level = level.clone();
+        }
+        if let (Some(cert), Some(key)) = (&self.tls_cert, &self.tls_key) {
+            config.tls = Some(Tls { cert: cert.clone(), key: key.clone() });
+        }
+        if let Some(path) = self.bind.as_deref().and_then(|b| b.strip_prefix("unix:")) {
+            config.listeners = vec![Listener::unix(Path::new(path))];
+        } else if self.bind.is_some() || self.port.is_some() {
+            let current = config.listeners.iter()
+                .find_map(|l| l.address.clone())
+                .unwrap_or_else(|| "0.0.0.0:80".to_string());
+            let address = listen_address(self.bind.as_deref(), self.port, &current);
+            config.listeners = vec![Listener::tcp(&address)];
+        }
+    }
+}
+
+// Combine --bind and --port with the configured address.
+// Anything unparsable is passed through as is and reported by the config validation.
+fn listen_address(bind: Option<&str>, port: Option<u16>, current: &str) -> String {
+    let current: Option<SocketAddr> = current.parse().ok();
+    let bind = match bind {
+        Some(b) => b,
+        None => return match current {
+            Some(mut addr) => {
+                addr.set_port(port.unwrap_or(addr.port()));
+                addr.to_string()
+            },
+            None => format!("0.0.0.0:{}", port.unwrap_or(80)),
+        },
+    };
+    if let Ok(mut addr) = bind.parse::<SocketAddr>() {
+        if let Some(p) = port {
+            addr.set_port(p);
+        }
+        return addr.to_string();
+    }
+    match bind.parse::<IpAddr>() {
+        Ok(ip) => {
+            let port = port.or(current.map(|c| c.port())).unwrap_or(80);
+            SocketAddr::new(ip, port).to_string()
+        },
+        Err(_) => bind.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    #[test]
+    fn test_parse_args() {
+        let cli = super::Cli::try_parse_from(["rushttp", "check-config", "--port", "8080", "--root", "src/static/assets/html"]).unwrap();
+        assert_eq!(cli.command, Some(super::Command::CheckConfig));
+        assert_eq!(cli.opts.port, Some(8080));
+    }
+    #[test]
+    fn test_load_overrides_file() {
+        let path = std::env::temp_dir().join(format!("rushttp-cli-{}.toml", std::process::id()));
+        std::fs::write(&path, "root = \"/nonexistent\"\n").unwrap();
+        let mut opts = super::Options { config: Some(path.clone()), ..Default::default() };
+        assert!(opts.load().is_err());
+        opts.root = Some("src/static/assets/html".into());
+        assert_eq!(opts.load().unwrap().root, std::path::Path::new("src/static/assets/html"));
+        let _ = std::fs::remove_file(path);
+    }
+    #[test]
+    fn test_parse_args_tls_requires_key() {
+        assert!(super::Cli::try_parse_from(["rushttp", "--tls-cert", "cert.pem"]).is_err());
+    }
+    #[test]
+    fn test_listen_address() {
+        assert_eq!(super::listen_address(None, Some(8080), "0.0.0.0:80"), "0.0.0.0:8080");
+        assert_eq!(super::listen_address(Some("127.0.0.1"), None, "0.0.0.0:9999"), "127.0.0.1:9999");
+        assert_eq!(super::listen_address(Some("127.0.0.1:8000"), Some(8080), "0.0.0.0:80"), "127.0.0.1:8080");
+    }
+    #[test]
+    fn test_apply() {
+        let opts = super::Options {
+            root: Some(super::PathBuf::from("src/static/assets/html")),
+            port: Some(8080),
+            threads: Some(2),
+            ..Default::default()
+        };
+        let mut config = super::Config::default();
+        opts.apply(&mut config);
+        assert_eq!(config.listeners[0].address.as_deref(), Some("0.0.0.0:8080"));
+        assert_eq!(config.threads, 2);
+        assert!(config.validate().is_ok());
+    }
+    #[test]
+    fn test_apply_unix() {
+        let opts = super::Options {
+            bind: Some("unix:/tmp/rushttp.sock".to_string()),
+            ..Default::default()
+        };
+        let mut config = super::Config::default();
+        opts.apply(&mut config);
+        assert_eq!(config.listeners, vec![super::Listener::unix(super::Path::new("/tmp/rushttp.sock"))]);
+    }
+}
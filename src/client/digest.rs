@@ -0,0 +1,160 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Mutex, MutexGuard};
+use crate::client::origin;
+use crate::http::method::Method;
+use crate::http::header::params;
+use crate::server::auth::quote;
+use crate::server::crypto::{encode, random_bytes};
+use crate::server::digest::{response, Algorithm};
+use crate::uri::uri::Uri;
+
+type Origin = (Option<String>, Option<String>, Option<usize>);
+
+/// A user and password to answer Digest challenges with.
+/// The last challenge of each origin is kept and answered ahead of time on later requests.
+pub(crate) struct DigestLogin {
+    user: String,
+    password: String,
+    challenges: Mutex<HashMap<Origin, Challenge>>,
+}
+
+struct Challenge {
+    realm: String,
+    nonce: String,
+    opaque: Option<String>,
+    algorithm: Algorithm,
+    // `qop=auth` was offered, without it the RFC 2069 response is sent
+    qop: bool,
+    // requests sent with the nonce
+    count: u32,
+}
+
+impl DigestLogin {
+    pub(crate) fn new(user: &str, password: &str) -> Self {
+        DigestLogin {
+            user: user.to_string(),
+            password: password.to_string(),
+            challenges: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Keep the best Digest challenge among `WWW-Authenticate` values of a response from `uri`.
+    /// False when none can be answered.
+    pub(crate) fn challenged<'a, I>(&self, uri: &Uri, values: I) -> bool
+        where I: Iterator<Item = &'a str> {
+        let best = values.filter_map(Challenge::parse)
+            .max_by_key(|challenge| (challenge.algorithm == Algorithm::Sha256, challenge.qop));
+        match best {
+            Some(challenge) => {
+                self.lock().insert(origin(uri), challenge);
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// The `Authorization` value for a request to `uri`, when its origin sent a challenge.
+    pub(crate) fn authorization(&self, method: &Method, uri: &Uri) -> Option<String> {
+        let mut challenges = self.lock();
+        let challenge = challenges.get_mut(&origin(uri))?;
+        challenge.count += 1;
+        let nc = format!("{:08x}", challenge.count);
+        let cnonce = encode(&random_bytes(16));
+        let target = uri.origin_form();
+        let algorithm = challenge.algorithm;
+        let ha1 = algorithm.hex(&format!("{}:{}:{}", self.user, challenge.realm, self.password));
+        let qop = Some((nc.as_str(), cnonce.as_str())).filter(|_| challenge.qop);
+        let response = response(algorithm, &ha1, &challenge.nonce, qop, method.as_str(), &target);
+        let mut value = format!(
+            "Digest username={}, realm={}, uri={}, algorithm={}, nonce={}",
+            quote(&self.user), quote(&challenge.realm), quote(&target), algorithm.as_str(), quote(&challenge.nonce),
+        );
+        if challenge.qop {
+            value.push_str(&format!(", qop=auth, nc={}, cnonce=\"{}\"", nc, cnonce));
+        }
+        value.push_str(&format!(", response=\"{}\"", response));
+        if let Some(opaque) = &challenge.opaque {
+            value.push_str(&format!(", opaque={}", quote(opaque)));
+        }
+        Some(value)
+    }
+
+    fn lock(&self) -> MutexGuard<'_, HashMap<Origin, Challenge>> {
+        match self.challenges.lock() {
+            Ok(challenges) => challenges,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+}
+
+impl fmt::Debug for DigestLogin {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("DigestLogin")
+            .field("user", &self.user)
+            .finish()
+    }
+}
+
+impl Challenge {
+    fn parse(value: &str) -> Option<Self> {
+        let (scheme, rest) = value.trim().split_once(' ')?;
+        if !scheme.eq_ignore_ascii_case("Digest") {
+            return None;
+        }
+        let params = params(rest, ',');
+        let param = |name: &str| params.iter().find(|(n, _)| n == name).map(|(_, value)| value.clone());
+        let qop = match param("qop") {
+            Some(qop) => {
+                // `auth-int` alone is not supported
+                if !qop.split(',').any(|qop| qop.trim().eq_ignore_ascii_case("auth")) {
+                    return None;
+                }
+                true
+            },
+            None => false,
+        };
+        Some(Challenge {
+            realm: param("realm")?,
+            nonce: param("nonce")?,
+            opaque: param("opaque"),
+            algorithm: Algorithm::parse(&param("algorithm").unwrap_or_else(|| "MD5".to_string()))?,
+            qop,
+            count: 0,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::http::method::Method;
+    use crate::http::header::params;
+    use crate::uri::uri::Uri;
+    use super::DigestLogin;
+
+    #[test]
+    fn test_authorization() {
+        let login = DigestLogin::new("Mufasa", "Circle of Life");
+        let uri = Uri::new("http://www.example.org/dir/index.html").unwrap();
+        assert_eq!(login.authorization(&Method::GET, &uri), None);
+        let challenges = vec![
+            "Basic realm=\"http-auth@example.org\"",
+            "Digest realm=\"http-auth@example.org\", qop=\"auth, auth-int\", algorithm=MD5, nonce=\"n\"",
+            "Digest realm=\"http-auth@example.org\", qop=\"auth, auth-int\", algorithm=SHA-256, nonce=\"n\", opaque=\"o\"",
+            "Digest realm=\"http-auth@example.org\", qop=\"auth\", algorithm=SHA-512-256, nonce=\"n\"",
+        ];
+        assert!(login.challenged(&uri, challenges.into_iter()));
+        let value = login.authorization(&Method::GET, &uri).unwrap();
+        let params = params(value.strip_prefix("Digest ").unwrap(), ',');
+        let param = |name: &str| params.iter().find(|(n, _)| n == name).map(|(_, value)| value.as_str());
+        assert_eq!(param("algorithm"), Some("SHA-256"));
+        assert_eq!(param("uri"), Some("/dir/index.html"));
+        assert_eq!(param("nc"), Some("00000001"));
+        assert_eq!(param("opaque"), Some("o"));
+        // the nonce count goes up with each request
+        assert!(login.authorization(&Method::GET, &uri).unwrap().contains("nc=00000002"));
+        let other = Uri::new("http://www.example.org:8080/").unwrap();
+        assert_eq!(login.authorization(&Method::GET, &other), None);
+        assert!(!login.challenged(&other, vec!["Basic realm=\"a\""].into_iter()));
+    }
+}
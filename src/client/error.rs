@@ -0,0 +1,112 @@
+use crate::http::error::Error as HttpError;
+use thiserror::Error;
+use std::error;
+use std::fmt;
+
+#[derive(Error)]
+pub struct Error {
+    inner: ErrorKind
+}
+
+#[derive(Error, Debug)]
+pub enum ErrorKind {
+    Http(HttpError),
+    Io(std::io::Error),
+    Redirect(TooManyRedirects),
+}
+
+#[derive(Error)]
+pub struct TooManyRedirects {
+    hops: usize,
+}
+
+impl Error {
+    /// Return true if the underlying error has the same type as T.
+    pub fn is<T: error::Error + 'static>(&self) -> bool {
+        self.get_ref().is::<T>()
+    }
+
+    /// Return a reference to the lower level, inner error.
+    pub fn get_ref(&self) -> &(dyn error::Error + 'static) {
+        use self::ErrorKind::*;
+
+        match self.inner {
+            Http(ref e) => e,
+            Io(ref e) => e,
+            Redirect(ref e) => e,
+        }
+    }
+
+    pub fn kind(&self) -> &ErrorKind {
+        &self.inner
+    }
+}
+
+impl TooManyRedirects {
+    pub fn new(hops: usize) -> Self {
+        TooManyRedirects { hops }
+    }
+
+    /// How many redirects were followed before giving up.
+    pub fn hops(&self) -> usize {
+        self.hops
+    }
+}
+
+impl fmt::Debug for TooManyRedirects {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("TooManyRedirects")
+            .field("hops", &self.hops)
+            .finish()
+    }
+}
+
+impl fmt::Display for TooManyRedirects {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "stopped after {} redirects", self.hops)
+    }
+}
+
+impl fmt::Debug for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("client::Error")
+            .field(&self.get_ref())
+            .finish()
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self.get_ref(), f)
+    }
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("client error kind")
+    }
+}
+
+impl From<HttpError> for Error {
+    fn from(err: HttpError) -> Error {
+        Error {
+            inner: ErrorKind::Http(err),
+        }
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(err: std::io::Error) -> Error {
+        Error {
+            inner: ErrorKind::Io(err),
+        }
+    }
+}
+
+impl From<TooManyRedirects> for Error {
+    fn from(err: TooManyRedirects) -> Error {
+        Error {
+            inner: ErrorKind::Redirect(err),
+        }
+    }
+}
@@ -0,0 +1,202 @@
+use std::fmt;
+use std::net::IpAddr;
+use std::sync::{Mutex, MutexGuard};
+use std::time::SystemTime;
+use crate::http::cookie::SetCookie;
+use crate::uri::uri::Uri;
+
+/// Cookies a client received, sent back with later requests as RFC 6265 describes.
+/// Public suffixes are not known, a `Domain` without a dot is only kept for that very host.
+#[derive(Default)]
+pub struct CookieJar {
+    cookies: Mutex<Vec<Cookie>>,
+}
+
+struct Cookie {
+    name: String,
+    value: String,
+    domain: String,
+    // sent to `domain` only, not to its subdomains
+    host_only: bool,
+    path: String,
+    expires: Option<SystemTime>,
+    secure: bool,
+    created: SystemTime,
+}
+
+impl CookieJar {
+    pub fn new() -> Self {
+        CookieJar::default()
+    }
+
+    /// Store a `Set-Cookie` value of a response to `uri`, one which may not be set from there is ignored.
+    pub fn store(&self, uri: &Uri, set_cookie: &str) {
+        let now = SystemTime::now();
+        let host = match uri.host() {
+            Some(host) => host.to_ascii_lowercase(),
+            None => return,
+        };
+        let set = match SetCookie::parse(set_cookie) {
+            Some(set) => set,
+            None => return,
+        };
+        let (domain, host_only) = match set.get_domain() {
+            Some(domain) => {
+                let domain = domain.trim_start_matches('.').to_ascii_lowercase();
+                if !domain_matches(&host, &domain) || (!domain.contains('.') && domain != host) {
+                    return;
+                }
+                (domain, false)
+            },
+            None => (host, true),
+        };
+        let path = match set.get_path() {
+            Some(path) if path.starts_with('/') => path.to_string(),
+            _ => default_path(uri.path()),
+        };
+        let mut cookies = self.lock();
+        let old = cookies.iter()
+            .position(|c| c.name == set.name() && c.domain == domain && c.path == path)
+            .map(|i| cookies.remove(i));
+        let expires = set.expiry(now);
+        // an expired cookie only removes the one it replaces
+        if expires.is_some_and(|expires| expires <= now) {
+            return;
+        }
+        cookies.push(Cookie {
+            name: set.name().to_string(),
+            value: set.value().to_string(),
+            domain,
+            host_only,
+            path,
+            expires,
+            secure: set.is_secure(),
+            created: old.map_or(now, |old| old.created),
+        });
+    }
+
+    /// The `Cookie` value to send with a request to `uri`, longer paths first.
+    pub fn cookies(&self, uri: &Uri) -> Option<String> {
+        let now = SystemTime::now();
+        let host = uri.host()?.to_ascii_lowercase();
+        let secure = uri.scheme().as_deref() == Some("https");
+        let path = match uri.path() {
+            "" => "/",
+            path => path,
+        };
+        let mut cookies = self.lock();
+        cookies.retain(|c| c.expires.is_none_or(|expires| expires > now));
+        let mut matched: Vec<&Cookie> = cookies.iter()
+            .filter(|c| if c.host_only { c.domain == host } else { domain_matches(&host, &c.domain) })
+            .filter(|c| path_matches(path, &c.path))
+            .filter(|c| secure || !c.secure)
+            .collect();
+        if matched.is_empty() {
+            return None;
+        }
+        matched.sort_by(|a, b| b.path.len().cmp(&a.path.len()).then(a.created.cmp(&b.created)));
+        let pairs: Vec<String> = matched.iter()
+            .map(|c| format!("{}={}", c.name, c.value))
+            .collect();
+        Some(pairs.join("; "))
+    }
+
+    /// Drop every cookie.
+    pub fn clear(&self) {
+        self.lock().clear();
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Vec<Cookie>> {
+        match self.cookies.lock() {
+            Ok(cookies) => cookies,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+}
+
+impl fmt::Debug for CookieJar {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("CookieJar")
+            .field("cookies", &self.lock().len())
+            .finish()
+    }
+}
+
+// the host is the domain or one of its subdomains, an address only matches itself
+fn domain_matches(host: &str, domain: &str) -> bool {
+    if host == domain {
+        return true;
+    }
+    let address = host.trim_start_matches('[').trim_end_matches(']');
+    address.parse::<IpAddr>().is_err()
+        && host.len() > domain.len()
+        && host.ends_with(domain)
+        && host.as_bytes()[host.len() - domain.len() - 1] == b'.'
+}
+
+// the cookie path is the request path or one of its directories
+fn path_matches(path: &str, cookie_path: &str) -> bool {
+    path == cookie_path
+        || (path.starts_with(cookie_path)
+            && (cookie_path.ends_with('/') || path.as_bytes()[cookie_path.len()] == b'/'))
+}
+
+// the directory of the request path, when `Path` is not given
+fn default_path(path: &str) -> String {
+    match path.rfind('/') {
+        Some(0) | None => "/".to_string(),
+        Some(i) => path[..i].to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::uri::uri::Uri;
+    use super::CookieJar;
+
+    fn uri(uri: &str) -> Uri {
+        Uri::new(uri).unwrap()
+    }
+
+    #[test]
+    fn test_domain() {
+        let jar = CookieJar::new();
+        let from = uri("http://www.example.com/");
+        jar.store(&from, "host=1");
+        jar.store(&from, "wide=2; Domain=.Example.com");
+        jar.store(&from, "other=3; Domain=example.org");
+        jar.store(&from, "tld=4; Domain=com");
+        assert_eq!(jar.cookies(&uri("http://www.example.com/")), Some("host=1; wide=2".to_string()));
+        assert_eq!(jar.cookies(&uri("http://api.example.com/")), Some("wide=2".to_string()));
+        assert_eq!(jar.cookies(&uri("http://notexample.com/")), None);
+        assert_eq!(jar.cookies(&uri("http://example.org/")), None);
+    }
+    #[test]
+    fn test_path() {
+        let jar = CookieJar::new();
+        jar.store(&uri("http://localhost/docs/guide/index.html"), "dir=1");
+        jar.store(&uri("http://localhost/"), "root=2; Path=/");
+        jar.store(&uri("http://localhost/"), "deep=3; Path=/docs/guide/");
+        assert_eq!(jar.cookies(&uri("http://localhost/docs/guide/a")), Some("deep=3; dir=1; root=2".to_string()));
+        assert_eq!(jar.cookies(&uri("http://localhost/docs/guide")), Some("dir=1; root=2".to_string()));
+        assert_eq!(jar.cookies(&uri("http://localhost/docs/guidebook")), Some("root=2".to_string()));
+        // secure cookies are kept for https
+        jar.store(&uri("http://localhost/"), "secret=4; Secure; Path=/");
+        assert_eq!(jar.cookies(&uri("https://localhost/")), Some("root=2; secret=4".to_string()));
+    }
+    #[test]
+    fn test_expiry() {
+        let jar = CookieJar::new();
+        let from = uri("http://localhost/");
+        jar.store(&from, "a=1; Max-Age=60");
+        jar.store(&from, "b=2; Expires=Wed, 21 Oct 2015 07:28:00 GMT");
+        jar.store(&from, "c=3");
+        assert_eq!(jar.cookies(&from), Some("a=1; c=3".to_string()));
+        // a replaced cookie keeps its place
+        jar.store(&from, "a=5");
+        jar.store(&from, "c=; Max-Age=0");
+        assert_eq!(jar.cookies(&from), Some("a=5".to_string()));
+        jar.clear();
+        assert_eq!(jar.cookies(&from), None);
+    }
+}
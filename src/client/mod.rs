@@ -0,0 +1,754 @@
+use std::io::{self, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+use log::debug;
+use crate::http::method::Method;
+use crate::http::parser::Parser;
+use crate::http::request::Request;
+use crate::http::response::Response;
+use crate::http::version::Version;
+use crate::uri::uri::{InvalidUri, Uri};
+use crate::server::limits::RESPONSE_LIMITS;
+use crate::server::listener::Stream;
+use crate::server::reader::{framing, Framing, ReadError, Reader};
+
+use crate::client::digest::DigestLogin;
+use crate::client::pool::Pool;
+
+pub use crate::client::error::{Error, ErrorKind, TooManyRedirects};
+pub use crate::client::jar::CookieJar;
+pub use crate::client::retry::Retry;
+
+mod digest;
+mod error;
+mod jar;
+mod pool;
+mod retry;
+
+/// A blocking HTTP/1.1 client.
+/// Connections are kept open and reused for later requests to the same host, clones share them.
+#[derive(Debug, Clone)]
+pub struct Client {
+    user_agent: String,
+    connect_timeout: Duration,
+    read_timeout: Duration,
+    timeout: Option<Duration>,
+    max_redirects: usize,
+    retry: Option<Retry>,
+    jar: Option<Arc<CookieJar>>,
+    digest: Option<Arc<DigestLogin>>,
+    pool: Arc<Pool>,
+}
+
+// why an exchange failed
+enum Failure {
+    // nothing came back, the server may have closed an idle connection just before the request
+    Unanswered(Error),
+    Failed(Error),
+}
+
+// a request as it is sent, kept to send it again on a retry or a redirect
+struct Outgoing {
+    method: Method,
+    uri: Uri,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl Client {
+    pub fn new() -> Self {
+        Client::default()
+    }
+
+    /// The `User-Agent` of requests which do not set one.
+    pub fn user_agent(self, user_agent: &str) -> Self {
+        Client {
+            user_agent: user_agent.to_string(),
+            ..self
+        }
+    }
+
+    /// How long connecting to a host may take.
+    pub fn connect_timeout(self, timeout: Duration) -> Self {
+        Client {
+            connect_timeout: timeout,
+            ..self
+        }
+    }
+
+    /// How long a single write or read may wait.
+    pub fn read_timeout(self, timeout: Duration) -> Self {
+        Client {
+            read_timeout: timeout,
+            ..self
+        }
+    }
+
+    /// How long a request may take altogether, with its redirects and retries.
+    pub fn timeout(self, timeout: Duration) -> Self {
+        Client {
+            timeout: Some(timeout),
+            ..self
+        }
+    }
+
+    /// Redirects followed before failing with `TooManyRedirects`, 0 returns redirects as they are.
+    pub fn max_redirects(self, max: usize) -> Self {
+        Client {
+            max_redirects: max,
+            ..self
+        }
+    }
+
+    /// Send idempotent requests again as `retry` allows, none are retried by default.
+    pub fn retry(self, retry: Retry) -> Self {
+        Client {
+            retry: Some(retry),
+            ..self
+        }
+    }
+
+    /// Keep the cookies of responses in `jar` and send them with later requests, none are kept by default.
+    pub fn cookie_jar(self, jar: Arc<CookieJar>) -> Self {
+        Client {
+            jar: Some(jar),
+            ..self
+        }
+    }
+
+    /// Answer Digest challenges of the origin a request is sent to with `user` and `password`.
+    /// Requests that set `Authorization` themselves are left as they are.
+    pub fn digest_auth(self, user: &str, password: &str) -> Self {
+        Client {
+            digest: Some(Arc::new(DigestLogin::new(user, password))),
+            ..self
+        }
+    }
+
+    /// Idle connections kept per host, 0 closes every connection after its response.
+    pub fn pool_max_idle_per_host(self, max: usize) -> Self {
+        Client {
+            pool: Arc::new(Pool::new(max, self.pool.idle_timeout())),
+            ..self
+        }
+    }
+
+    /// How long an idle connection is kept for reuse.
+    pub fn pool_idle_timeout(self, timeout: Duration) -> Self {
+        Client {
+            pool: Arc::new(Pool::new(self.pool.max_idle_per_host(), timeout)),
+            ..self
+        }
+    }
+
+    pub fn get(&self, uri: &str) -> Result<Response<Vec<u8>>, Error> {
+        let req = Request::builder()
+            .method(Method::GET)
+            .uri(parse_uri(uri)?)
+            .parts();
+        self.send(Request::from_parts(req, Vec::new()))
+    }
+
+    pub fn post(&self, uri: &str, content_type: &str, body: &[u8]) -> Result<Response<Vec<u8>>, Error> {
+        let req = Request::builder()
+            .method(Method::POST)
+            .uri(parse_uri(uri)?)
+            .push_header("Content-Type", content_type)
+            .parts();
+        self.send(Request::from_parts(req, body.to_vec()))
+    }
+
+    /// Send a request to the host and port of its absolute uri and read the whole response.
+    /// Redirects are followed, `Authorization` is only sent again to the same scheme, host and port.
+    /// A Digest challenge of the origin of the request is answered when `digest_auth` was given.
+    pub fn send<T: AsRef<[u8]>>(&self, req: Request<T>) -> Result<Response<Vec<u8>>, Error> {
+        let deadline = self.timeout.map(|timeout| Instant::now() + timeout);
+        let mut out = Outgoing::new(&req)?;
+        let first = origin(&out.uri);
+        let mut hops = 0;
+        let mut answered = false;
+        loop {
+            let res = self.send_retrying(&out, deadline)?;
+            let status = res.status().as_u16();
+            if let Some(login) = &self.digest {
+                // a challenge is answered once, and only by the origin the request was meant for
+                if status == 401 && !answered && out.get("Authorization").is_none() && origin(&out.uri) == first
+                    && login.challenged(&out.uri, res.header().get_all("WWW-Authenticate")) {
+                    answered = true;
+                    continue;
+                }
+            }
+            let location = match res.header().get("Location") {
+                Some(location) if is_redirect(status) && self.max_redirects > 0 => location,
+                _ => return Ok(res),
+            };
+            if hops == self.max_redirects {
+                return Err(Error::from(TooManyRedirects::new(hops)));
+            }
+            hops += 1;
+            let uri = out.uri.join(location.trim()).ok_or_else(invalid_uri)?;
+            debug!("{} redirects to {}", out.uri.as_str(), uri.as_str());
+            out = out.redirect(status, uri);
+        }
+    }
+
+    // send a request, again after a failure as the retry policy allows
+    fn send_retrying(&self, out: &Outgoing, deadline: Option<Instant>) -> Result<Response<Vec<u8>>, Error> {
+        let mut retries = 0;
+        loop {
+            let result = self.send_once(out, deadline);
+            let retry = match &self.retry {
+                Some(retry) if retry.allows(retries) && is_idempotent(&out.method) => retry,
+                _ => return result,
+            };
+            let delay = match &result {
+                Ok(res) => retry.delay_response(res, retries),
+                Err(e) => retry.delay_error(e, retries),
+            };
+            let delay = match delay {
+                // a retry is not started when it could not finish in time
+                Some(delay) if deadline.is_none_or(|deadline| Instant::now() + delay < deadline) => delay,
+                _ => return result,
+            };
+            debug!("retrying {} {} in {:?}", out.method.as_str(), out.uri.as_str(), delay);
+            thread::sleep(delay);
+            retries += 1;
+        }
+    }
+
+    fn send_once(&self, out: &Outgoing, deadline: Option<Instant>) -> Result<Response<Vec<u8>>, Error> {
+        let uri = &out.uri;
+        match uri.scheme().as_deref() {
+            Some("http") => {},
+            Some(scheme) => return Err(Error::from(io::Error::new(io::ErrorKind::Unsupported,
+                format!("the {} scheme is not supported", scheme)))),
+            None => return Err(invalid_uri()),
+        }
+        let (host, port) = match (uri.host(), uri.port()) {
+            (Some(host), Some(port)) => (host, port),
+            _ => return Err(invalid_uri()),
+        };
+        let address = format!("{}:{}", host, port);
+        debug!("{} {}", out.method.as_str(), uri.as_str());
+        let mut buf = self.head(out, &address);
+        buf.extend_from_slice(&out.body);
+        let head_request = out.method == Method::HEAD;
+
+        let (mut stream, mut reused) = match self.pool.take(&address) {
+            Some(stream) => (stream, true),
+            None => (self.connect(&address, deadline)?, false),
+        };
+        loop {
+            let mut conn = Stream::Tcp(stream);
+            match self.exchange(&mut conn, &buf, head_request, deadline) {
+                Ok((res, reusable)) => {
+                    if let (true, Stream::Tcp(stream)) = (reusable, conn) {
+                        self.pool.put(&address, stream);
+                    }
+                    if let Some(jar) = &self.jar {
+                        res.header().get_all("Set-Cookie").for_each(|cookie| jar.store(uri, cookie));
+                    }
+                    return Ok(res);
+                },
+                // a request which may be repeated gets one more try on a new connection
+                Err(Failure::Unanswered(e)) if reused && is_idempotent(&out.method) => {
+                    debug!("reused connection to {} failed, retrying: {}", address, e);
+                    stream = self.connect(&address, deadline)?;
+                    reused = false;
+                },
+                Err(Failure::Unanswered(e)) | Err(Failure::Failed(e)) => return Err(e),
+            }
+        }
+    }
+
+    // send a request and read its response, which tells whether the connection can be reused
+    fn exchange(&self, stream: &mut Stream, buf: &[u8], head_request: bool, deadline: Option<Instant>) -> Result<(Response<Vec<u8>>, bool), Failure> {
+        let write_timeout = remaining(self.read_timeout, deadline).map_err(|e| Failure::Failed(Error::from(e)))?;
+        stream.set_write_timeout(Some(write_timeout)).map_err(|e| Failure::Failed(Error::from(e)))?;
+        stream.write_all(buf).map_err(|e| Failure::Unanswered(Error::from(e)))?;
+        let mut reader = Reader::with_deadline(deadline);
+        let res = loop {
+            let head = reader.read_head(stream, self.read_timeout, self.read_timeout, &RESPONSE_LIMITS)
+                .map_err(|e| match e {
+                    ReadError::Closed => Failure::Unanswered(read_error(e)),
+                    ReadError::Io(ref io) if io.kind() == io::ErrorKind::ConnectionReset => Failure::Unanswered(read_error(e)),
+                    e => Failure::Failed(read_error(e)),
+                })?;
+            let res = Parser::new().parse_response(&head).map_err(|e| Failure::Failed(Error::from(e)))?;
+            // interim responses such as `100 Continue` are skipped
+            if !(100..200).contains(&res.status().as_u16()) {
+                break res;
+            }
+        };
+        let framing = framing(head_request, &res);
+        let body = match framing {
+            Framing::Empty => Ok(Vec::new()),
+            Framing::Length(len) => reader.read_body(stream, len, self.read_timeout),
+            Framing::Chunked => reader.read_chunked(stream, RESPONSE_LIMITS.max_request_line, self.read_timeout),
+            Framing::ToEnd => {
+                let mut body = Vec::new();
+                reader.copy(stream, &mut body, None, self.read_timeout).map(|_| body)
+            },
+        };
+        let body = body.map_err(|e| Failure::Failed(read_error(e)))?;
+        let reusable = framing != Framing::ToEnd && reader.is_empty() && keeps_alive(&res);
+        let (parts, _) = res.into_parts();
+        Ok((Response::from_parts(parts, body), reusable))
+    }
+
+    fn connect(&self, address: &str, deadline: Option<Instant>) -> io::Result<TcpStream> {
+        let mut last = io::Error::new(io::ErrorKind::AddrNotAvailable,
+            format!("{} does not resolve to any address", address));
+        for addr in address.to_socket_addrs()? {
+            match TcpStream::connect_timeout(&addr, remaining(self.connect_timeout, deadline)?) {
+                Ok(stream) => return Ok(stream),
+                Err(e) => last = e,
+            }
+        }
+        Err(last)
+    }
+
+    // the request line and headers, with `Host`, `User-Agent` and `Content-Length` added when missing
+    fn head(&self, out: &Outgoing, address: &str) -> Vec<u8> {
+        let uri = &out.uri;
+        let mut head = format!("{} {} HTTP/1.1\r\n", out.method.as_str(), uri.origin_form());
+        if out.get("Host").is_none() {
+            // the default port is left out
+            let host = match uri.port() {
+                Some(80) => uri.host().unwrap_or(address).to_string(),
+                _ => address.to_string(),
+            };
+            head.push_str(&format!("Host: {}\r\n", host));
+        }
+        if out.get("User-Agent").is_none() {
+            head.push_str(&format!("User-Agent: {}\r\n", self.user_agent));
+        }
+        let len = out.body.len();
+        let with_body = len > 0 || matches!(out.method, Method::POST | Method::PUT | Method::PATCH);
+        if with_body && out.get("Content-Length").is_none() {
+            head.push_str(&format!("Content-Length: {}\r\n", len));
+        }
+        let mut cookie = out.get("Cookie").map(|cookie| cookie.to_string());
+        if let Some(stored) = self.jar.as_ref().and_then(|jar| jar.cookies(uri)) {
+            // cookies of the request come before stored ones
+            cookie = Some(match cookie {
+                Some(cookie) => format!("{}; {}", cookie, stored),
+                None => stored,
+            });
+        }
+        if let Some(cookie) = cookie {
+            head.push_str(&format!("Cookie: {}\r\n", cookie));
+        }
+        if out.get("Authorization").is_none() {
+            if let Some(authorization) = self.digest.as_ref().and_then(|login| login.authorization(&out.method, uri)) {
+                head.push_str(&format!("Authorization: {}\r\n", authorization));
+            }
+        }
+        for (name, value) in &out.headers {
+            if !name.eq_ignore_ascii_case("Connection") && !name.eq_ignore_ascii_case("Cookie") {
+                head.push_str(&format!("{}: {}\r\n", name, value));
+            }
+        }
+        if self.pool.max_idle_per_host() == 0 {
+            head.push_str("Connection: close\r\n");
+        }
+        head.push_str("\r\n");
+        head.into_bytes()
+    }
+}
+
+impl Default for Client {
+    fn default() -> Self {
+        Client {
+            user_agent: format!("rushttp/{}", env!("CARGO_PKG_VERSION")),
+            connect_timeout: Duration::from_secs(10),
+            read_timeout: Duration::from_secs(30),
+            timeout: None,
+            max_redirects: 10,
+            retry: None,
+            jar: None,
+            digest: None,
+            pool: Arc::new(Pool::new(8, Duration::from_secs(30))),
+        }
+    }
+}
+
+impl Outgoing {
+    fn new<T: AsRef<[u8]>>(req: &Request<T>) -> Result<Self, Error> {
+        Ok(Outgoing {
+            method: *req.method(),
+            uri: parse_uri(req.uri().as_str())?,
+            headers: req.header().iter().map(|(name, value)| (name.to_string(), value.to_string())).collect(),
+            body: req.body().as_ref().to_vec(),
+        })
+    }
+
+    fn get(&self, name: &str) -> Option<&str> {
+        self.headers.iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    fn remove(&mut self, name: &str) {
+        self.headers.retain(|(n, _)| !n.eq_ignore_ascii_case(name));
+    }
+
+    // the request to send to `uri` after a redirect with `status`
+    fn redirect(mut self, status: u16, uri: Uri) -> Self {
+        // 303 asks for a GET, 301 and 302 have always turned a POST into one, 307 and 308 keep the request
+        let to_get = match status {
+            303 => self.method != Method::HEAD,
+            301 | 302 => self.method == Method::POST,
+            _ => false,
+        };
+        if to_get {
+            self.method = Method::GET;
+            self.body.clear();
+            for name in ["Content-Length", "Content-Type", "Transfer-Encoding"] {
+                self.remove(name);
+            }
+        }
+        if origin(&self.uri) != origin(&uri) {
+            self.remove("Authorization");
+            self.remove("Host");
+        }
+        self.uri = uri;
+        self
+    }
+}
+
+fn is_redirect(status: u16) -> bool {
+    matches!(status, 301 | 302 | 303 | 307 | 308)
+}
+
+fn origin(uri: &Uri) -> (Option<String>, Option<String>, Option<usize>) {
+    (uri.scheme(), uri.host().map(|host| host.to_ascii_lowercase()), uri.port())
+}
+
+// the time left before `deadline`, at most `timeout`
+fn remaining(timeout: Duration, deadline: Option<Instant>) -> io::Result<Duration> {
+    let left = match deadline {
+        Some(deadline) => deadline.saturating_duration_since(Instant::now()),
+        None => return Ok(timeout),
+    };
+    if left.is_zero() {
+        return Err(io::Error::new(io::ErrorKind::TimedOut, "the request timed out"));
+    }
+    Ok(left.min(timeout))
+}
+
+// HTTP/1.1 keeps the connection unless the server closes it, HTTP/1.0 only when it says so
+fn keeps_alive<T>(res: &Response<T>) -> bool {
+    let connection = res.header().get("Connection").unwrap_or_default();
+    let has = |token: &str| connection.split(',').any(|t| t.trim().eq_ignore_ascii_case(token));
+    match res.version() {
+        Version::HTTP11 => !has("close"),
+        Version::HTTP10 => has("keep-alive"),
+        _ => false,
+    }
+}
+
+// methods which may be sent again without changing the outcome
+fn is_idempotent(method: &Method) -> bool {
+    !matches!(method, Method::POST | Method::PATCH | Method::CONNECT)
+}
+
+fn read_error(err: ReadError) -> Error {
+    let err = match err {
+        ReadError::Io(e) => e,
+        ReadError::Idle | ReadError::Timeout => io::Error::new(io::ErrorKind::TimedOut, "timed out reading the response"),
+        ReadError::Closed => io::ErrorKind::UnexpectedEof.into(),
+        ReadError::Limit(_) => io::Error::new(io::ErrorKind::InvalidData, "response head is too large"),
+        ReadError::Malformed => io::Error::new(io::ErrorKind::InvalidData, "invalid chunked body"),
+    };
+    Error::from(err)
+}
+
+fn invalid_uri() -> Error {
+    Error::from(crate::http::error::Error::from(InvalidUri::new()))
+}
+
+fn parse_uri(uri: &str) -> Result<Uri, Error> {
+    Uri::new(uri).ok_or_else(invalid_uri)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, Read, Write};
+    use std::net::TcpListener;
+    use std::sync::{mpsc, Arc};
+    use std::thread;
+    use std::time::{Duration, Instant};
+    use crate::http::method::Method;
+    use crate::http::request::Request;
+    use super::{Client, CookieJar, ErrorKind, Retry};
+
+    // answer one connection with `res` and hand back the request it read
+    fn serve(res: &'static [u8]) -> (String, thread::JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let handle = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut buf = [0u8; 4096];
+            let n = stream.read(&mut buf).unwrap();
+            stream.write_all(res).unwrap();
+            String::from_utf8_lossy(&buf[..n]).to_string()
+        });
+        (address, handle)
+    }
+
+    #[test]
+    fn test_get() {
+        let (address, handle) = serve(b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhello");
+        let res = Client::new().user_agent("test").get(&format!("http://{}/a?b=c", address)).unwrap();
+        assert_eq!(res.status().as_u16(), 200);
+        assert_eq!(res.body(), b"hello");
+        let req = handle.join().unwrap();
+        assert_eq!(req, format!("GET /a?b=c HTTP/1.1\r\nHost: {}\r\nUser-Agent: test\r\n\r\n", address));
+    }
+    #[test]
+    fn test_post_chunked() {
+        let (address, handle) = serve(b"HTTP/1.1 100 Continue\r\n\r\nHTTP/1.1 201 Created\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nabc\r\n2\r\nde\r\n0\r\n\r\n");
+        let res = Client::new().post(&format!("http://{}/items", address), "text/plain", b"item").unwrap();
+        assert_eq!(res.status().as_u16(), 201);
+        assert_eq!(res.body(), b"abcde");
+        let req = handle.join().unwrap();
+        assert!(req.starts_with("POST /items HTTP/1.1\r\n"));
+        assert!(req.contains("Content-Length: 4\r\n"));
+        assert!(req.contains("Content-Type: text/plain\r\n"));
+        assert!(req.ends_with("\r\n\r\nitem"));
+    }
+    // answer each request on each connection with "<connection>-<request>",
+    // a connection is closed at the request after `per_conn` answered ones
+    fn serve_counting(per_conn: usize) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        thread::spawn(move || {
+            for (conn, stream) in listener.incoming().enumerate() {
+                let mut stream = stream.unwrap();
+                thread::spawn(move || {
+                    for req in 0..=per_conn {
+                        let mut buf = [0u8; 4096];
+                        if stream.read(&mut buf).unwrap_or(0) == 0 || req == per_conn {
+                            break;
+                        }
+                        let body = format!("{}-{}", conn, req);
+                        let _ = write!(stream, "HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n{}", body.len(), body);
+                    }
+                });
+            }
+        });
+        address
+    }
+
+    #[test]
+    fn test_reuse() {
+        let address = serve_counting(10);
+        let client = Client::new();
+        let bodies: Vec<Vec<u8>> = (0..3)
+            .map(|_| client.get(&format!("http://{}/", address)).unwrap().body().clone())
+            .collect();
+        assert_eq!(bodies, [b"0-0".to_vec(), b"0-1".to_vec(), b"0-2".to_vec()]);
+        // without idle connections every request connects
+        let client = Client::new().pool_max_idle_per_host(0);
+        assert_eq!(client.get(&format!("http://{}/", address)).unwrap().body(), b"1-0");
+        assert_eq!(client.get(&format!("http://{}/", address)).unwrap().body(), b"2-0");
+    }
+    #[test]
+    fn test_retry_stale() {
+        // the server drops the second request on a connection without answering it
+        let address = serve_counting(1);
+        let client = Client::new();
+        assert_eq!(client.get(&format!("http://{}/", address)).unwrap().body(), b"0-0");
+        assert_eq!(client.get(&format!("http://{}/", address)).unwrap().body(), b"1-0");
+    }
+    #[test]
+    fn test_read_to_close() {
+        let (address, _) = serve(b"HTTP/1.0 200 OK\r\n\r\nuntil the end");
+        let res = Client::new().get(&format!("http://{}/", address)).unwrap();
+        assert_eq!(res.body(), b"until the end");
+    }
+    // answer each request on its own connection with `answer(request)` and send the request on the channel
+    fn serve_with<F>(answer: F) -> (String, mpsc::Receiver<String>)
+        where F: Fn(&str) -> String + Send + 'static {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut buf = [0u8; 4096];
+                let n = stream.read(&mut buf).unwrap_or(0);
+                let req = String::from_utf8_lossy(&buf[..n]).to_string();
+                let _ = stream.write_all(answer(&req).as_bytes());
+                if tx.send(req).is_err() {
+                    break;
+                }
+            }
+        });
+        (address, rx)
+    }
+
+    fn redirect(status: &str, location: &str) -> String {
+        format!("HTTP/1.1 {}\r\nLocation: {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n", status, location)
+    }
+
+    fn ok(body: &str) -> String {
+        format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", body.len(), body)
+    }
+
+    fn post(uri: &str, body: &str) -> Request<Vec<u8>> {
+        let parts = Request::builder()
+            .method(Method::POST)
+            .uri_from_str(uri)
+            .push_header("Content-Type", "text/plain")
+            .push_header("Authorization", "Basic dXNlcjpwYXNz")
+            .parts();
+        Request::from_parts(parts, body.as_bytes().to_vec())
+    }
+
+    #[test]
+    fn test_redirect_method() {
+        let (address, rx) = serve_with(|req| {
+            match req.split(' ').nth(1).unwrap_or_default() {
+                "/found" => redirect("302 Found", "/to?x=1"),
+                "/temporary" => redirect("307 Temporary Redirect", "to"),
+                _ => ok("done"),
+            }
+        });
+        let client = Client::new();
+        let res = client.send(post(&format!("http://{}/found", address), "data")).unwrap();
+        assert_eq!(res.body(), b"done");
+        rx.recv().unwrap();
+        // the POST becomes a GET without a body
+        let req = rx.recv().unwrap();
+        assert!(req.starts_with("GET /to?x=1 HTTP/1.1\r\n"));
+        assert!(!req.contains("Content-Type") && !req.contains("Content-Length") && !req.ends_with("data"));
+        assert!(req.contains("Authorization: Basic dXNlcjpwYXNz\r\n"));
+
+        client.send(post(&format!("http://{}/temporary", address), "data")).unwrap();
+        rx.recv().unwrap();
+        let req = rx.recv().unwrap();
+        assert!(req.starts_with("POST /to HTTP/1.1\r\n"));
+        assert!(req.contains("Content-Type: text/plain\r\n"));
+        assert!(req.ends_with("\r\n\r\ndata"));
+    }
+    #[test]
+    fn test_redirect_cross_origin() {
+        let (other, other_rx) = serve_with(|_| ok("other"));
+        let location = format!("http://{}/moved", other);
+        let (address, rx) = serve_with(move |_| redirect("308 Permanent Redirect", &location));
+        let res = Client::new().send(post(&format!("http://{}/", address), "data")).unwrap();
+        assert_eq!(res.body(), b"other");
+        assert!(rx.recv().unwrap().contains("Authorization"));
+        let req = other_rx.recv().unwrap();
+        assert!(req.starts_with("POST /moved HTTP/1.1\r\n"));
+        assert!(req.contains(&format!("Host: {}\r\n", other)));
+        assert!(!req.contains("Authorization"));
+    }
+    #[test]
+    fn test_too_many_redirects() {
+        let (address, _rx) = serve_with(|_| redirect("302 Found", "/again"));
+        let err = Client::new().max_redirects(3).get(&format!("http://{}/", address)).err().unwrap();
+        match err.kind() {
+            ErrorKind::Redirect(e) => assert_eq!(e.hops(), 3),
+            kind => panic!("unexpected error {:?}", kind),
+        }
+        // without following, the redirect is the response
+        let res = Client::new().max_redirects(0).get(&format!("http://{}/", address)).unwrap();
+        assert_eq!(res.status().as_u16(), 302);
+    }
+    #[test]
+    fn test_retry() {
+        let (address, rx) = serve_with(|req| {
+            match req.split(' ').nth(1).unwrap_or_default() {
+                "/2" => ok("finally"),
+                _ => "HTTP/1.1 503 Service Unavailable\r\nRetry-After: 0\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string(),
+            }
+        });
+        let client = Client::new().retry(Retry::new().max_retries(2).backoff(Duration::from_millis(1), Duration::from_millis(10)));
+        let res = client.get(&format!("http://{}/", address)).unwrap();
+        assert_eq!(res.status().as_u16(), 503);
+        for _ in 0..3 {
+            rx.recv().unwrap();
+        }
+        assert!(rx.recv_timeout(Duration::from_millis(100)).is_err());
+        // a POST is not sent again
+        let res = client.send(post(&format!("http://{}/", address), "data")).unwrap();
+        assert_eq!(res.status().as_u16(), 503);
+        rx.recv().unwrap();
+        assert!(rx.recv_timeout(Duration::from_millis(100)).is_err());
+        assert_eq!(client.get(&format!("http://{}/2", address)).unwrap().body(), b"finally");
+    }
+    #[test]
+    fn test_timeout() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        thread::spawn(move || {
+            // the request is read but never answered
+            let (_stream, _) = listener.accept().unwrap();
+            thread::sleep(Duration::from_secs(5));
+        });
+        let start = Instant::now();
+        let err = Client::new().timeout(Duration::from_millis(200)).get(&format!("http://{}/", address)).err().unwrap();
+        assert!(start.elapsed() < Duration::from_secs(2));
+        match err.kind() {
+            ErrorKind::Io(e) => assert_eq!(e.kind(), io::ErrorKind::TimedOut),
+            kind => panic!("unexpected error {:?}", kind),
+        }
+    }
+    #[test]
+    fn test_cookie_jar() {
+        let (address, rx) = serve_with(|req| {
+            match req.split(' ').nth(1).unwrap_or_default() {
+                "/login" => "HTTP/1.1 303 See Other\r\nLocation: /home\r\nSet-Cookie: session=abc; Path=/; HttpOnly\r\nSet-Cookie: theme=dark\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string(),
+                _ => ok("home"),
+            }
+        });
+        let jar = Arc::new(CookieJar::new());
+        let client = Client::new().cookie_jar(jar.clone());
+        client.send(post(&format!("http://{}/login", address), "user")).unwrap();
+        assert!(!rx.recv().unwrap().contains("Cookie"));
+        // both cookies are sent on the redirect
+        assert!(rx.recv().unwrap().contains("Cookie: session=abc; theme=dark\r\n"));
+        let parts = Request::builder()
+            .method(Method::GET)
+            .uri_from_str(&format!("http://{}/other/page", address))
+            .push_header("Cookie", "extra=1")
+            .parts();
+        client.send(Request::from_parts(parts, Vec::new())).unwrap();
+        assert!(rx.recv().unwrap().contains("Cookie: extra=1; session=abc; theme=dark\r\n"));
+    }
+    #[test]
+    fn test_digest_auth() {
+        let (address, rx) = serve_with(|req| {
+            if req.contains("Authorization: Digest ") {
+                ok("welcome")
+            } else {
+                let challenge = "Digest realm=\"devices\", qop=\"auth\", algorithm=MD5, nonce=\"abc\", opaque=\"xyz\"";
+                format!("HTTP/1.1 401 Unauthorized\r\nWWW-Authenticate: {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n", challenge)
+            }
+        });
+        let uri = format!("http://{}/status", address);
+        assert_eq!(Client::new().get(&uri).unwrap().status().as_u16(), 401);
+        rx.recv().unwrap();
+        let client = Client::new().digest_auth("admin", "secret");
+        assert_eq!(client.get(&uri).unwrap().body(), b"welcome");
+        assert!(!rx.recv().unwrap().contains("Authorization"));
+        let req = rx.recv().unwrap();
+        assert!(req.contains("Digest username=\"admin\", realm=\"devices\", uri=\"/status\", algorithm=MD5, nonce=\"abc\", qop=auth, nc=00000001"));
+        assert!(req.contains("opaque=\"xyz\""));
+        // the nonce is used again without waiting for a challenge
+        assert_eq!(client.get(&uri).unwrap().body(), b"welcome");
+        assert!(rx.recv().unwrap().contains("nc=00000002"));
+        // a challenge to a request with its own credentials is returned
+        let parts = Request::builder()
+            .method(Method::GET)
+            .uri_from_str(&format!("http://{}/", address))
+            .push_header("Authorization", "Basic YTpi")
+            .parts();
+        assert_eq!(client.send(Request::from_parts(parts, Vec::new())).unwrap().status().as_u16(), 401);
+    }
+}
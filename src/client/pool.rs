@@ -0,0 +1,128 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, Read};
+use std::net::TcpStream;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Idle keep-alive connections of a client, by `host:port`.
+pub(crate) struct Pool {
+    max_idle_per_host: usize,
+    idle_timeout: Duration,
+    idle: Mutex<HashMap<String, Vec<Idle>>>,
+}
+
+struct Idle {
+    stream: TcpStream,
+    since: Instant,
+}
+
+impl Pool {
+    pub fn new(max_idle_per_host: usize, idle_timeout: Duration) -> Self {
+        Pool {
+            max_idle_per_host,
+            idle_timeout,
+            idle: Mutex::default(),
+        }
+    }
+
+    pub fn max_idle_per_host(&self) -> usize {
+        self.max_idle_per_host
+    }
+
+    pub fn idle_timeout(&self) -> Duration {
+        self.idle_timeout
+    }
+
+    /// The most recently used connection to `host` which still looks open.
+    pub fn take(&self, host: &str) -> Option<TcpStream> {
+        let mut idle = self.lock();
+        let conns = idle.get_mut(host)?;
+        let mut found = None;
+        while let Some(conn) = conns.pop() {
+            if conn.since.elapsed() < self.idle_timeout && is_open(&conn.stream) {
+                found = Some(conn.stream);
+                break;
+            }
+        }
+        if conns.is_empty() {
+            idle.remove(host);
+        }
+        found
+    }
+
+    /// Keep a connection whose response was read completely, unless `host` has enough idle ones.
+    pub fn put(&self, host: &str, stream: TcpStream) {
+        if self.max_idle_per_host == 0 {
+            return;
+        }
+        let mut idle = self.lock();
+        let conns = idle.entry(host.to_string()).or_default();
+        conns.retain(|conn| conn.since.elapsed() < self.idle_timeout);
+        if conns.len() < self.max_idle_per_host {
+            conns.push(Idle {
+                stream,
+                since: Instant::now(),
+            });
+        }
+    }
+
+    fn idle_count(&self) -> usize {
+        self.lock().values().map(|conns| conns.len()).sum()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<String, Vec<Idle>>> {
+        match self.idle.lock() {
+            Ok(idle) => idle,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+}
+
+impl fmt::Debug for Pool {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Pool")
+            .field("max_idle_per_host", &self.max_idle_per_host)
+            .field("idle_timeout", &self.idle_timeout)
+            .field("idle", &self.idle_count())
+            .finish()
+    }
+}
+
+// an idle connection which the server closed reads EOF, one with unexpected bytes is out of step
+fn is_open(stream: &TcpStream) -> bool {
+    if stream.set_nonblocking(true).is_err() {
+        return false;
+    }
+    let mut buf = [0u8; 1];
+    let open = match (&*stream).read(&mut buf) {
+        Err(e) => e.kind() == io::ErrorKind::WouldBlock,
+        Ok(_) => false,
+    };
+    stream.set_nonblocking(false).is_ok() && open
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{TcpListener, TcpStream};
+    use std::time::Duration;
+    use super::Pool;
+
+    #[test]
+    fn test_take_stale() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let pool = Pool::new(1, Duration::from_secs(60));
+        let open = TcpStream::connect(&address).unwrap();
+        let (_server, _) = listener.accept().unwrap();
+        let closed = TcpStream::connect(&address).unwrap();
+        drop(listener.accept().unwrap());
+        pool.put(&address, closed);
+        // the host has an idle connection already
+        pool.put(&address, open.try_clone().unwrap());
+        assert!(pool.take(&address).is_none());
+        pool.put(&address, open);
+        assert!(pool.take(&address).is_some());
+        assert!(pool.take(&address).is_none());
+    }
+}
@@ -0,0 +1,115 @@
+use std::io;
+use std::time::Duration;
+use crate::client::error::{Error, ErrorKind};
+use crate::http::response::Response;
+
+/// When a client sends a request again.
+/// Only idempotent requests are retried, after a connection failure or one of the retried statuses,
+/// each wait twice as long as the one before up to the maximum backoff.
+#[derive(Debug, Clone)]
+pub struct Retry {
+    max_retries: usize,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+    statuses: Vec<u16>,
+}
+
+impl Retry {
+    pub fn new() -> Self {
+        Retry::default()
+    }
+
+    /// How many times a request is sent again, 0 never retries.
+    pub fn max_retries(self, max_retries: usize) -> Self {
+        Retry {
+            max_retries,
+            ..self
+        }
+    }
+
+    /// The wait before the first retry and the longest wait.
+    pub fn backoff(self, initial: Duration, max: Duration) -> Self {
+        Retry {
+            initial_backoff: initial,
+            max_backoff: max.max(initial),
+            ..self
+        }
+    }
+
+    /// Response statuses which are retried.
+    pub fn statuses(self, statuses: &[u16]) -> Self {
+        Retry {
+            statuses: statuses.to_vec(),
+            ..self
+        }
+    }
+
+    pub(crate) fn allows(&self, retries: usize) -> bool {
+        retries < self.max_retries
+    }
+
+    // the wait before retry number `retries`, counted from 0
+    pub(crate) fn delay(&self, retries: usize) -> Duration {
+        let factor = 1u32.checked_shl(retries as u32).unwrap_or(u32::MAX);
+        self.initial_backoff.saturating_mul(factor).min(self.max_backoff)
+    }
+
+    // the wait before retrying a response, `Retry-After` seconds are honored up to the maximum backoff
+    pub(crate) fn delay_response<T>(&self, res: &Response<T>, retries: usize) -> Option<Duration> {
+        if !self.statuses.contains(&res.status().as_u16()) {
+            return None;
+        }
+        let after = res.header().get("Retry-After")
+            .and_then(|secs| secs.trim().parse::<u64>().ok())
+            .map(|secs| Duration::from_secs(secs).min(self.max_backoff));
+        Some(after.unwrap_or_else(|| self.delay(retries)))
+    }
+
+    // the wait before retrying a request which failed, if the failure may pass
+    pub(crate) fn delay_error(&self, err: &Error, retries: usize) -> Option<Duration> {
+        use io::ErrorKind::*;
+
+        match err.kind() {
+            ErrorKind::Io(e) if matches!(e.kind(), ConnectionRefused | ConnectionReset | ConnectionAborted
+                | NotConnected | BrokenPipe | TimedOut | UnexpectedEof) => Some(self.delay(retries)),
+            _ => None,
+        }
+    }
+}
+
+impl Default for Retry {
+    fn default() -> Self {
+        Retry {
+            max_retries: 3,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(10),
+            statuses: vec![502, 503, 504],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::time::Duration;
+    use crate::client::error::Error;
+    use super::Retry;
+
+    #[test]
+    fn test_delay() {
+        let retry = Retry::new().backoff(Duration::from_millis(100), Duration::from_secs(1));
+        let delays: Vec<Duration> = (0..6).map(|n| retry.delay(n)).collect();
+        assert_eq!(delays, [100, 200, 400, 800, 1000, 1000].iter().map(|&ms| Duration::from_millis(ms)).collect::<Vec<_>>());
+        assert_eq!(retry.delay(100), Duration::from_secs(1));
+        assert!(retry.allows(2));
+        assert!(!retry.allows(3));
+    }
+    #[test]
+    fn test_delay_error() {
+        let retry = Retry::new();
+        let refused = Error::from(io::Error::from(io::ErrorKind::ConnectionRefused));
+        assert_eq!(retry.delay_error(&refused, 1), Some(Duration::from_millis(200)));
+        let invalid = Error::from(io::Error::from(io::ErrorKind::InvalidData));
+        assert_eq!(retry.delay_error(&invalid, 0), None);
+    }
+}
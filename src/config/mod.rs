@@ -0,0 +1,936 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::net::ToSocketAddrs;
+use std::path::{Path, PathBuf};
+use std::thread;
+use serde::Deserialize;
+use crate::config::error::ConfigError;
+
+pub mod error;
+
+pub const DEFAULT_PATH: &str = "/etc/rushttp/rushttp.toml";
+
+const LOG_LEVELS: [&str; 6] = ["off", "error", "warn", "info", "debug", "trace"];
+const LOG_FORMATS: [&str; 3] = ["common", "combined", "json"];
+const BALANCES: [&str; 4] = ["round_robin", "least_connections", "weighted", "hash"];
+const MAX_WEIGHT: u32 = 100;
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    #[serde(default = "default_root")]
+    pub root: PathBuf,
+    #[serde(default = "default_listeners", rename = "listener")]
+    pub listeners: Vec<Listener>,
+    /// Let IPv6 listeners accept IPv4 clients too.
+    #[serde(default)]
+    pub dual_stack: bool,
+    /// Number of worker threads handling connections.
+    #[serde(default = "default_threads")]
+    pub threads: usize,
+    #[serde(default, rename = "static")]
+    pub mounts: Vec<StaticMount>,
+    #[serde(default, rename = "proxy")]
+    pub proxies: Vec<ProxyRoute>,
+    /// Named groups of backends which proxy routes can use as their `upstream`.
+    #[serde(default, rename = "upstream")]
+    pub upstreams: Vec<Upstream>,
+    /// Name-based virtual hosts. Requests for other hosts are served by the settings above.
+    #[serde(default, rename = "vhost")]
+    pub vhosts: Vec<VirtualHost>,
+    pub tls: Option<Tls>,
+    #[serde(default)]
+    pub limits: Limits,
+    #[serde(default)]
+    pub timeouts: Timeouts,
+    #[serde(default)]
+    pub log: Log,
+    /// Per-client rate limiting, disabled when the section is missing.
+    pub rate_limit: Option<RateLimit>,
+    /// Paths which need HTTP Basic authentication, on every virtual host.
+    #[serde(default, rename = "auth")]
+    pub auth: Vec<Auth>,
+    /// Paths which need HTTP Digest authentication, on every virtual host.
+    #[serde(default)]
+    pub digest_auth: Vec<DigestAuth>,
+    /// Paths which need a JWT as a Bearer token, on every virtual host.
+    #[serde(default)]
+    pub jwt_auth: Vec<JwtAuth>,
+    /// Prometheus metrics, disabled when the section is missing.
+    pub metrics: Option<Metrics>,
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+}
+
+/// A TCP listener on `address`, or a Unix domain socket listener at `path`.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Listener {
+    pub address: Option<String>,
+    pub path: Option<PathBuf>,
+    /// Permission bits of the socket file, e.g. `0o660`.
+    pub mode: Option<u32>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct StaticMount {
+    pub path: String,
+    pub dir: PathBuf,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ProxyRoute {
+    pub path: String,
+    /// A `host:port` or the name of an `[[upstream]]`.
+    pub upstream: String,
+    /// Seconds to wait for the upstream connection, 5 when unset.
+    pub connect_timeout: Option<u64>,
+    /// Seconds to wait for the upstream response and between reads of its body, 60 when unset.
+    pub read_timeout: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Upstream {
+    pub name: String,
+    /// One of `round_robin`, `least_connections`, `weighted` or `hash`.
+    #[serde(default = "default_balance")]
+    pub balance: String,
+    /// Hash by this header instead of the client IP.
+    pub hash_header: Option<String>,
+    #[serde(rename = "server")]
+    pub servers: Vec<UpstreamServer>,
+    /// Idle connections kept open to each server.
+    #[serde(default = "default_keepalive")]
+    pub keepalive: usize,
+    /// Failed requests in a row which eject a server for `fail_timeout` seconds, 0 never ejects.
+    #[serde(default = "default_max_fails")]
+    pub max_fails: u32,
+    #[serde(default = "default_fail_timeout")]
+    pub fail_timeout: u64,
+    /// Active checks, disabled when the section is missing.
+    pub health_check: Option<HealthCheck>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct UpstreamServer {
+    pub address: String,
+    /// From 1 to 100.
+    #[serde(default = "default_weight")]
+    pub weight: u32,
+}
+
+/// A `GET` of `path` on each server every `interval` seconds.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HealthCheck {
+    pub path: String,
+    pub interval: u64,
+    pub timeout: u64,
+    /// Passed checks in a row which bring a server back.
+    pub passes: u32,
+    /// Failed checks in a row which take a server out.
+    pub fails: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct VirtualHost {
+    /// Host names such as `example.com` or `*.example.com`.
+    pub names: Vec<String>,
+    pub root: PathBuf,
+    #[serde(default, rename = "static")]
+    pub mounts: Vec<StaticMount>,
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Tls {
+    pub cert: PathBuf,
+    pub key: PathBuf,
+}
+
+/// Request size limits, in bytes unless noted otherwise.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Limits {
+    pub max_request_line: usize,
+    /// Maximum number of header fields.
+    pub max_headers: usize,
+    pub max_header_bytes: usize,
+    pub max_body: usize,
+    /// Connections open at the same time, unlimited when unset.
+    pub max_connections: Option<usize>,
+    pub max_connections_per_ip: Option<usize>,
+    /// Overrides for request paths under a prefix, such as upload endpoints.
+    #[serde(rename = "route")]
+    pub routes: Vec<RouteLimits>,
+}
+
+/// Limits of a route, unset fields keep the server value.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RouteLimits {
+    pub path: String,
+    pub max_request_line: Option<usize>,
+    pub max_headers: Option<usize>,
+    pub max_header_bytes: Option<usize>,
+    pub max_body: Option<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Metrics {
+    #[serde(default = "default_metrics_path")]
+    pub path: String,
+    /// A separate admin `host:port` to serve the metrics on instead of the listeners.
+    pub address: Option<String>,
+}
+
+/// A token bucket per client: `burst` requests at once, refilled at `rate` per second.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RateLimit {
+    pub rate: f64,
+    pub burst: u32,
+    /// Count requests by this header, such as `X-Api-Key`, instead of the client IP.
+    pub header: Option<String>,
+}
+
+/// Basic authentication of paths under `path` against the users of an htpasswd file.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Auth {
+    pub path: String,
+    pub realm: String,
+    pub htpasswd: PathBuf,
+}
+
+/// Digest authentication of paths under `path` against the users of an htdigest file,
+/// which only hold MD5 hashes.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DigestAuth {
+    pub path: String,
+    pub realm: String,
+    pub htdigest: PathBuf,
+}
+
+/// Bearer token authentication of paths under `path` with JWTs signed by one of the keys.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct JwtAuth {
+    pub path: String,
+    pub realm: String,
+    /// HS256 shared secret.
+    pub secret: Option<String>,
+    /// RS256 or ES256 public keys in PEM files.
+    #[serde(default)]
+    pub public_keys: Vec<PathBuf>,
+    /// A JWK set file.
+    pub jwks: Option<PathBuf>,
+    pub issuer: Option<String>,
+    pub audience: Option<String>,
+    /// The clock skew allowed for `exp` and `nbf`, in seconds.
+    #[serde(default = "default_leeway")]
+    pub leeway: u64,
+}
+
+/// Connection timeouts, in seconds.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Timeouts {
+    pub header_read: u64,
+    pub body_read: u64,
+    pub write: u64,
+    pub keep_alive: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Log {
+    /// A level such as `info`, optionally followed by per-module levels:
+    /// `info,rushttp::server=debug`.
+    pub level: String,
+    /// `stdout` or a file path. Access logging is disabled when unset.
+    pub access_log: Option<String>,
+    pub format: String,
+}
+
+impl Config {
+    pub fn load(path: &Path) -> Result<Config, ConfigError> {
+        let config = Config::read(path)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Read a config file without validating it, so that overrides can be applied first.
+    pub fn read(path: &Path) -> Result<Config, ConfigError> {
+        let src = fs::read_to_string(path)?;
+        Ok(toml::from_str(&src)?)
+    }
+
+    pub fn parse(src: &str) -> Result<Config, ConfigError> {
+        let config: Config = toml::from_str(src)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        validate_dir("root", &self.root)?;
+        if self.threads == 0 {
+            return Err(ConfigError::Invalid("threads: must be greater than 0".to_string()));
+        }
+        if self.listeners.is_empty() {
+            return Err(ConfigError::Invalid("at least one [[listener]] is required".to_string()));
+        }
+        for (i, listener) in self.listeners.iter().enumerate() {
+            listener.validate(&format!("listener[{}]", i))?;
+        }
+        for (i, mount) in self.mounts.iter().enumerate() {
+            validate_route_path(&format!("static[{}].path", i), &mount.path)?;
+            validate_dir(&format!("static[{}].dir", i), &mount.dir)?;
+        }
+        for (i, upstream) in self.upstreams.iter().enumerate() {
+            upstream.validate(&format!("upstream[{}]", i))?;
+            if self.upstreams[..i].iter().any(|u| u.name == upstream.name) {
+                return Err(ConfigError::Invalid(format!("upstream[{}].name: `{}` is used twice", i, upstream.name)));
+            }
+        }
+        for (i, proxy) in self.proxies.iter().enumerate() {
+            validate_route_path(&format!("proxy[{}].path", i), &proxy.path)?;
+            if !is_host_port(&proxy.upstream) && !self.upstreams.iter().any(|u| u.name == proxy.upstream) {
+                return Err(ConfigError::Invalid(format!(
+                    "proxy[{}].upstream: `{}` is not a `host:port` or an upstream name", i, proxy.upstream)));
+            }
+            if proxy.connect_timeout == Some(0) || proxy.read_timeout == Some(0) {
+                return Err(ConfigError::Invalid(format!("proxy[{}]: timeouts must be at least 1 second", i)));
+            }
+        }
+        for (i, vhost) in self.vhosts.iter().enumerate() {
+            vhost.validate(&format!("vhost[{}]", i))?;
+        }
+        if let Some(tls) = &self.tls {
+            validate_file("tls.cert", &tls.cert)?;
+            validate_file("tls.key", &tls.key)?;
+        }
+        self.limits.validate()?;
+        self.timeouts.validate()?;
+        self.log.validate()?;
+        if let Some(rate_limit) = &self.rate_limit {
+            if !(rate_limit.rate > 0.0 && rate_limit.rate.is_finite()) {
+                return Err(ConfigError::Invalid("rate_limit.rate: must be greater than 0".to_string()));
+            }
+            if rate_limit.burst == 0 {
+                return Err(ConfigError::Invalid("rate_limit.burst: must be greater than 0".to_string()));
+            }
+            if let Some(header) = &rate_limit.header {
+                if header.is_empty() || !header.bytes().all(is_token) {
+                    return Err(ConfigError::Invalid(format!("rate_limit.header: `{}` is not a header name", header)));
+                }
+            }
+        }
+        for (i, auth) in self.auth.iter().enumerate() {
+            validate_route_path(&format!("auth[{}].path", i), &auth.path)?;
+            validate_file(&format!("auth[{}].htpasswd", i), &auth.htpasswd)?;
+            if self.auth[..i].iter().any(|a| a.path == auth.path) {
+                return Err(ConfigError::Invalid(format!("auth[{}].path: `{}` is used twice", i, auth.path)));
+            }
+        }
+        for (i, auth) in self.digest_auth.iter().enumerate() {
+            validate_route_path(&format!("digest_auth[{}].path", i), &auth.path)?;
+            validate_file(&format!("digest_auth[{}].htdigest", i), &auth.htdigest)?;
+            // one challenge per path, a client could not answer two schemes at once
+            if self.digest_auth[..i].iter().any(|a| a.path == auth.path) || self.auth.iter().any(|a| a.path == auth.path) {
+                return Err(ConfigError::Invalid(format!("digest_auth[{}].path: `{}` is used twice", i, auth.path)));
+            }
+        }
+        for (i, auth) in self.jwt_auth.iter().enumerate() {
+            let field = format!("jwt_auth[{}]", i);
+            validate_route_path(&format!("{}.path", field), &auth.path)?;
+            if auth.secret.is_none() && auth.public_keys.is_empty() && auth.jwks.is_none() {
+                return Err(ConfigError::Invalid(format!("{}: one of secret, public_keys or jwks is required", field)));
+            }
+            if auth.secret.as_deref() == Some("") {
+                return Err(ConfigError::Invalid(format!("{}.secret: must not be empty", field)));
+            }
+            for (k, key) in auth.public_keys.iter().enumerate() {
+                validate_file(&format!("{}.public_keys[{}]", field, k), key)?;
+            }
+            if let Some(jwks) = &auth.jwks {
+                validate_file(&format!("{}.jwks", field), jwks)?;
+            }
+            if self.jwt_auth[..i].iter().any(|a| a.path == auth.path)
+                || self.auth.iter().any(|a| a.path == auth.path)
+                || self.digest_auth.iter().any(|a| a.path == auth.path) {
+                return Err(ConfigError::Invalid(format!("{}.path: `{}` is used twice", field, auth.path)));
+            }
+        }
+        if let Some(metrics) = &self.metrics {
+            validate_route_path("metrics.path", &metrics.path)?;
+            if let Some(address) = &metrics.address {
+                validate_address("metrics.address", address)?;
+            }
+        }
+        validate_headers("headers", &self.headers)
+    }
+}
+
+impl Listener {
+    pub fn tcp(address: &str) -> Self {
+        Listener {
+            address: Some(address.to_string()),
+            path: None,
+            mode: None,
+        }
+    }
+
+    pub fn unix(path: &Path) -> Self {
+        Listener {
+            address: None,
+            path: Some(path.to_path_buf()),
+            mode: None,
+        }
+    }
+
+    fn validate(&self, field: &str) -> Result<(), ConfigError> {
+        match (&self.address, &self.path) {
+            (Some(address), None) => {
+                validate_address(&format!("{}.address", field), address)?;
+                if self.mode.is_some() {
+                    return Err(ConfigError::Invalid(format!("{}.mode: only applies to a unix socket `path`", field)));
+                }
+            },
+            (None, Some(path)) => {
+                if path.as_os_str().is_empty() || path.is_dir() {
+                    return Err(ConfigError::Invalid(format!(
+                        "{}.path: `{}` is not a socket file path", field, path.display())));
+                }
+                if let Some(mode) = self.mode {
+                    if mode > 0o777 {
+                        return Err(ConfigError::Invalid(format!("{}.mode: `{:o}` is not a permission mode", field, mode)));
+                    }
+                }
+            },
+            _ => return Err(ConfigError::Invalid(format!("{}: exactly one of `address` or `path` is required", field))),
+        }
+        Ok(())
+    }
+}
+
+impl Upstream {
+    fn validate(&self, field: &str) -> Result<(), ConfigError> {
+        if self.name.is_empty() || self.name.contains(':') {
+            return Err(ConfigError::Invalid(format!("{}.name: `{}` must be non-empty without `:`", field, self.name)));
+        }
+        if !BALANCES.contains(&self.balance.as_str()) {
+            return Err(ConfigError::Invalid(format!(
+                "{}.balance: `{}` is not one of {}", field, self.balance, BALANCES.join(", "))));
+        }
+        if let Some(header) = &self.hash_header {
+            if self.balance != "hash" {
+                return Err(ConfigError::Invalid(format!("{}.hash_header: only applies to `balance = \"hash\"`", field)));
+            }
+            if header.is_empty() || !header.bytes().all(is_token) {
+                return Err(ConfigError::Invalid(format!("{}.hash_header: `{}` is not a header name", field, header)));
+            }
+        }
+        if self.servers.is_empty() {
+            return Err(ConfigError::Invalid(format!("{}.server: at least one server is required", field)));
+        }
+        for (i, server) in self.servers.iter().enumerate() {
+            if !is_host_port(&server.address) {
+                return Err(ConfigError::Invalid(format!(
+                    "{}.server[{}].address: `{}` is not a `host:port`", field, i, server.address)));
+            }
+            if server.weight == 0 || server.weight > MAX_WEIGHT {
+                return Err(ConfigError::Invalid(format!("{}.server[{}].weight: must be between 1 and {}", field, i, MAX_WEIGHT)));
+            }
+        }
+        if self.fail_timeout == 0 {
+            return Err(ConfigError::Invalid(format!("{}.fail_timeout: must be greater than 0", field)));
+        }
+        if let Some(check) = &self.health_check {
+            validate_route_path(&format!("{}.health_check.path", field), &check.path)?;
+            let fields = [
+                ("interval", check.interval),
+                ("timeout", check.timeout),
+                ("passes", u64::from(check.passes)),
+                ("fails", u64::from(check.fails)),
+            ];
+            for (name, value) in fields.iter() {
+                if *value == 0 {
+                    return Err(ConfigError::Invalid(format!("{}.health_check.{}: must be greater than 0", field, name)));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl VirtualHost {
+    fn validate(&self, field: &str) -> Result<(), ConfigError> {
+        if self.names.is_empty() {
+            return Err(ConfigError::Invalid(format!("{}.names: at least one host name is required", field)));
+        }
+        for name in self.names.iter() {
+            let host = name.strip_prefix("*.").unwrap_or(name);
+            if host.is_empty() || host.contains('*') || host.contains(':') || host.contains('/') {
+                return Err(ConfigError::Invalid(format!(
+                    "{}.names: `{}` is not a host name or a `*.` wildcard", field, name)));
+            }
+        }
+        validate_dir(&format!("{}.root", field), &self.root)?;
+        for (i, mount) in self.mounts.iter().enumerate() {
+            validate_route_path(&format!("{}.static[{}].path", field, i), &mount.path)?;
+            validate_dir(&format!("{}.static[{}].dir", field, i), &mount.dir)?;
+        }
+        validate_headers(&format!("{}.headers", field), &self.headers)
+    }
+}
+
+impl Limits {
+    fn validate(&self) -> Result<(), ConfigError> {
+        let fields = [
+            ("max_request_line", self.max_request_line),
+            ("max_headers", self.max_headers),
+            ("max_header_bytes", self.max_header_bytes),
+            ("max_body", self.max_body),
+        ];
+        for (name, value) in fields.iter() {
+            if *value == 0 {
+                return Err(ConfigError::Invalid(format!("limits.{}: must be greater than 0", name)));
+            }
+        }
+        for (name, value) in [("max_connections", self.max_connections), ("max_connections_per_ip", self.max_connections_per_ip)].iter() {
+            if *value == Some(0) {
+                return Err(ConfigError::Invalid(format!("limits.{}: must be greater than 0", name)));
+            }
+        }
+        for (i, route) in self.routes.iter().enumerate() {
+            validate_route_path(&format!("limits.route[{}].path", i), &route.path)?;
+            let fields = [
+                ("max_request_line", route.max_request_line),
+                ("max_headers", route.max_headers),
+                ("max_header_bytes", route.max_header_bytes),
+                ("max_body", route.max_body),
+            ];
+            for (name, value) in fields.iter() {
+                if *value == Some(0) {
+                    return Err(ConfigError::Invalid(format!("limits.route[{}].{}: must be greater than 0", i, name)));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Timeouts {
+    fn validate(&self) -> Result<(), ConfigError> {
+        let fields = [
+            ("header_read", self.header_read),
+            ("body_read", self.body_read),
+            ("write", self.write),
+            ("keep_alive", self.keep_alive),
+        ];
+        for (name, value) in fields.iter() {
+            if *value == 0 {
+                return Err(ConfigError::Invalid(format!("timeouts.{}: must be greater than 0", name)));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Log {
+    fn validate(&self) -> Result<(), ConfigError> {
+        for directive in self.level.split(',') {
+            // `module=level` or a bare level for every module
+            let level = directive.rsplit('=').next().unwrap_or_default().trim();
+            if !LOG_LEVELS.contains(&level) {
+                return Err(ConfigError::Invalid(format!(
+                    "log.level: `{}` is not one of {}", level, LOG_LEVELS.join(", "))));
+            }
+        }
+        if !LOG_FORMATS.contains(&self.format.as_str()) {
+            return Err(ConfigError::Invalid(format!(
+                "log.format: `{}` is not one of {}", self.format, LOG_FORMATS.join(", "))));
+        }
+        if let Some(path) = &self.access_log {
+            if path.is_empty() {
+                return Err(ConfigError::Invalid("log.access_log: must be `stdout` or a file path".to_string()));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            root: default_root(),
+            listeners: default_listeners(),
+            dual_stack: false,
+            threads: default_threads(),
+            mounts: Vec::new(),
+            proxies: Vec::new(),
+            upstreams: Vec::new(),
+            vhosts: Vec::new(),
+            tls: None,
+            limits: Limits::default(),
+            timeouts: Timeouts::default(),
+            log: Log::default(),
+            rate_limit: None,
+            auth: Vec::new(),
+            digest_auth: Vec::new(),
+            jwt_auth: Vec::new(),
+            metrics: None,
+            headers: BTreeMap::new(),
+        }
+    }
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            max_request_line: 8 * 1024,
+            max_headers: 100,
+            max_header_bytes: 8 * 1024,
+            max_body: 1024 * 1024,
+            max_connections: None,
+            max_connections_per_ip: None,
+            routes: Vec::new(),
+        }
+    }
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Timeouts {
+            header_read: 10,
+            body_read: 30,
+            write: 30,
+            keep_alive: 5,
+        }
+    }
+}
+
+impl Default for HealthCheck {
+    fn default() -> Self {
+        HealthCheck {
+            path: "/".to_string(),
+            interval: 5,
+            timeout: 2,
+            passes: 2,
+            fails: 3,
+        }
+    }
+}
+
+impl Default for Log {
+    fn default() -> Self {
+        Log {
+            level: "info".to_string(),
+            access_log: None,
+            format: "common".to_string(),
+        }
+    }
+}
+
+fn default_root() -> PathBuf {
+    Path::new("/etc/rushttp/static/assets/html").to_path_buf()
+}
+
+fn default_listeners() -> Vec<Listener> {
+    vec![Listener::tcp("0.0.0.0:80")]
+}
+
+fn default_threads() -> usize {
+    thread::available_parallelism().map(|n| n.get()).unwrap_or(4)
+}
+
+fn default_metrics_path() -> String {
+    "/metrics".to_string()
+}
+
+fn default_balance() -> String {
+    "round_robin".to_string()
+}
+
+fn default_keepalive() -> usize {
+    8
+}
+
+fn default_max_fails() -> u32 {
+    1
+}
+
+fn default_fail_timeout() -> u64 {
+    10
+}
+
+fn default_weight() -> u32 {
+    1
+}
+
+fn default_leeway() -> u64 {
+    60
+}
+
+// an upstream is not resolved before it is used, so that it may come up after the server
+fn is_host_port(address: &str) -> bool {
+    match address.rsplit_once(':') {
+        Some((host, port)) => !host.is_empty() && port.parse::<u16>().is_ok(),
+        None => false,
+    }
+}
+
+fn validate_address(field: &str, address: &str) -> Result<(), ConfigError> {
+    // `:port` listens on all IPv4 interfaces
+    let resolved = match address.strip_prefix(':') {
+        Some(port) => format!("0.0.0.0:{}", port),
+        None => address.to_string(),
+    };
+    if let Err(e) = resolved.to_socket_addrs() {
+        return Err(ConfigError::Invalid(format!(
+            "{}: `{}` is not a valid `host:port`: {}", field, address, e)));
+    }
+    Ok(())
+}
+
+fn validate_route_path(field: &str, path: &str) -> Result<(), ConfigError> {
+    if !path.starts_with('/') {
+        return Err(ConfigError::Invalid(format!("{}: `{}` must start with `/`", field, path)));
+    }
+    Ok(())
+}
+
+fn validate_dir(field: &str, dir: &Path) -> Result<(), ConfigError> {
+    if !dir.is_dir() {
+        return Err(ConfigError::Invalid(format!("{}: `{}` is not a directory", field, dir.display())));
+    }
+    Ok(())
+}
+
+fn validate_file(field: &str, file: &Path) -> Result<(), ConfigError> {
+    if !file.is_file() {
+        return Err(ConfigError::Invalid(format!("{}: `{}` is not a file", field, file.display())));
+    }
+    Ok(())
+}
+
+fn validate_headers(field: &str, headers: &BTreeMap<String, String>) -> Result<(), ConfigError> {
+    for (name, value) in headers.iter() {
+        if name.is_empty() || !name.bytes().all(is_token) {
+            return Err(ConfigError::Invalid(format!("{}: `{}` is not a valid header name", field, name)));
+        }
+        if value.contains('\r') || value.contains('\n') {
+            return Err(ConfigError::Invalid(format!("{}.{}: value must not contain line breaks", field, name)));
+        }
+    }
+    Ok(())
+}
+
+fn is_token(c: u8) -> bool {
+    c.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&c)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ConfigError;
+
+    #[test]
+    fn test_parse() {
+        let src = r#"
+            root = "src/static/assets/html"
+
+            dual_stack = true
+
+            [[listener]]
+            address = "127.0.0.1:8080"
+
+            [[listener]]
+            address = "[::]:8080"
+
+            [[listener]]
+            path = "/tmp/rushttp.sock"
+            mode = 0o660
+
+            [[static]]
+            path = "/assets"
+            dir = "src/static/assets"
+
+            [[proxy]]
+            path = "/api"
+            upstream = "localhost:9000"
+            read_timeout = 120
+
+            [timeouts]
+            write = 5
+
+            [headers]
+            X-Frame-Options = "DENY"
+        "#;
+        let config = super::Config::parse(src).unwrap();
+        assert_eq!(config.listeners[0].address.as_deref(), Some("127.0.0.1:8080"));
+        assert_eq!(config.listeners[1].address.as_deref(), Some("[::]:8080"));
+        assert_eq!(config.listeners[2].mode, Some(0o660));
+        assert!(config.dual_stack);
+        assert_eq!(config.mounts[0].path, "/assets");
+        assert_eq!(config.proxies[0].upstream, "localhost:9000");
+        assert_eq!(config.proxies[0].read_timeout, Some(120));
+        assert_eq!(config.proxies[0].connect_timeout, None);
+        assert_eq!(config.timeouts.write, 5);
+        assert_eq!(config.timeouts.header_read, 10);
+        assert_eq!(config.limits, super::Limits::default());
+        assert_eq!(config.headers["X-Frame-Options"], "DENY");
+    }
+    #[test]
+    fn test_parse_invalid_listener() {
+        let src = "root = \"src/static/assets/html\"\n[[listener]]\naddress = \"localhost\"\n";
+        match super::Config::parse(src) {
+            Err(ConfigError::Invalid(msg)) => assert!(msg.starts_with("listener[0].address")),
+            res => panic!("unexpected result: {:?}", res),
+        }
+    }
+    #[test]
+    fn test_parse_vhost() {
+        let src = r#"
+            root = "src/static/assets/html"
+
+            [[vhost]]
+            names = ["example.com", "*.example.com"]
+            root = "src/static/assets/html"
+
+            [[vhost.static]]
+            path = "/assets"
+            dir = "src/static/assets"
+        "#;
+        let config = super::Config::parse(src).unwrap();
+        assert_eq!(config.vhosts[0].names, vec!["example.com", "*.example.com"]);
+        assert_eq!(config.vhosts[0].mounts[0].path, "/assets");
+    }
+    #[test]
+    fn test_parse_invalid_vhost_name() {
+        let src = "root = \"src/static/assets/html\"\n[[vhost]]\nnames = [\"example.*\"]\nroot = \"src/static/assets/html\"\n";
+        match super::Config::parse(src) {
+            Err(ConfigError::Invalid(msg)) => assert!(msg.starts_with("vhost[0].names")),
+            res => panic!("unexpected result: {:?}", res),
+        }
+    }
+    #[test]
+    fn test_parse_invalid_unix_listener() {
+        let src = "root = \"src/static/assets/html\"\n[[listener]]\naddress = \":80\"\npath = \"/tmp/rushttp.sock\"\n";
+        match super::Config::parse(src) {
+            Err(ConfigError::Invalid(msg)) => assert!(msg.starts_with("listener[0]: exactly one")),
+            res => panic!("unexpected result: {:?}", res),
+        }
+    }
+    #[test]
+    fn test_parse_log_level() {
+        let src = "root = \"src/static/assets/html\"\n[log]\nlevel = \"warn,rushttp::server=debug\"\n";
+        assert_eq!(super::Config::parse(src).unwrap().log.level, "warn,rushttp::server=debug");
+        let src = "root = \"src/static/assets/html\"\n[log]\nlevel = \"off,rushttp=error\"\n";
+        assert_eq!(super::Config::parse(src).unwrap().log.level, "off,rushttp=error");
+        let src = "root = \"src/static/assets/html\"\n[log]\nlevel = \"rushttp=loud\"\n";
+        match super::Config::parse(src) {
+            Err(ConfigError::Invalid(msg)) => assert!(msg.starts_with("log.level: `loud`")),
+            res => panic!("unexpected result: {:?}", res),
+        }
+    }
+    #[test]
+    fn test_parse_metrics() {
+        let src = "root = \"src/static/assets/html\"\n[metrics]\naddress = \"127.0.0.1:9100\"\n";
+        let metrics = super::Config::parse(src).unwrap().metrics.unwrap();
+        assert_eq!(metrics.path, "/metrics");
+        assert_eq!(metrics.address.as_deref(), Some("127.0.0.1:9100"));
+        let src = "root = \"src/static/assets/html\"\n[metrics]\npath = \"metrics\"\n";
+        assert!(matches!(super::Config::parse(src), Err(ConfigError::Invalid(_))));
+    }
+    #[test]
+    fn test_parse_route_limits() {
+        let src = r#"
+            root = "src/static/assets/html"
+
+            [[limits.route]]
+            path = "/upload"
+            max_body = 104857600
+        "#;
+        let config = super::Config::parse(src).unwrap();
+        assert_eq!(config.limits.max_body, 1024 * 1024);
+        assert_eq!(config.limits.routes[0].path, "/upload");
+        assert_eq!(config.limits.routes[0].max_body, Some(100 * 1024 * 1024));
+        assert_eq!(config.limits.routes[0].max_headers, None);
+    }
+    #[test]
+    fn test_parse_rate_limit() {
+        let src = "root = \"src/static/assets/html\"\n[rate_limit]\nrate = 0.5\nburst = 10\nheader = \"X-Api-Key\"\n";
+        let rate_limit = super::Config::parse(src).unwrap().rate_limit.unwrap();
+        assert_eq!(rate_limit.rate, 0.5);
+        assert_eq!(rate_limit.header.as_deref(), Some("X-Api-Key"));
+        let src = "root = \"src/static/assets/html\"\n[rate_limit]\nrate = 0.0\nburst = 10\n";
+        assert!(matches!(super::Config::parse(src), Err(ConfigError::Invalid(_))));
+    }
+    #[test]
+    fn test_parse_auth() {
+        let src = "root = \"src/static/assets/html\"\n[[auth]]\npath = \"/admin\"\nrealm = \"Admin\"\nhtpasswd = \"Cargo.toml\"\n";
+        let auth = super::Config::parse(src).unwrap().auth;
+        assert_eq!(auth[0].realm, "Admin");
+        let src = "root = \"src/static/assets/html\"\n[[digest_auth]]\npath = \"/admin\"\nrealm = \"Admin\"\nhtdigest = \"Cargo.toml\"\n";
+        assert_eq!(super::Config::parse(src).unwrap().digest_auth[0].realm, "Admin");
+        let src = format!("{}[[auth]]\npath = \"/admin\"\nrealm = \"Admin\"\nhtpasswd = \"Cargo.toml\"\n", src);
+        assert!(matches!(super::Config::parse(&src), Err(ConfigError::Invalid(_))));
+        let src = "root = \"src/static/assets/html\"\n[[jwt_auth]]\npath = \"/api\"\nrealm = \"api\"\nsecret = \"s3cret\"\n";
+        let jwt = &super::Config::parse(src).unwrap().jwt_auth[0];
+        assert_eq!((jwt.secret.as_deref(), jwt.leeway), (Some("s3cret"), 60));
+        let src = "root = \"src/static/assets/html\"\n[[jwt_auth]]\npath = \"/api\"\nrealm = \"api\"\n";
+        assert!(matches!(super::Config::parse(src), Err(ConfigError::Invalid(_))));
+        let src = "root = \"src/static/assets/html\"\n[[jwt_auth]]\npath = \"/api\"\nrealm = \"api\"\njwks = \"missing\"\n";
+        assert!(matches!(super::Config::parse(src), Err(ConfigError::Invalid(_))));
+        let src = "root = \"src/static/assets/html\"\n[[auth]]\npath = \"/admin\"\nrealm = \"Admin\"\nhtpasswd = \"missing\"\n";
+        assert!(matches!(super::Config::parse(src), Err(ConfigError::Invalid(_))));
+    }
+    #[test]
+    fn test_parse_upstream() {
+        let src = r#"
+            root = "src/static/assets/html"
+
+            [[upstream]]
+            name = "api"
+            balance = "hash"
+            hash_header = "X-User"
+            server = [{ address = "10.0.0.1:9000", weight = 2 }, { address = "10.0.0.2:9000" }]
+            [upstream.health_check]
+            path = "/health"
+
+            [[proxy]]
+            path = "/api"
+            upstream = "api"
+        "#;
+        let config = super::Config::parse(src).unwrap();
+        let upstream = &config.upstreams[0];
+        assert_eq!(upstream.servers[1].weight, 1);
+        assert_eq!(upstream.keepalive, 8);
+        let check = upstream.health_check.as_ref().unwrap();
+        assert_eq!((check.path.as_str(), check.interval), ("/health", 5));
+        let heavy = src.replace("weight = 2", "weight = 4000000000");
+        assert!(matches!(super::Config::parse(&heavy), Err(ConfigError::Invalid(_))));
+        let src = src.replace("upstream = \"api\"", "upstream = \"web\"");
+        assert!(matches!(super::Config::parse(&src), Err(ConfigError::Invalid(_))));
+    }
+    #[test]
+    fn test_parse_unknown_field() {
+        let src = "root = \"src/static/assets/html\"\nport = 80\n";
+        assert!(matches!(super::Config::parse(src), Err(ConfigError::Parse(_))));
+    }
+    #[test]
+    fn test_parse_missing_root() {
+        let src = "root = \"src/static/assets/none\"\n";
+        assert!(matches!(super::Config::parse(src), Err(ConfigError::Invalid(_))));
+    }
+}
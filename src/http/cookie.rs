@@ -0,0 +1,297 @@
+use std::fmt;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use httpdate::{fmt_http_date, parse_http_date};
+use crate::http::header::Header;
+
+/// A `Set-Cookie` value, built with its attributes and written with `to_string`.
+/// The name must be a token and the value must not contain whitespace, `"`, `,`, `;` or `\`.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct SetCookie {
+    name: String,
+    value: String,
+    expires: Option<SystemTime>,
+    max_age: Option<i64>,
+    domain: Option<String>,
+    path: Option<String>,
+    secure: bool,
+    http_only: bool,
+    same_site: Option<SameSite>,
+}
+
+/// Whether a browser sends the cookie with requests from other sites.
+/// `None` needs `Secure` as well.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum SameSite {
+    Strict,
+    Lax,
+    None,
+}
+
+/// The name and value pairs of a `Cookie` request header value, in order.
+pub fn parse(value: &str) -> Vec<(String, String)> {
+    value.split(';')
+        .filter_map(|pair| pair.split_once('='))
+        .map(|(name, value)| (name.trim().to_string(), unquote(value.trim()).to_string()))
+        .filter(|(name, _)| !name.is_empty())
+        .collect()
+}
+
+/// The cookies of every `Cookie` field of a request.
+pub fn cookies(header: &Header) -> Vec<(String, String)> {
+    header.get_all("Cookie").flat_map(parse).collect()
+}
+
+/// The value of the first cookie called `name`.
+pub fn get(header: &Header, name: &str) -> Option<String> {
+    cookies(header).into_iter()
+        .find(|(n, _)| n == name)
+        .map(|(_, value)| value)
+}
+
+impl SetCookie {
+    pub fn new(name: &str, value: &str) -> Self {
+        SetCookie {
+            name: name.to_string(),
+            value: value.to_string(),
+            expires: None,
+            max_age: None,
+            domain: None,
+            path: None,
+            secure: false,
+            http_only: false,
+            same_site: None,
+        }
+    }
+
+    /// A cookie which makes the client drop its cookie called `name`.
+    /// Domain and path have to be the ones it was set with.
+    pub fn remove(name: &str) -> Self {
+        SetCookie::new(name, "").max_age(0).expires(UNIX_EPOCH)
+    }
+
+    pub fn expires(self, expires: SystemTime) -> Self {
+        SetCookie {
+            expires: Some(expires),
+            ..self
+        }
+    }
+
+    /// Seconds until the cookie expires, 0 or less expires it at once.
+    pub fn max_age(self, seconds: i64) -> Self {
+        SetCookie {
+            max_age: Some(seconds),
+            ..self
+        }
+    }
+
+    pub fn domain(self, domain: &str) -> Self {
+        SetCookie {
+            domain: Some(domain.to_string()),
+            ..self
+        }
+    }
+
+    pub fn path(self, path: &str) -> Self {
+        SetCookie {
+            path: Some(path.to_string()),
+            ..self
+        }
+    }
+
+    pub fn secure(self, secure: bool) -> Self {
+        SetCookie {
+            secure,
+            ..self
+        }
+    }
+
+    pub fn http_only(self, http_only: bool) -> Self {
+        SetCookie {
+            http_only,
+            ..self
+        }
+    }
+
+    pub fn same_site(self, same_site: SameSite) -> Self {
+        SetCookie {
+            same_site: Some(same_site),
+            ..self
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn value(&self) -> &str {
+        &self.value
+    }
+
+    pub fn get_expires(&self) -> Option<SystemTime> {
+        self.expires
+    }
+
+    pub fn get_max_age(&self) -> Option<i64> {
+        self.max_age
+    }
+
+    pub fn get_domain(&self) -> Option<&str> {
+        self.domain.as_deref()
+    }
+
+    pub fn get_path(&self) -> Option<&str> {
+        self.path.as_deref()
+    }
+
+    pub fn is_secure(&self) -> bool {
+        self.secure
+    }
+
+    pub fn is_http_only(&self) -> bool {
+        self.http_only
+    }
+
+    pub fn get_same_site(&self) -> Option<SameSite> {
+        self.same_site
+    }
+
+    /// Parse a `Set-Cookie` value as a client receives it, unknown attributes are ignored.
+    pub fn parse(src: &str) -> Option<Self> {
+        let mut parts = src.split(';');
+        let (name, value) = parts.next()?.split_once('=')?;
+        let name = name.trim();
+        if name.is_empty() {
+            return None;
+        }
+        let mut cookie = SetCookie::new(name, unquote(value.trim()));
+        for attribute in parts {
+            let (key, value) = match attribute.split_once('=') {
+                Some((key, value)) => (key.trim(), value.trim()),
+                None => (attribute.trim(), ""),
+            };
+            cookie = match key.to_ascii_lowercase().as_str() {
+                "expires" => match parse_http_date(value) {
+                    Ok(expires) => cookie.expires(expires),
+                    Err(_) => cookie,
+                },
+                "max-age" => match value.parse() {
+                    Ok(seconds) => cookie.max_age(seconds),
+                    Err(_) => cookie,
+                },
+                "domain" if !value.is_empty() => cookie.domain(value),
+                "path" => cookie.path(value),
+                "secure" => cookie.secure(true),
+                "httponly" => cookie.http_only(true),
+                "samesite" => match value.to_ascii_lowercase().as_str() {
+                    "strict" => cookie.same_site(SameSite::Strict),
+                    "lax" => cookie.same_site(SameSite::Lax),
+                    "none" => cookie.same_site(SameSite::None),
+                    _ => cookie,
+                },
+                _ => cookie,
+            };
+        }
+        Some(cookie)
+    }
+
+    /// When the cookie expires as seen at `now`, `Max-Age` wins over `Expires`.
+    pub fn expiry(&self, now: SystemTime) -> Option<SystemTime> {
+        match self.max_age {
+            Some(seconds) if seconds <= 0 => Some(UNIX_EPOCH),
+            Some(seconds) => Some(now.checked_add(Duration::from_secs(seconds as u64)).unwrap_or(now)),
+            None => self.expires,
+        }
+    }
+}
+
+impl fmt::Display for SetCookie {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}={}", self.name, self.value)?;
+        if let Some(expires) = self.expires {
+            write!(f, "; Expires={}", fmt_http_date(expires))?;
+        }
+        if let Some(max_age) = self.max_age {
+            write!(f, "; Max-Age={}", max_age)?;
+        }
+        if let Some(domain) = &self.domain {
+            write!(f, "; Domain={}", domain)?;
+        }
+        if let Some(path) = &self.path {
+            write!(f, "; Path={}", path)?;
+        }
+        if self.secure {
+            f.write_str("; Secure")?;
+        }
+        if self.http_only {
+            f.write_str("; HttpOnly")?;
+        }
+        if let Some(same_site) = self.same_site {
+            write!(f, "; SameSite={}", same_site.as_str())?;
+        }
+        Ok(())
+    }
+}
+
+impl SameSite {
+    pub fn as_str(&self) -> &str {
+        match self {
+            SameSite::Strict => "Strict",
+            SameSite::Lax => "Lax",
+            SameSite::None => "None",
+        }
+    }
+}
+
+fn unquote(value: &str) -> &str {
+    value.strip_prefix('"')
+        .and_then(|value| value.strip_suffix('"'))
+        .unwrap_or(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, UNIX_EPOCH};
+    use crate::http::header::Header;
+    use super::{SameSite, SetCookie};
+
+    #[test]
+    fn test_parse() {
+        let mut header = Header::new();
+        header.add("Cookie", "session=abc; theme=\"dark\";;broken; lang=en=GB");
+        header.add("Cookie", "late=1");
+        assert_eq!(super::cookies(&header), [
+            ("session".to_string(), "abc".to_string()),
+            ("theme".to_string(), "dark".to_string()),
+            ("lang".to_string(), "en=GB".to_string()),
+            ("late".to_string(), "1".to_string()),
+        ]);
+        assert_eq!(super::get(&header, "late"), Some("1".to_string()));
+        assert_eq!(super::get(&header, "missing"), None);
+    }
+    #[test]
+    fn test_format() {
+        let cookie = SetCookie::new("id", "a3fWa")
+            .expires(UNIX_EPOCH + Duration::from_secs(1445412480))
+            .max_age(3600)
+            .domain("example.com")
+            .path("/docs")
+            .secure(true)
+            .http_only(true)
+            .same_site(SameSite::Lax);
+        let value = "id=a3fWa; Expires=Wed, 21 Oct 2015 07:28:00 GMT; Max-Age=3600; Domain=example.com; Path=/docs; Secure; HttpOnly; SameSite=Lax";
+        assert_eq!(cookie.to_string(), value);
+        assert_eq!(SetCookie::parse(value), Some(cookie));
+        assert_eq!(SetCookie::remove("id").to_string(), "id=; Expires=Thu, 01 Jan 1970 00:00:00 GMT; Max-Age=0");
+    }
+    #[test]
+    fn test_parse_set_cookie() {
+        let cookie = SetCookie::parse("a = b ; path=/ ; SECURE; Max-Age=oops; Expires=never; Unknown=1").unwrap();
+        assert_eq!((cookie.name(), cookie.value()), ("a", "b"));
+        assert_eq!(cookie.get_path(), Some("/"));
+        assert!(cookie.is_secure());
+        assert_eq!(cookie.get_max_age(), None);
+        assert_eq!(cookie.get_expires(), None);
+        assert_eq!(SetCookie::parse("no value"), None);
+        assert_eq!(SetCookie::parse("=value"), None);
+    }
+}
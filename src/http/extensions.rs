@@ -0,0 +1,68 @@
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::fmt;
+
+/// Values attached to a request by middleware for later middleware and handlers, one per type.
+#[derive(Default)]
+pub struct Extensions {
+    map: HashMap<TypeId, Box<dyn Any + Send + Sync>>,
+}
+
+impl Extensions {
+    pub fn new() -> Self {
+        Extensions::default()
+    }
+
+    /// Attach a value, returning the one of the same type it replaces.
+    pub fn insert<T: Any + Send + Sync>(&mut self, value: T) -> Option<T> {
+        self.map.insert(TypeId::of::<T>(), Box::new(value))
+            .and_then(|old| old.downcast().ok())
+            .map(|old| *old)
+    }
+
+    pub fn get<T: Any + Send + Sync>(&self) -> Option<&T> {
+        self.map.get(&TypeId::of::<T>())
+            .and_then(|value| value.downcast_ref())
+    }
+
+    pub fn get_mut<T: Any + Send + Sync>(&mut self) -> Option<&mut T> {
+        self.map.get_mut(&TypeId::of::<T>())
+            .and_then(|value| value.downcast_mut())
+    }
+
+    pub fn remove<T: Any + Send + Sync>(&mut self) -> Option<T> {
+        self.map.remove(&TypeId::of::<T>())
+            .and_then(|value| value.downcast().ok())
+            .map(|value| *value)
+    }
+}
+
+impl fmt::Debug for Extensions {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Extensions")
+            .field("len", &self.map.len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Extensions;
+
+    #[derive(Debug, PartialEq)]
+    struct User(String);
+
+    #[test]
+    fn test_insert() {
+        let mut ext = Extensions::new();
+        assert_eq!(ext.insert(User("alice".to_string())), None);
+        ext.insert(7u32);
+        assert_eq!(ext.get::<User>(), Some(&User("alice".to_string())));
+        if let Some(n) = ext.get_mut::<u32>() {
+            *n += 1;
+        }
+        assert_eq!(ext.insert(User("bob".to_string())), Some(User("alice".to_string())));
+        assert_eq!(ext.remove::<u32>(), Some(8));
+        assert_eq!(ext.get::<u32>(), None);
+    }
+}
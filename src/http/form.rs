@@ -0,0 +1,158 @@
+use std::fmt;
+
+/// `application/x-www-form-urlencoded` name and value pairs, in order and with repeated names kept.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct Form {
+    pairs: Vec<(String, String)>,
+}
+
+pub const CONTENT_TYPE: &str = "application/x-www-form-urlencoded";
+
+impl Form {
+    pub fn new() -> Self {
+        Form::default()
+    }
+
+    /// Parse a form body or query string as the URL Standard does:
+    /// `+` is a space, percent escapes are decoded, a pair without `=` has an empty value
+    /// and bytes which are not UTF-8 become U+FFFD.
+    pub fn parse(src: &str) -> Self {
+        let pairs = src.split('&')
+            .filter(|pair| !pair.is_empty())
+            .map(|pair| {
+                let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+                (decode(name), decode(value))
+            })
+            .collect();
+        Form { pairs }
+    }
+
+    /// The first value of `name`.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.pairs.iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn get_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.pairs.iter()
+            .filter(move |(n, _)| n == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+
+    pub fn append(&mut self, name: &str, value: &str) {
+        self.pairs.push((name.to_string(), value.to_string()));
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.pairs.iter().map(|(name, value)| (name.as_str(), value.as_str()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.pairs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pairs.is_empty()
+    }
+}
+
+/// Encoded back into a form body.
+impl fmt::Display for Form {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, (name, value)) in self.pairs.iter().enumerate() {
+            if i > 0 {
+                f.write_str("&")?;
+            }
+            write!(f, "{}={}", encode(name), encode(value))?;
+        }
+        Ok(())
+    }
+}
+
+/// Whether a `Content-Type` value is a form, parameters such as `charset` aside.
+pub fn is_form(content_type: &str) -> bool {
+    let essence = content_type.split(';').next().unwrap_or_default();
+    essence.trim().eq_ignore_ascii_case(CONTENT_TYPE)
+}
+
+/// Decode percent escapes, an escape without two hex digits is kept as it is.
+pub fn percent_decode(src: &str) -> String {
+    let bytes = src.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = match bytes.get(i + 1..i + 3) {
+            Some(hex) if bytes[i] == b'%' => std::str::from_utf8(hex).ok()
+                .filter(|hex| hex.bytes().all(|b| b.is_ascii_hexdigit()))
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok()),
+            _ => None,
+        };
+        match escaped {
+            Some(byte) => {
+                out.push(byte);
+                i += 3;
+            },
+            None => {
+                out.push(bytes[i]);
+                i += 1;
+            },
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+fn decode(src: &str) -> String {
+    percent_decode(&src.replace('+', " "))
+}
+
+// the form encoding of the URL Standard: spaces become `+`, bytes other than `*-._` and alphanumerics are escaped
+fn encode(src: &str) -> String {
+    let mut out = String::with_capacity(src.len());
+    for byte in src.bytes() {
+        match byte {
+            b' ' => out.push('+'),
+            b'*' | b'-' | b'.' | b'_' => out.push(char::from(byte)),
+            b if b.is_ascii_alphanumeric() => out.push(char::from(b)),
+            b => out.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Form;
+
+    #[test]
+    fn test_parse() {
+        let form = Form::parse("name=J%C3%BCrgen+M&tag=a&tag=b&empty=&flag&&a%26b=1%3D2&bad=%zz%4");
+        let pairs: Vec<(&str, &str)> = form.iter().collect();
+        assert_eq!(pairs, [
+            ("name", "Jürgen M"), ("tag", "a"), ("tag", "b"), ("empty", ""), ("flag", ""), ("a&b", "1=2"), ("bad", "%zz%4"),
+        ]);
+        assert_eq!(form.get("tag"), Some("a"));
+        assert_eq!(form.get_all("tag").collect::<Vec<_>>(), ["a", "b"]);
+        assert!(form.contains("flag") && !form.contains("missing"));
+        assert_eq!(Form::parse("x=%FF").get("x"), Some("\u{FFFD}"));
+        assert!(Form::parse("").is_empty());
+    }
+    #[test]
+    fn test_format() {
+        let mut form = Form::new();
+        form.append("name", "Jürgen M");
+        form.append("q", "a&b=c+d*");
+        assert_eq!(form.to_string(), "name=J%C3%BCrgen+M&q=a%26b%3Dc%2Bd*");
+        assert_eq!(Form::parse(&form.to_string()), form);
+    }
+    #[test]
+    fn test_is_form() {
+        assert!(super::is_form("application/x-www-form-urlencoded"));
+        assert!(super::is_form("Application/X-WWW-Form-Urlencoded; charset=UTF-8"));
+        assert!(!super::is_form("multipart/form-data; boundary=x"));
+    }
+}
@@ -2,20 +2,22 @@ use std::collections::{HashMap, BinaryHeap};
 use std::time::SystemTime;
 use thiserror::Error;
 use std::{fmt, error};
-use regex::Regex;
 use crate::http::parser::ParseError;
 use httpdate::HttpDate;
 
-#[derive(Debug, Eq, PartialEq)]
+/// Header fields in the order they were added, a name may repeat.
+#[derive(Debug, Clone, Eq, PartialEq)]
 pub struct Header {
     pub name: Vec<HeaderName>,
+    pub value: Vec<HeaderValue>,
+    // the first value of each name
     pub map: HashMap<String, String>
 }
 
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Debug, Clone, Eq, PartialEq)]
 pub struct HeaderName(pub String);
 
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Debug, Clone, Eq, PartialEq)]
 pub struct HeaderValue(pub String);
 
 #[derive(Error)]
@@ -89,31 +91,66 @@ impl Header {
         Header::default()
     }
 
+    /// Append a field, an earlier one with the same name is kept.
     pub fn add(&mut self, key: &str, value: &str) {
         self.name.push(HeaderName(key.to_string()));
+        self.value.push(HeaderValue(value.to_string()));
         self.map.entry(key.to_string()).or_insert(value.to_string());
     }
 
+    /// Replace every field named `key` with one.
+    pub fn set(&mut self, key: &str, value: &str) {
+        self.remove(key);
+        self.add(key, value);
+    }
+
+    /// Remove every field named `key`, matched case-insensitively.
+    pub fn remove(&mut self, key: &str) {
+        let (name, value) = self.name.drain(..).zip(self.value.drain(..))
+            .filter(|(name, _)| !name.0.eq_ignore_ascii_case(key))
+            .unzip();
+        self.name = name;
+        self.value = value;
+        self.map.retain(|name, _| !name.eq_ignore_ascii_case(key));
+    }
+
+    /// Look up the first header value, the name is matched case-insensitively.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value)
+    }
+
+    /// Every value of a header in order, such as repeated `Set-Cookie` fields.
+    pub fn get_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.iter()
+            .filter(move |(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value)
+    }
+
+    /// The fields as name and value, in order.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.name.iter().zip(self.value.iter())
+            .map(|(name, value)| (name.0.as_str(), value.0.as_str()))
+    }
+
     pub fn parse(&mut self, src: &str) -> Result<(), InvalidHeader> {
-        let re = Regex::new(".*: .*").unwrap();
-        if !re.is_match(src) {
-            println!("invalid header name {}", src);
+        // the name ends at the first colon, the value may contain more
+        let (name, value) = src.split_once(':')
+            .ok_or_else(|| InvalidHeader::Name(InvalidHeaderName::new()))?;
+        if name.is_empty() || name.contains(|c: char| c.is_ascii_whitespace() || c.is_ascii_control()) {
             return Err(InvalidHeader::Name(InvalidHeaderName::new()));
         }
-        let mut name_value = src.split(": ");
-        let n: &str = name_value.next().unwrap();
-        let v: &str = name_value.next().unwrap();
-        self.add(n, v);
+        self.add(name, value.trim_matches(|c| c == ' ' || c == '\t'));
         Ok(())
     }
 
     pub fn format(&self) -> Result<String, ParseError> {
         let mut headers = String::new();
-        for (name, value) in self.map.iter() {
+        for (name, value) in self.iter() {
             let header = &format!("{}: {}\r\n", name, value);
             headers = headers + header;
         }
-        // headers = headers + "\r\n";
         Ok(headers)
     }
 }
@@ -132,11 +169,49 @@ pub fn accept_ranges() -> String {
     "Accept-Ranges: bytes".to_string()
 }
 
+/// `name=value` parameters separated by `separator`, such as `realm="a", qop=auth` of an auth scheme
+/// or `name="a"; filename="b"` of a `Content-Disposition`.
+/// Quoted values are unescaped, names are lowercased.
+pub fn params(src: &str, separator: char) -> Vec<(String, String)> {
+    let mut params = Vec::new();
+    let mut rest = src.trim_start();
+    while let Some((name, after)) = rest.split_once('=') {
+        let name = name.trim().trim_start_matches(separator).trim().to_ascii_lowercase();
+        let after = after.trim_start();
+        let (value, after) = match after.strip_prefix('"') {
+            Some(quoted) => {
+                let mut value = String::new();
+                let mut chars = quoted.char_indices();
+                let mut end = quoted.len();
+                while let Some((i, c)) = chars.next() {
+                    match c {
+                        '\\' => if let Some((_, escaped)) = chars.next() { value.push(escaped) },
+                        '"' => {
+                            end = i + 1;
+                            break;
+                        },
+                        c => value.push(c),
+                    }
+                }
+                (value, &quoted[end..])
+            },
+            None => {
+                let end = after.find(separator).unwrap_or(after.len());
+                (after[..end].trim().to_string(), &after[end..])
+            },
+        };
+        params.push((name, value));
+        rest = after.trim_start().trim_start_matches(separator);
+    }
+    params
+}
+
 
 impl Default for Header {
     fn default() -> Self {
         Header {
             name: Vec::new(),
+            value: Vec::new(),
             map: HashMap::new(),
         }
     }
@@ -232,6 +307,13 @@ mod tests {
         assert_eq!(headers.map["Host"], "localhost:18888".to_string());
     }
     #[test]
+    fn test_get() {
+        let mut headers = super::Header::new();
+        headers.parse("Content-Type: text/html").unwrap();
+        assert_eq!(headers.get("content-type"), Some("text/html"));
+        assert_eq!(headers.get("Host"), None);
+    }
+    #[test]
     fn test_format() {
         let header = "Host: localhost:18888";
         let mut headers = super::Header::new();
@@ -239,4 +321,35 @@ mod tests {
         headers.parse("Content-Type: text/html").unwrap();
         debug_assert_eq!(headers.format().unwrap(), format!("{}\r\n{}\r\n", header, "Content-Type: text/html"));
     }
+    #[test]
+    fn test_parse_colons() {
+        let mut headers = super::Header::new();
+        headers.parse("Referer: http://localhost:8080/a").unwrap();
+        headers.parse("X-Empty:").unwrap();
+        assert_eq!(headers.get("Referer"), Some("http://localhost:8080/a"));
+        assert_eq!(headers.get("X-Empty"), Some(""));
+        assert!(headers.parse("no colon").is_err());
+        assert!(headers.parse("Bad Name: value").is_err());
+    }
+    #[test]
+    fn test_repeated() {
+        let mut headers = super::Header::new();
+        headers.add("Set-Cookie", "a=1");
+        headers.add("Content-Type", "text/html");
+        headers.add("set-cookie", "b=2");
+        assert_eq!(headers.get("Set-Cookie"), Some("a=1"));
+        assert_eq!(headers.get_all("Set-Cookie").collect::<Vec<_>>(), ["a=1", "b=2"]);
+        assert_eq!(headers.format().unwrap(), "Set-Cookie: a=1\r\nContent-Type: text/html\r\nset-cookie: b=2\r\n");
+        headers.set("SET-COOKIE", "c=3");
+        assert_eq!(headers.format().unwrap(), "Content-Type: text/html\r\nSET-COOKIE: c=3\r\n");
+    }
+    #[test]
+    fn test_params() {
+        let params = super::params("realm=\"a \\\"b\\\", c\", qop=auth, NC=00000001 ,empty=\"\"", ',');
+        let params: Vec<(&str, &str)> = params.iter().map(|(n, v)| (n.as_str(), v.as_str())).collect();
+        assert_eq!(params, [("realm", "a \"b\", c"), ("qop", "auth"), ("nc", "00000001"), ("empty", "")]);
+        let params = super::params(" name=\"a;b\"; Filename=c.txt;x=", ';');
+        let params: Vec<(&str, &str)> = params.iter().map(|(n, v)| (n.as_str(), v.as_str())).collect();
+        assert_eq!(params, [("name", "a;b"), ("filename", "c.txt"), ("x", "")]);
+    }
 }
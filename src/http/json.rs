@@ -0,0 +1,88 @@
+use std::fmt;
+use thiserror::Error;
+use crate::http::header::Header;
+use crate::http::response::Response;
+use crate::http::status::StatusCode;
+
+pub const CONTENT_TYPE: &str = "application/json";
+
+/// Why a request body could not be read as JSON.
+#[derive(Error)]
+pub enum JsonError {
+    /// The `Content-Type` is missing or not JSON.
+    ContentType,
+    /// The body is not JSON or not of the expected shape.
+    Invalid(serde_json::Error),
+}
+
+/// Whether a `Content-Type` value is JSON: `application/json` or a `+json` type such as `application/problem+json`.
+pub fn is_json(content_type: &str) -> bool {
+    let essence = content_type.split(';').next().unwrap_or_default().trim().to_ascii_lowercase();
+    match essence.split_once('/') {
+        Some((kind, subtype)) => kind == "application" && (subtype == "json" || subtype.ends_with("+json")),
+        None => false,
+    }
+}
+
+impl JsonError {
+    /// The status to answer the request with.
+    pub fn status(&self) -> StatusCode {
+        match self {
+            JsonError::ContentType => StatusCode(415),
+            JsonError::Invalid(_) => StatusCode(400),
+        }
+    }
+}
+
+/// A plain text response with the status of the error and its message.
+impl From<JsonError> for Response<Vec<u8>> {
+    fn from(err: JsonError) -> Response<Vec<u8>> {
+        let body = err.to_string().into_bytes();
+        let mut header = Header::new();
+        header.add("Content-Type", "text/plain; charset=utf-8");
+        header.add("Content-Length", &body.len().to_string());
+        Response::builder()
+            .status(err.status())
+            .header(header)
+            .response(body)
+    }
+}
+
+impl fmt::Debug for JsonError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            JsonError::ContentType => f.write_str("JsonError::ContentType"),
+            JsonError::Invalid(e) => f.debug_tuple("JsonError::Invalid").field(e).finish(),
+        }
+    }
+}
+
+impl fmt::Display for JsonError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JsonError::ContentType => write!(f, "expected a body of type {}", CONTENT_TYPE),
+            JsonError::Invalid(e) => write!(f, "invalid JSON body: {}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::http::response::Response;
+    use super::JsonError;
+
+    #[test]
+    fn test_is_json() {
+        assert!(super::is_json("application/json"));
+        assert!(super::is_json("Application/JSON; charset=utf-8"));
+        assert!(super::is_json("application/merge-patch+json"));
+        assert!(!super::is_json("text/json+x"));
+        assert!(!super::is_json("application/jsonp"));
+    }
+    #[test]
+    fn test_response() {
+        let res = Response::from(JsonError::ContentType);
+        assert_eq!(res.status().as_u16(), 415);
+        assert_eq!(res.body(), b"expected a body of type application/json");
+    }
+}
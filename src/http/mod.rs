@@ -6,4 +6,10 @@ pub mod request;
 pub mod response;
 pub mod version;
 pub mod parser;
-pub mod error;
\ No newline at end of file
+pub mod error;
+pub mod cookie;
+pub mod extensions;
+pub mod form;
+pub mod multipart;
+#[cfg(feature = "json")]
+pub mod json;
\ No newline at end of file
@@ -0,0 +1,569 @@
+use std::env;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+use crate::http::form::percent_decode;
+use crate::http::header::{params, Header};
+
+/// Size limits of a `multipart/form-data` body, in bytes unless noted otherwise.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Limits {
+    /// Maximum number of parts.
+    pub max_parts: usize,
+    /// Maximum size of the headers of a part.
+    pub max_header_bytes: usize,
+    /// Maximum size of a part without a filename, which is always kept in memory.
+    pub max_field_size: usize,
+    /// Maximum size of a file part.
+    pub max_file_size: usize,
+    /// File parts larger than this are written to a temporary file.
+    pub memory_threshold: usize,
+    /// Where temporary files go, the system temporary directory when unset.
+    pub temp_dir: Option<PathBuf>,
+}
+
+/// The parts of a `multipart/form-data` body, in order.
+#[derive(Debug, Default)]
+pub struct Multipart {
+    parts: Vec<Part>,
+}
+
+/// A part of a `multipart/form-data` body, a form field or an uploaded file.
+#[derive(Debug)]
+pub struct Part {
+    header: Header,
+    name: String,
+    filename: Option<String>,
+    len: usize,
+    data: Data,
+}
+
+#[derive(Debug)]
+enum Data {
+    Memory(Vec<u8>),
+    File(TempFile),
+}
+
+// a file removed when dropped
+#[derive(Debug)]
+struct TempFile {
+    path: PathBuf,
+    file: File,
+    // moved elsewhere, not to be removed
+    kept: bool,
+}
+
+/// Why a `multipart/form-data` body was rejected.
+#[derive(Error)]
+pub enum MultipartError {
+    Io(io::Error),
+    Malformed(&'static str),
+    TooLarge(&'static str),
+}
+
+/// Parses a `multipart/form-data` body as it is written to it, such as by `io::copy`.
+/// After a failed write the error is kept and returned by `finish`.
+pub struct MultipartParser {
+    // `CRLF--boundary`, the body gets a CRLF in front so the first delimiter looks like the others
+    delimiter: Vec<u8>,
+    limits: Limits,
+    buf: Vec<u8>,
+    state: State,
+    parts: Vec<Part>,
+    error: Option<MultipartError>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Preamble,
+    Delimiter,
+    Headers,
+    Body,
+    Done,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            max_parts: 100,
+            max_header_bytes: 8 * 1024,
+            max_field_size: 64 * 1024,
+            max_file_size: 64 * 1024 * 1024,
+            memory_threshold: 256 * 1024,
+            temp_dir: None,
+        }
+    }
+}
+
+/// The boundary of a `multipart/form-data` `Content-Type` value.
+pub fn boundary(content_type: &str) -> Option<String> {
+    let (essence, rest) = content_type.split_once(';').unwrap_or((content_type, ""));
+    if !essence.trim().eq_ignore_ascii_case("multipart/form-data") {
+        return None;
+    }
+    params(rest, ';').into_iter()
+        .find(|(name, _)| name == "boundary")
+        .map(|(_, value)| value)
+        .filter(|boundary| !boundary.is_empty() && boundary.len() <= 70)
+}
+
+impl Multipart {
+    /// Parse a body held in memory.
+    pub fn parse(content_type: &str, body: &[u8], limits: Limits) -> Result<Self, MultipartError> {
+        let boundary = boundary(content_type).ok_or(MultipartError::Malformed("no multipart/form-data boundary"))?;
+        let mut parser = MultipartParser::new(&boundary, limits);
+        // a failed write leaves its error for `finish`
+        let _ = parser.write_all(body);
+        parser.finish()
+    }
+
+    /// The first part called `name`.
+    pub fn get(&self, name: &str) -> Option<&Part> {
+        self.parts.iter().find(|part| part.name == name)
+    }
+
+    pub fn get_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a Part> + 'a {
+        self.parts.iter().filter(move |part| part.name == name)
+    }
+
+    /// The text of the first field called `name`.
+    pub fn text(&self, name: &str) -> Option<&str> {
+        self.get(name).and_then(Part::text)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Part> {
+        self.parts.iter()
+    }
+
+    pub fn into_parts(self) -> Vec<Part> {
+        self.parts
+    }
+
+    pub fn len(&self) -> usize {
+        self.parts.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.parts.is_empty()
+    }
+}
+
+impl Part {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The file name the client gave, without any directories.
+    pub fn filename(&self) -> Option<&str> {
+        self.filename.as_deref()
+    }
+
+    pub fn content_type(&self) -> Option<&str> {
+        self.header.get("Content-Type")
+    }
+
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The temporary file of a part too large to keep in memory.
+    pub fn path(&self) -> Option<&Path> {
+        match &self.data {
+            Data::Memory(_) => None,
+            Data::File(temp) => Some(&temp.path),
+        }
+    }
+
+    /// The content of a part kept in memory when it is UTF-8.
+    pub fn text(&self) -> Option<&str> {
+        match &self.data {
+            Data::Memory(bytes) => std::str::from_utf8(bytes).ok(),
+            Data::File(_) => None,
+        }
+    }
+
+    /// The content, read from the temporary file if there is one.
+    pub fn bytes(&self) -> io::Result<Vec<u8>> {
+        match &self.data {
+            Data::Memory(bytes) => Ok(bytes.clone()),
+            Data::File(temp) => fs::read(&temp.path),
+        }
+    }
+
+    /// Move the content to a file at `to`, renaming the temporary file when possible.
+    pub fn persist<P: AsRef<Path>>(self, to: P) -> io::Result<()> {
+        match self.data {
+            Data::Memory(bytes) => fs::write(to, bytes),
+            Data::File(mut temp) => {
+                if fs::rename(&temp.path, &to).is_ok() {
+                    temp.kept = true;
+                    return Ok(());
+                }
+                // another file system
+                temp.file.seek(SeekFrom::Start(0))?;
+                io::copy(&mut temp.file, &mut File::create(to)?)?;
+                Ok(())
+            },
+        }
+    }
+
+    fn write(&mut self, bytes: &[u8], limits: &Limits) -> Result<(), MultipartError> {
+        self.len += bytes.len();
+        let max = if self.filename.is_some() { limits.max_file_size } else { limits.max_field_size };
+        if self.len > max {
+            return Err(MultipartError::TooLarge("a part is too large"));
+        }
+        match &mut self.data {
+            Data::Memory(memory) if self.filename.is_some() && self.len > limits.memory_threshold => {
+                let mut temp = TempFile::new(limits.temp_dir.as_deref())?;
+                temp.file.write_all(memory)?;
+                temp.file.write_all(bytes)?;
+                self.data = Data::File(temp);
+            },
+            Data::Memory(memory) => memory.extend_from_slice(bytes),
+            Data::File(temp) => temp.file.write_all(bytes)?,
+        }
+        Ok(())
+    }
+}
+
+impl TempFile {
+    fn new(dir: Option<&Path>) -> io::Result<Self> {
+        let dir = dir.map_or_else(env::temp_dir, Path::to_path_buf);
+        let mut random = [0u8; 12];
+        getrandom::getrandom(&mut random).map_err(io::Error::other)?;
+        let name: String = random.iter().map(|b| format!("{:02x}", b)).collect();
+        let path = dir.join(format!("rushttp-upload-{}", name));
+        let mut options = OpenOptions::new();
+        options.read(true).write(true).create_new(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        let file = options.open(&path)?;
+        Ok(TempFile { path, file, kept: false })
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        if !self.kept {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+}
+
+impl MultipartParser {
+    pub fn new(boundary: &str, limits: Limits) -> Self {
+        MultipartParser {
+            delimiter: format!("\r\n--{}", boundary).into_bytes(),
+            limits,
+            buf: b"\r\n".to_vec(),
+            state: State::Preamble,
+            parts: Vec::new(),
+            error: None,
+        }
+    }
+
+    /// Whether a write failed, the body is then rejected by `finish`.
+    pub fn failed(&self) -> bool {
+        self.error.is_some()
+    }
+
+    /// The parts once the whole body was written.
+    pub fn finish(self) -> Result<Multipart, MultipartError> {
+        if let Some(e) = self.error {
+            return Err(e);
+        }
+        if self.state != State::Done {
+            return Err(MultipartError::Malformed("the body ended before the closing boundary"));
+        }
+        Ok(Multipart { parts: self.parts })
+    }
+
+    // take as much of the buffer as the state allows, false when more bytes are needed
+    fn step(&mut self) -> Result<bool, MultipartError> {
+        match self.state {
+            State::Preamble => match find(&self.buf, &self.delimiter) {
+                Some(i) => {
+                    self.buf.drain(..i + self.delimiter.len());
+                    self.state = State::Delimiter;
+                    Ok(true)
+                },
+                None => {
+                    let keep = self.buf.len().min(self.delimiter.len() - 1);
+                    self.buf.drain(..self.buf.len() - keep);
+                    Ok(false)
+                },
+            },
+            State::Delimiter => {
+                // transport padding may follow a delimiter
+                let padding = self.buf.iter().take_while(|b| **b == b' ' || **b == b'\t').count();
+                self.buf.drain(..padding);
+                if self.buf.len() < 2 {
+                    return Ok(false);
+                }
+                self.state = match &self.buf[..2] {
+                    b"--" => State::Done,
+                    b"\r\n" => State::Headers,
+                    _ => return Err(MultipartError::Malformed("no line break after a boundary")),
+                };
+                self.buf.drain(..2);
+                Ok(true)
+            },
+            State::Headers => {
+                let end = if self.buf.starts_with(b"\r\n") { Some(0) } else { find(&self.buf, b"\r\n\r\n").map(|i| i + 2) };
+                let end = match end {
+                    Some(end) if end <= self.limits.max_header_bytes => end,
+                    None if self.buf.len() <= self.limits.max_header_bytes => return Ok(false),
+                    _ => return Err(MultipartError::TooLarge("the headers of a part are too large")),
+                };
+                if self.parts.len() == self.limits.max_parts {
+                    return Err(MultipartError::TooLarge("too many parts"));
+                }
+                let head: Vec<u8> = self.buf.drain(..end + 2).collect();
+                self.parts.push(part(&head[..end])?);
+                self.state = State::Body;
+                Ok(true)
+            },
+            State::Body => {
+                let (len, found) = match find(&self.buf, &self.delimiter) {
+                    Some(i) => (i, true),
+                    // the end may be the start of a delimiter
+                    None => (self.buf.len().saturating_sub(self.delimiter.len() - 1), false),
+                };
+                let part = self.parts.last_mut().expect("a part is being read");
+                part.write(&self.buf[..len], &self.limits)?;
+                if found {
+                    self.buf.drain(..len + self.delimiter.len());
+                    self.state = State::Delimiter;
+                } else {
+                    self.buf.drain(..len);
+                }
+                Ok(found)
+            },
+            State::Done => {
+                // the epilogue is ignored
+                self.buf.clear();
+                Ok(false)
+            },
+        }
+    }
+}
+
+impl Write for MultipartParser {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        if self.failed() {
+            return Err(io::Error::other("the multipart body was rejected"));
+        }
+        self.buf.extend_from_slice(data);
+        loop {
+            match self.step() {
+                Ok(true) => continue,
+                Ok(false) => return Ok(data.len()),
+                Err(e) => {
+                    let err = io::Error::other(e.to_string());
+                    self.error = Some(e);
+                    return Err(err);
+                },
+            }
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl fmt::Debug for MultipartParser {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("MultipartParser")
+            .field("state", &self.state)
+            .field("parts", &self.parts.len())
+            .finish()
+    }
+}
+
+impl MultipartError {
+    /// The status to answer the request with.
+    pub fn status(&self) -> (u16, &'static str) {
+        match self {
+            MultipartError::Io(_) => (500, "Internal Server Error"),
+            MultipartError::Malformed(_) => (400, "Bad Request"),
+            MultipartError::TooLarge(_) => (413, "Payload Too Large"),
+        }
+    }
+}
+
+impl From<io::Error> for MultipartError {
+    fn from(err: io::Error) -> MultipartError {
+        MultipartError::Io(err)
+    }
+}
+
+impl fmt::Debug for MultipartError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MultipartError::Io(e) => f.debug_tuple("MultipartError::Io").field(e).finish(),
+            MultipartError::Malformed(msg) => f.debug_tuple("MultipartError::Malformed").field(msg).finish(),
+            MultipartError::TooLarge(msg) => f.debug_tuple("MultipartError::TooLarge").field(msg).finish(),
+        }
+    }
+}
+
+impl fmt::Display for MultipartError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MultipartError::Io(e) => write!(f, "failed to store a part: {}", e),
+            MultipartError::Malformed(msg) => write!(f, "malformed multipart body: {}", msg),
+            MultipartError::TooLarge(msg) => write!(f, "multipart body too large: {}", msg),
+        }
+    }
+}
+
+// a part from its header lines, which need a `Content-Disposition: form-data` with a name
+fn part(head: &[u8]) -> Result<Part, MultipartError> {
+    let mut header = Header::new();
+    let head = String::from_utf8_lossy(head);
+    for line in head.split("\r\n").filter(|line| !line.is_empty()) {
+        let (name, value) = line.split_once(':').ok_or(MultipartError::Malformed("a part header has no colon"))?;
+        header.add(name.trim(), value.trim());
+    }
+    let disposition = header.get("Content-Disposition").ok_or(MultipartError::Malformed("a part has no Content-Disposition"))?;
+    let (kind, rest) = disposition.split_once(';').unwrap_or((disposition, ""));
+    if !kind.trim().eq_ignore_ascii_case("form-data") {
+        return Err(MultipartError::Malformed("a part is not form-data"));
+    }
+    let params = params(rest, ';');
+    let param = |name: &str| params.iter().find(|(n, _)| n == name).map(|(_, value)| value.clone());
+    let name = param("name").ok_or(MultipartError::Malformed("a part has no name"))?;
+    // `filename*` of RFC 5987 wins over `filename`
+    let filename = param("filename*")
+        .and_then(|value| value.split_once("''").map(|(_, encoded)| percent_decode(encoded)))
+        .or_else(|| param("filename"))
+        .map(|filename| filename.rsplit(['/', '\\']).next().unwrap_or_default().to_string());
+    Ok(Part {
+        header,
+        name,
+        filename,
+        len: 0,
+        data: Data::Memory(Vec::new()),
+    })
+}
+
+fn find(buf: &[u8], pattern: &[u8]) -> Option<usize> {
+    buf.windows(pattern.len()).position(|window| window == pattern)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+    use super::{Limits, Multipart, MultipartError, MultipartParser};
+
+    const BODY: &str = "preamble\r\n\
+        --XyZ\r\n\
+        Content-Disposition: form-data; name=\"title\"\r\n\
+        \r\n\
+        Hello --XyZ world\r\n\
+        --XyZ  \r\n\
+        Content-Disposition: form-data; name=\"file\"; filename=\"C:\\\\docs\\\\a \\\"b\\\".txt\"\r\n\
+        Content-Type: text/plain\r\n\
+        \r\n\
+        line 1\r\nline 2\r\n\r\n\
+        --XyZ\r\n\
+        Content-Disposition: form-data; name=\"empty\"\r\n\
+        \r\n\
+        \r\n\
+        --XyZ--\r\n\
+        epilogue";
+
+    fn limits() -> Limits {
+        Limits {
+            temp_dir: Some(std::env::temp_dir()),
+            ..Limits::default()
+        }
+    }
+
+    #[test]
+    fn test_boundary() {
+        assert_eq!(super::boundary("multipart/form-data; boundary=XyZ"), Some("XyZ".to_string()));
+        assert_eq!(super::boundary("Multipart/Form-Data; charset=utf-8; Boundary=\"a b\""), Some("a b".to_string()));
+        assert_eq!(super::boundary("multipart/form-data; boundary=\"a;b\"; charset=utf-8"), Some("a;b".to_string()));
+        assert_eq!(super::boundary("multipart/mixed; boundary=XyZ"), None);
+        assert_eq!(super::boundary("multipart/form-data"), None);
+    }
+    #[test]
+    fn test_parse() {
+        let multipart = Multipart::parse("multipart/form-data; boundary=XyZ", BODY.as_bytes(), limits()).unwrap();
+        assert_eq!(multipart.len(), 3);
+        assert_eq!(multipart.text("title"), Some("Hello --XyZ world"));
+        let file = multipart.get("file").unwrap();
+        assert_eq!(file.filename(), Some("a \"b\".txt"));
+        assert_eq!(file.content_type(), Some("text/plain"));
+        assert_eq!(file.bytes().unwrap(), b"line 1\r\nline 2\r\n");
+        assert!(file.path().is_none());
+        assert!(multipart.get("empty").unwrap().is_empty());
+    }
+    #[test]
+    fn test_streaming() {
+        // fed a byte at a time, the delimiter is found across writes
+        let mut parser = MultipartParser::new("XyZ", limits());
+        for byte in BODY.as_bytes() {
+            parser.write_all(&[*byte]).unwrap();
+        }
+        let multipart = parser.finish().unwrap();
+        assert_eq!(multipart.get_all("file").count(), 1);
+        assert_eq!(multipart.text("title"), Some("Hello --XyZ world"));
+        let parser = MultipartParser::new("XyZ", limits());
+        assert!(matches!(parser.finish(), Err(MultipartError::Malformed(_))));
+    }
+    #[test]
+    fn test_spill() {
+        let data: Vec<u8> = (0..10_000u32).map(|i| (i % 251) as u8).collect();
+        let mut body = b"--b\r\nContent-Disposition: form-data; name=\"upload\"; filename*=UTF-8''%E6%97%A5.bin\r\n\r\n".to_vec();
+        body.extend_from_slice(&data);
+        body.extend_from_slice(b"\r\n--b--\r\n");
+        let small = Limits { memory_threshold: 1024, ..limits() };
+        let multipart = Multipart::parse("multipart/form-data; boundary=b", &body, small.clone()).unwrap();
+        let part = multipart.into_parts().pop().unwrap();
+        assert_eq!(part.filename(), Some("日.bin"));
+        assert_eq!(part.len(), data.len());
+        let path = part.path().unwrap().to_path_buf();
+        assert_eq!(std::fs::read(&path).unwrap(), data);
+        let to = std::env::temp_dir().join(format!("rushttp-persist-{}", std::process::id()));
+        part.persist(&to).unwrap();
+        assert!(!path.exists());
+        assert_eq!(std::fs::read(&to).unwrap(), data);
+        std::fs::remove_file(&to).unwrap();
+        // the temporary file goes with a part that is dropped
+        let multipart = Multipart::parse("multipart/form-data; boundary=b", &body, small.clone()).unwrap();
+        let path = multipart.get("upload").unwrap().path().unwrap().to_path_buf();
+        drop(multipart);
+        assert!(!path.exists());
+        let tight = Limits { max_file_size: 9_999, ..small };
+        let err = Multipart::parse("multipart/form-data; boundary=b", &body, tight).unwrap_err();
+        assert_eq!(err.status().0, 413);
+    }
+    #[test]
+    fn test_malformed() {
+        let parse = |body: &str| Multipart::parse("multipart/form-data; boundary=b", body.as_bytes(), limits()).map(|m| m.len());
+        assert_eq!(parse("--b--").unwrap(), 0);
+        assert!(parse("--b\r\nContent-Type: text/plain\r\n\r\nx\r\n--b--").is_err());
+        assert!(parse("--b\r\nContent-Disposition: attachment; name=\"a\"\r\n\r\nx\r\n--b--").is_err());
+        assert!(parse("--bX\r\n").is_err());
+        let many = Limits { max_parts: 1, ..limits() };
+        let body = "--b\r\nContent-Disposition: form-data; name=a\r\n\r\n1\r\n--b\r\nContent-Disposition: form-data; name=b\r\n\r\n2\r\n--b--";
+        assert!(matches!(Multipart::parse("multipart/form-data; boundary=b", body.as_bytes(), many), Err(MultipartError::TooLarge(_))));
+    }
+}
@@ -33,21 +33,19 @@ impl Parser {
         let mut data = String::from_utf8(buf.to_vec())
             .map_err(|_| Error::from(ParseError::new()))?;
         // println!("{:?}", data);
-        let mut request_header_body = data.split("\r\n\r\n");
+        // the body may contain blank lines itself
+        let mut request_header_body = data.splitn(2, "\r\n\r\n");
         let mut request = request_header_body.next().ok_or(Error::from(ParseError::new()))?
             .split("\r\n");
         let body = request_header_body.next().unwrap_or("").to_string();
         // validate http packet
         let request_line: &str = request.next().unwrap();
         let ver = Version::parse(request_line).map_err(|e| Error::from(e))?;
-        println!("---------------------------------------------");
-        println!("[info] version: {}", ver.format());
         let mut  split_line = request_line.split_whitespace();
         let method = match split_line.next() {
             Some(m) => Method::from_str(m)?,
             None => return Err(Error::from(ParseError::new())),
         };
-        println!("[info] method {}", method.as_str());
         let path = match split_line.next() {
             Some(p) => match Uri::new(p) {
                 Some(uri) => uri,
@@ -55,22 +53,16 @@ impl Parser {
             },
             None => return Err(Error::from(ParseError::new())),
         };
-        println!("[info] path {}", path.path());
         let request_builder = request_builder.method(method)
             .uri(path).version(ver);
 
         // parse header
         let mut header = Header::new();
         for line in request {
-            match header.parse(line) {
-                Ok(_) => {},
-                Err(e) => println!("[error] {}", e),
-            };
-            println!("[info] header {}", line);
+            // malformed header lines are skipped
+            let _ = header.parse(line);
         }
         let request_builder = request_builder.header(header);
-        println!("[info] body {}", body);
-        println!("---------------------------------------------");
         let req = Request::from_parts(request_builder.parts(), body);
         Ok(req)
     }
@@ -79,11 +71,10 @@ impl Parser {
         let mut data = String::from_utf8(buf.to_vec())
             .map_err(|_| Error::from(ParseError::new()))?;
         let response_builder = Response::builder();
-        let mut response_header_body = data.split("\r\r\n\n");
+        let mut response_header_body = data.splitn(2, "\r\n\r\n");
         let mut response = response_header_body.next().ok_or(Error::from(ParseError::new()))?
             .split("\r\n");
         let body = response_header_body.next().unwrap_or("").to_string();
-        println!("body: {:?}", body);
         // validate http response
         let response_line: &str = response.next().unwrap();
         let mut split_line = response_line.split_whitespace();
@@ -96,8 +87,8 @@ impl Parser {
         // parse header
         let mut header = Header::new();
         for line in response {
-            header.parse(line)?;
-            println!("{:?}", line);
+            // malformed header lines are skipped
+            let _ = header.parse(line);
         }
         let response_builder = response_builder.header(header);
         let res = Response::from_parts(response_builder.parts(), body);
@@ -1,11 +1,15 @@
+use std::net::SocketAddr;
 use crate::http::version::Version;
 use crate::uri::uri::Uri;
 use crate::http::method::Method;
 use crate::http::header::{Header, HeaderName, HeaderValue};
+use crate::http::extensions::Extensions;
+use crate::http::form::{self, Form};
 use crate::http::status::StatusCode;
 
 pub struct Request<T> {
     head: Parts,
+    extensions: Extensions,
     body: T
 }
 
@@ -15,6 +19,8 @@ pub struct Parts {
     pub uri: Uri,
     pub method: Method,
     pub header: Header,
+    /// Address of the client, None when unknown such as over a Unix domain socket.
+    pub remote_addr: Option<SocketAddr>,
     // status: StatusCode,
 }
 
@@ -63,6 +69,12 @@ impl Builder {
         }
     }
 
+    pub fn remote_addr(self, addr: SocketAddr) -> Builder {
+        Builder {
+            inner: self.inner.remote_addr(addr)
+        }
+    }
+
     pub fn version(self, ver: Version) -> Builder {
         Builder {
             inner: self.inner.version(ver),
@@ -81,6 +93,7 @@ impl<T> Request<T> {
     pub fn new(body: T) -> Request<T> {
         Request {
             head: Parts::new(),
+            extensions: Extensions::new(),
             body,
         }
     }
@@ -88,12 +101,17 @@ impl<T> Request<T> {
     pub fn from_parts(parts: Parts, body: T) -> Request<T> {
         Request {
             head: parts,
+            extensions: Extensions::new(),
             body,
         }
     }
 
     pub fn set_body(self, data: T) -> Request<T> {
-        Request::from_parts(self.head, data)
+        Request {
+            head: self.head,
+            extensions: self.extensions,
+            body: data,
+        }
     }
 
     pub fn version(&self) -> &Version {
@@ -112,11 +130,53 @@ impl<T> Request<T> {
         &self.head.header
     }
 
+    pub fn header_mut(&mut self) -> &mut Header {
+        &mut self.head.header
+    }
+
+    pub fn remote_addr(&self) -> Option<SocketAddr> {
+        self.head.remote_addr
+    }
+
+    pub fn set_remote_addr(&mut self, addr: Option<SocketAddr>) {
+        self.head.remote_addr = addr;
+    }
+
+    /// Values attached by middleware, such as the session.
+    pub fn extensions(&self) -> &Extensions {
+        &self.extensions
+    }
+
+    pub fn extensions_mut(&mut self) -> &mut Extensions {
+        &mut self.extensions
+    }
+
     pub fn body(&self) -> &T {
         &self.body
     }
 }
 
+impl<T: AsRef<[u8]>> Request<T> {
+    /// The body as a form, `None` unless `Content-Type` is `application/x-www-form-urlencoded`.
+    pub fn form(&self) -> Option<Form> {
+        if !self.header().get("Content-Type").is_some_and(form::is_form) {
+            return None;
+        }
+        Some(Form::parse(&String::from_utf8_lossy(self.body.as_ref())))
+    }
+
+    /// Deserialize a JSON body, which needs a JSON `Content-Type`.
+    /// The error converts into a `415` or `400` response.
+    #[cfg(feature = "json")]
+    pub fn json<D: serde::de::DeserializeOwned>(&self) -> Result<D, crate::http::json::JsonError> {
+        use crate::http::json::{is_json, JsonError};
+        if !self.header().get("Content-Type").is_some_and(is_json) {
+            return Err(JsonError::ContentType);
+        }
+        serde_json::from_slice(self.body.as_ref()).map_err(JsonError::Invalid)
+    }
+}
+
 impl Parts {
     fn new() -> Self {
         Parts {
@@ -124,6 +184,7 @@ impl Parts {
             uri: Uri::default(),
             method: Method::default(),
             header: Header::default(),
+            remote_addr: None,
             // status: StatusCode::default()
         }
     }
@@ -133,7 +194,8 @@ impl Parts {
             version: self.version,
             uri: self.uri,
             method,
-            header: self.header
+            header: self.header,
+            remote_addr: self.remote_addr,
         }
     }
 
@@ -143,7 +205,8 @@ impl Parts {
             version: self.version,
             uri,
             method: self.method,
-            header: self.header
+            header: self.header,
+            remote_addr: self.remote_addr,
         }
     }
 
@@ -152,7 +215,8 @@ impl Parts {
             version: self.version,
             uri: src,
             method: self.method,
-            header: self.header
+            header: self.header,
+            remote_addr: self.remote_addr,
         }
     }
 
@@ -162,6 +226,7 @@ impl Parts {
             uri: self.uri,
             method: self.method,
             header: self.header,
+            remote_addr: self.remote_addr,
         }
     }
 
@@ -170,7 +235,15 @@ impl Parts {
             version: self.version,
             uri: self.uri,
             method: self.method,
-            header
+            header,
+            remote_addr: self.remote_addr,
+        }
+    }
+
+    fn remote_addr(self, addr: SocketAddr) -> Parts {
+        Parts {
+            remote_addr: Some(addr),
+            ..self
         }
     }
 
@@ -181,7 +254,8 @@ impl Parts {
             version: self.version,
             uri: self.uri,
             method: self.method,
-            header
+            header,
+            remote_addr: self.remote_addr,
         }
     }
 }
@@ -206,9 +280,43 @@ mod tests {
         maps.insert("test_header".to_string(), "test_value".to_string());
         wanted.header = super::Header {
             name: vec![super::HeaderName("test_header".to_string())],
+            value: vec![super::HeaderValue("test_value".to_string())],
             map: maps
         };
         let p = p.push_header("test_header", "test_value");
         assert_eq!(p.header.map[&"test_header".to_string()], "test_value".to_string());
     }
-}
\ No newline at end of file
+    #[test]
+    fn test_form() {
+        let parts = super::Request::builder()
+            .method(super::Method::POST)
+            .push_header("Content-Type", "application/x-www-form-urlencoded; charset=UTF-8")
+            .parts();
+        let req = super::Request::from_parts(parts, "a=1&b=two+words&a=".to_string());
+        let form = req.form().unwrap();
+        assert_eq!(form.get_all("a").collect::<Vec<_>>(), ["1", ""]);
+        assert_eq!(form.get("b"), Some("two words"));
+        assert!(super::Request::new("a=1".to_string()).form().is_none());
+    }
+    #[cfg(feature = "json")]
+    #[test]
+    fn test_json() {
+        use crate::http::json::JsonError;
+        #[derive(serde::Deserialize, Debug, PartialEq)]
+        struct Item {
+            name: String,
+            count: u32,
+        }
+        let request = |content_type: &str, body: &str| {
+            let parts = super::Request::builder().push_header("Content-Type", content_type).parts();
+            super::Request::from_parts(parts, body.to_string())
+        };
+        let item: Item = request("application/json", r#"{"name":"pen","count":2}"#).json().unwrap();
+        assert_eq!(item, Item { name: "pen".to_string(), count: 2 });
+        let err = request("application/json", r#"{"name":"pen"}"#).json::<Item>().unwrap_err();
+        assert_eq!(err.status().as_u16(), 400);
+        let err = request("text/plain", r#"{"name":"pen","count":2}"#).json::<Item>().unwrap_err();
+        assert!(matches!(err, JsonError::ContentType));
+        assert!(super::Request::new("{}".to_string()).json::<serde_json::Value>().is_err());
+    }
+}
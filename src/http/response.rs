@@ -62,6 +62,16 @@ impl Builder {
             body,
         }
     }
+
+    /// A response with `value` serialized as its JSON body.
+    #[cfg(feature = "json")]
+    pub fn json<S: serde::Serialize>(self, value: &S) -> Result<Response<Vec<u8>>, serde_json::Error> {
+        let body = serde_json::to_vec(value)?;
+        let mut parts = self.inner;
+        parts.header.set("Content-Type", crate::http::json::CONTENT_TYPE);
+        parts.header.set("Content-Length", &body.len().to_string());
+        Ok(Response { head: parts, body })
+    }
 }
 
 impl Response<()> {
@@ -75,7 +85,7 @@ impl Response<()> {
     // }
 }
 
-impl <T: std::fmt::Display> Response<T> {
+impl<T> Response<T> {
     pub fn new(body: T) -> Response<T> {
         Response {
             head: Parts::new(),
@@ -100,10 +110,20 @@ impl <T: std::fmt::Display> Response<T> {
         &self.head.header
     }
 
+    pub fn header_mut(&mut self) -> &mut Header {
+        &mut self.head.header
+    }
+
     pub fn body(&self) -> &T {
         &self.body
     }
 
+    pub fn into_parts(self) -> (Parts, T) {
+        (self.head, self.body)
+    }
+}
+
+impl <T: std::fmt::Display> Response<T> {
     pub fn format(&self) -> Result<String, ParseError> {
         let buf = format!("{} {} {}\r\n", self.version().format(), self.status().to_string(), self.status().name());
         let headers = self.header().format()?;
@@ -113,6 +133,23 @@ impl <T: std::fmt::Display> Response<T> {
 
 }
 
+impl Response<Vec<u8>> {
+    /// A `200 OK` response with `value` serialized as its JSON body.
+    #[cfg(feature = "json")]
+    pub fn json<S: serde::Serialize>(value: &S) -> Result<Response<Vec<u8>>, serde_json::Error> {
+        Response::builder().json(value)
+    }
+
+    // the body may not be valid utf-8, so it is appended as raw bytes
+    pub fn to_bytes(&self) -> Result<Vec<u8>, ParseError> {
+        let head = format!("{} {} {}\r\n", self.version().format(), self.status().to_string(), self.status().name());
+        let headers = self.header().format()?;
+        let mut buf = format!("{}{}\r\n", head, headers).into_bytes();
+        buf.extend_from_slice(self.body());
+        Ok(buf)
+    }
+}
+
 impl Parts {
     fn new() -> Self {
         Parts {
@@ -170,4 +207,19 @@ mod tests {
         let b = b.format().unwrap();
         assert_eq!(b, "HTTP/1.1 200 OK\r\nContent-Type: text/html\r\nHost: localhost:9999\r\n\r\n".to_string());
     }
-}
\ No newline at end of file
+    #[cfg(feature = "json")]
+    #[test]
+    fn test_json() {
+        #[derive(serde::Serialize)]
+        struct Item {
+            name: &'static str,
+        }
+        let res = super::Response::json(&Item { name: "pen" }).unwrap();
+        assert_eq!(res.status().as_u16(), 200);
+        assert_eq!(res.header().get("Content-Type"), Some("application/json"));
+        assert_eq!(res.body(), br#"{"name":"pen"}"#);
+        let res = super::Response::builder().status(super::StatusCode(201)).json(&[1, 2]).unwrap();
+        assert_eq!(res.status().as_u16(), 201);
+        assert_eq!(res.header().get("Content-Length"), Some("5"));
+    }
+}
@@ -21,14 +21,10 @@ impl Version {
     }
 
     pub fn parse(src: &str) -> Result<Self, InvalidVersion> {
-        println!("[info] varsion = {}", src);
-        let re = Regex::new("HTTP/\\d(\\.\\d)?").unwrap();
-        if !re.is_match(src) {
-            return Err(InvalidVersion::new());
-        }
-        let version_re = Regex::new("\\d(\\.\\d)?").unwrap();
-        match version_re.captures(src) {
-            Some(caps) => match caps.index(0 as usize) {
+        // digits elsewhere in the line, such as in a query, are not the version
+        let re = Regex::new("HTTP/(\\d(\\.\\d)?)").unwrap();
+        match re.captures(src) {
+            Some(caps) => match caps.index(1 as usize) {
                 "0.9" => Ok(Version::HTTP09),
                 "1.0" => Ok(Version::HTTP10),
                 "1.1" => Ok(Version::HTTP11),
@@ -87,4 +83,8 @@ mod tests {
     fn test_parse_version_2() {
         assert_eq!(super::Version::parse("HTTP/2").unwrap(), super::Version::HTTP2);
     }
+    #[test]
+    fn test_parse_version_request_line() {
+        assert_eq!(super::Version::parse("GET /search?q=2 HTTP/1.1").unwrap(), super::Version::HTTP11);
+    }
 }
\ No newline at end of file
@@ -0,0 +1,10 @@
+extern crate regex;
+extern crate chrono;
+extern crate chrono_tz;
+extern crate httpdate;
+
+pub mod uri;
+pub mod http;
+pub mod config;
+pub mod server;
+pub mod client;
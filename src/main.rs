@@ -1,40 +1,377 @@
-
-extern crate regex;
-
 use std::env;
-use crate::server::Server;
-use crate::http::request::Request;
-use crate::http::response::Response;
 use std::path::Path;
-use std::fs::File;
-use std::io::Read;
+use std::process;
+use std::thread;
+use std::time::Duration;
+use signal_hook::consts::{SIGINT, SIGTERM};
+use signal_hook::iterator::Signals;
+use clap::Parser;
+use log::{error, info, warn};
+use rushttp::server::{AccessLog, Balance, BasicAuth, DigestAlgorithm, DigestAuth, HashKey, HealthCheck, Htdigest, Htpasswd, Limits, LogFormat, Proxy, RateLimitKey, RateLimiter, Server, Timeouts, Upstream, VirtualHost};
+#[cfg(feature = "jwt")]
+use rushttp::server::{JwtAuth, JwtKey};
+use rushttp::config::{self, Config};
+use crate::cli::{Cli, Command};
 
+mod cli;
 
-pub mod uri;
-pub mod http;
+fn main() {
+    let cli = Cli::parse();
+    let source = match cli.opts.config_path() {
+        Some(path) => path.display().to_string(),
+        None => "default config".to_string(),
+    };
+    let config = match cli.opts.load() {
+        Ok(c) => c,
+        Err(e) => {
+            eprintln!("[error] {}: {}", source, e);
+            process::exit(1);
+        }
+    };
 
-extern crate chrono;
-extern crate chrono_tz;
-extern crate httpdate;
+    init_logger(&config.log.level);
 
-mod server;
+    match cli.command.unwrap_or(Command::Serve) {
+        Command::CheckConfig => {
+            // the certificate and key are only checked to exist by the validation
+            tls(build_server(&config), &config, &source);
+            println!("{}: configuration OK", source);
+        },
+        Command::Routes => {
+            for route in build_server(&config).routes() {
+                println!("{}", route);
+            }
+        },
+        Command::Serve => {
+            serve(&config, &source);
+        },
+    }
+}
 
-fn main() {
-    let args: Vec<String> = env::args().collect();
-    let port = &args[1];
-    // let mut server = Server::new("/etc/static/assets/html")
-    let host = format!(":{}", port);
-    println!("{}", host);
-    // let mut server = Server::new("src/static/assets/html")
-    let mut server = Server::new("/etc/rushttp/static/assets/html")
-        .bind(&host);
-    server.register("/", "GET", index_handler);
-    server.serve()
-}
-
-fn index_handler(_: Request<String>) -> String {
-    let mut buf = [0u8; 256];
-    let mut file = File::open("/etc/rushttp/static/assets/html/index.html").expect("not found");
-    file.read(&mut buf).expect("failed to read");
-    String::from_utf8(buf.to_vec()).unwrap()
+fn serve(config: &Config, source: &str) {
+    let mut server = tls(build_server(config), config, source)
+        .dual_stack(config.dual_stack);
+    if let Some(target) = &config.log.access_log {
+        // the format is checked by the config validation
+        let format = config.log.format.parse().unwrap_or(LogFormat::Common);
+        let log = match target.as_str() {
+            "stdout" => AccessLog::stdout(format),
+            path => match AccessLog::file(Path::new(path), format) {
+                Ok(log) => log,
+                Err(e) => {
+                    error!("{}: log.access_log: {}: {}", source, path, e);
+                    process::exit(1);
+                }
+            },
+        };
+        server = server.access_log(log);
+    }
+    if let Some(metrics) = &config.metrics {
+        server = server.metrics(&metrics.path);
+        if let Some(address) = &metrics.address {
+            server = match server.metrics_bind(address) {
+                Ok(s) => s,
+                Err(e) => {
+                    error!("{}: metrics.address {}: {}", source, address, e);
+                    process::exit(1);
+                }
+            };
+        }
+    }
+    for listener in config.listeners.iter() {
+        if let Some(path) = &listener.path {
+            server = server.bind_unix(&path.to_string_lossy(), listener.mode);
+            continue;
+        }
+        let address = listener.address.as_deref().unwrap_or_default();
+        server = match server.bind(address) {
+            Ok(s) => s,
+            Err(e) => {
+                error!("{}: listener {}: {}", source, address, e);
+                process::exit(1);
+            }
+        };
+    }
+    // stop accepting and remove the unix socket files on SIGINT or SIGTERM
+    let shutdown = server.shutdown_handle();
+    match Signals::new([SIGINT, SIGTERM]) {
+        Ok(mut signals) => {
+            thread::spawn(move || {
+                if signals.forever().next().is_some() {
+                    info!("shutting down");
+                    shutdown.trigger();
+                }
+            });
+        },
+        Err(e) => warn!("failed to install signal handlers: {}", e),
+    }
+    if let Err(e) = server.serve() {
+        error!("failed to serve: {}", e);
+        process::exit(1);
+    }
+}
+
+fn tls(server: Server, config: &Config, source: &str) -> Server {
+    match &config.tls {
+        Some(tls) => match server.tls(&tls.cert, &tls.key) {
+            Ok(s) => s,
+            Err(e) => {
+                error!("{}: tls: {}", source, e);
+                process::exit(1);
+            }
+        },
+        None => server,
+    }
+}
+
+// diagnostics go to stderr, RUST_LOG takes precedence over the configured level
+fn init_logger(level: &str) {
+    let mut builder = env_logger::Builder::new();
+    builder.parse_filters(level);
+    if let Ok(filters) = env::var("RUST_LOG") {
+        builder.parse_filters(&filters);
+    }
+    builder.init();
+}
+
+fn build_server(config: &Config) -> Server {
+    let root = config.root.to_string_lossy();
+    let timeouts = Timeouts {
+        header_read: Duration::from_secs(config.timeouts.header_read),
+        body_read: Duration::from_secs(config.timeouts.body_read),
+        write: Duration::from_secs(config.timeouts.write),
+        keep_alive: Duration::from_secs(config.timeouts.keep_alive),
+    };
+    let limits = limits(&config.limits);
+    let mut server = Server::new(&root)
+        .threads(config.threads)
+        .timeouts(timeouts)
+        .limits(limits);
+    if let Some(max) = config.limits.max_connections {
+        server = server.max_connections(max);
+    }
+    if let Some(max) = config.limits.max_connections_per_ip {
+        server = server.max_connections_per_ip(max);
+    }
+    // shared by every virtual host, so that a client has one bucket
+    let rate_limiter = config.rate_limit.as_ref().map(|rate_limit| {
+        let key = match &rate_limit.header {
+            Some(header) => RateLimitKey::Header(header.clone()),
+            None => RateLimitKey::ClientIp,
+        };
+        RateLimiter::new(rate_limit.rate, rate_limit.burst, key)
+    });
+    if let Some(limiter) = &rate_limiter {
+        server.middleware(limiter.clone());
+    }
+    let auth = basic_auth(&config.auth);
+    if let Some(auth) = &auth {
+        server.middleware(auth.clone());
+    }
+    let digest = digest_auth(&config.digest_auth);
+    if let Some(digest) = &digest {
+        server.middleware(digest.clone());
+    }
+    #[cfg(feature = "jwt")]
+    let jwt = jwt_auth(&config.jwt_auth);
+    #[cfg(feature = "jwt")]
+    for auth in jwt.iter() {
+        server.middleware(auth.clone());
+    }
+    #[cfg(not(feature = "jwt"))]
+    if !config.jwt_auth.is_empty() {
+        error!("jwt_auth: rushttp is built without the jwt feature");
+        process::exit(1);
+    }
+    for route in config.limits.routes.iter() {
+        server.limit(&route.path, route_limits(route, &limits));
+    }
+    for mount in config.mounts.iter() {
+        server.mount(&mount.path, &mount.dir.to_string_lossy());
+    }
+    server.mount("/", &root);
+    // routes naming the same group share its backends and their health
+    let upstreams: Vec<Upstream> = config.upstreams.iter().map(upstream).collect();
+    for route in config.proxies.iter() {
+        server.proxy(&route.path, proxy(route, &upstreams));
+    }
+    for (name, value) in config.headers.iter() {
+        server.header(name, value);
+    }
+    for vhost in config.vhosts.iter() {
+        let mut vhost = build_vhost(vhost);
+        for route in config.limits.routes.iter() {
+            vhost.limit(&route.path, route_limits(route, &limits));
+        }
+        if let Some(limiter) = &rate_limiter {
+            vhost.middleware(limiter.clone());
+        }
+        if let Some(auth) = &auth {
+            vhost.middleware(auth.clone());
+        }
+        if let Some(digest) = &digest {
+            vhost.middleware(digest.clone());
+        }
+        #[cfg(feature = "jwt")]
+        for auth in jwt.iter() {
+            vhost.middleware(auth.clone());
+        }
+        server.vhost(vhost);
+    }
+    server
+}
+
+// routes sharing an htpasswd file share its users
+fn basic_auth(config: &[config::Auth]) -> Option<BasicAuth> {
+    if config.is_empty() {
+        return None;
+    }
+    let mut files: Vec<(&Path, Htpasswd)> = Vec::new();
+    let mut auth = BasicAuth::new();
+    for route in config.iter() {
+        let htpasswd = match files.iter().find(|(path, _)| *path == route.htpasswd) {
+            Some((_, htpasswd)) => htpasswd.clone(),
+            None => match Htpasswd::open(&route.htpasswd) {
+                Ok(htpasswd) => {
+                    files.push((&route.htpasswd, htpasswd.clone()));
+                    htpasswd
+                },
+                Err(e) => {
+                    error!("auth {}: {}: {}", route.path, route.htpasswd.display(), e);
+                    process::exit(1);
+                }
+            },
+        };
+        auth = auth.route(&route.path, &route.realm, htpasswd);
+    }
+    Some(auth)
+}
+
+// htdigest files only hold MD5 hashes, so no SHA-256 challenge is offered
+fn digest_auth(config: &[config::DigestAuth]) -> Option<DigestAuth> {
+    if config.is_empty() {
+        return None;
+    }
+    let mut files: Vec<(&Path, Htdigest)> = Vec::new();
+    let mut auth = DigestAuth::new().algorithms(&[DigestAlgorithm::Md5]);
+    for route in config.iter() {
+        let htdigest = match files.iter().find(|(path, _)| *path == route.htdigest) {
+            Some((_, htdigest)) => htdigest.clone(),
+            None => match Htdigest::open(&route.htdigest) {
+                Ok(htdigest) => {
+                    files.push((&route.htdigest, htdigest.clone()));
+                    htdigest
+                },
+                Err(e) => {
+                    error!("digest_auth {}: {}: {}", route.path, route.htdigest.display(), e);
+                    process::exit(1);
+                }
+            },
+        };
+        auth = auth.route(&route.path, &route.realm, htdigest);
+    }
+    Some(auth)
+}
+
+// one middleware per route, as each has its own keys and claims to check
+#[cfg(feature = "jwt")]
+fn jwt_auth(config: &[config::JwtAuth]) -> Vec<JwtAuth> {
+    config.iter().map(|route| {
+        let mut keys: Vec<JwtKey> = route.secret.iter().map(|secret| JwtKey::hs256(secret.as_bytes())).collect();
+        let files = route.public_keys.iter()
+            .map(|path| (path, JwtKey::open_pem(path).map(|key| vec![key])))
+            .chain(route.jwks.iter().map(|path| (path, JwtKey::open_jwks(path))));
+        for (path, loaded) in files {
+            match loaded {
+                Ok(loaded) => keys.extend(loaded),
+                Err(e) => {
+                    error!("jwt_auth {}: {}: {}", route.path, path.display(), e);
+                    process::exit(1);
+                }
+            }
+        }
+        let mut auth = JwtAuth::new()
+            .route(&route.path, &route.realm)
+            .keys(keys)
+            .leeway(Duration::from_secs(route.leeway));
+        if let Some(issuer) = &route.issuer {
+            auth = auth.issuer(issuer);
+        }
+        if let Some(audience) = &route.audience {
+            auth = auth.audience(audience);
+        }
+        auth
+    }).collect()
+}
+
+fn limits(config: &config::Limits) -> Limits {
+    Limits {
+        max_request_line: config.max_request_line,
+        max_headers: config.max_headers,
+        max_header_bytes: config.max_header_bytes,
+        max_body: config.max_body,
+    }
+}
+
+fn route_limits(config: &config::RouteLimits, server: &Limits) -> Limits {
+    Limits {
+        max_request_line: config.max_request_line.unwrap_or(server.max_request_line),
+        max_headers: config.max_headers.unwrap_or(server.max_headers),
+        max_header_bytes: config.max_header_bytes.unwrap_or(server.max_header_bytes),
+        max_body: config.max_body.unwrap_or(server.max_body),
+    }
+}
+
+fn proxy(config: &config::ProxyRoute, upstreams: &[Upstream]) -> Proxy {
+    let mut proxy = match upstreams.iter().find(|u| u.name() == config.upstream) {
+        Some(upstream) => Proxy::group(upstream.clone()),
+        None => Proxy::new(&config.upstream),
+    };
+    if let Some(secs) = config.connect_timeout {
+        proxy = proxy.connect_timeout(Duration::from_secs(secs));
+    }
+    if let Some(secs) = config.read_timeout {
+        proxy = proxy.read_timeout(Duration::from_secs(secs));
+    }
+    proxy
+}
+
+fn upstream(config: &config::Upstream) -> Upstream {
+    let balance = match config.balance.as_str() {
+        "least_connections" => Balance::LeastConnections,
+        "weighted" => Balance::Weighted,
+        "hash" => match &config.hash_header {
+            Some(header) => Balance::Hash(HashKey::Header(header.clone())),
+            None => Balance::Hash(HashKey::ClientIp),
+        },
+        _ => Balance::RoundRobin,
+    };
+    let mut upstream = config.servers.iter()
+        .fold(Upstream::new(&config.name, balance), |upstream, server| upstream.server(&server.address, server.weight))
+        .keepalive(config.keepalive)
+        .max_fails(config.max_fails, Duration::from_secs(config.fail_timeout));
+    if let Some(check) = &config.health_check {
+        upstream = upstream.health_check(HealthCheck {
+            path: check.path.clone(),
+            interval: Duration::from_secs(check.interval),
+            timeout: Duration::from_secs(check.timeout),
+            passes: check.passes,
+            fails: check.fails,
+        });
+    }
+    upstream
+}
+
+fn build_vhost(config: &config::VirtualHost) -> VirtualHost {
+    let root = config.root.to_string_lossy();
+    let mut vhost = config.names.iter()
+        .fold(VirtualHost::new(&root), |vhost, name| vhost.name(name));
+    for mount in config.mounts.iter() {
+        vhost.mount(&mount.path, &mount.dir.to_string_lossy());
+    }
+    vhost.mount("/", &root);
+    for (name, value) in config.headers.iter() {
+        vhost.header(name, value);
+    }
+    vhost
 }
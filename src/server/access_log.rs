@@ -0,0 +1,192 @@
+use std::fmt;
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::net::SocketAddr;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use chrono::{DateTime, Local};
+
+/// Line format of the access log.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// NCSA Common Log Format.
+    Common,
+    /// NCSA Combined Log Format, Common plus referer and user agent.
+    Combined,
+    /// One JSON object per line, with the request duration.
+    Json,
+}
+
+/// Writes one line per request to stdout or a file.
+#[derive(Clone)]
+pub struct AccessLog {
+    format: Format,
+    out: Arc<Mutex<Box<dyn Write + Send>>>,
+}
+
+/// What is logged about a request.
+#[derive(Debug, Clone)]
+pub struct Entry<'a> {
+    pub client: Option<SocketAddr>,
+    pub time: DateTime<Local>,
+    pub method: &'a str,
+    pub target: &'a str,
+    pub version: &'a str,
+    pub status: u16,
+    /// Size of the response body.
+    pub bytes: usize,
+    pub duration: Duration,
+    pub referer: Option<&'a str>,
+    pub user_agent: Option<&'a str>,
+}
+
+impl AccessLog {
+    pub fn new<W: Write + Send + 'static>(out: W, format: Format) -> Self {
+        AccessLog {
+            format,
+            out: Arc::new(Mutex::new(Box::new(out))),
+        }
+    }
+
+    pub fn stdout(format: Format) -> Self {
+        AccessLog::new(io::stdout(), format)
+    }
+
+    /// Append to the file at `path`, creating it when missing.
+    pub fn file(path: &Path, format: Format) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(AccessLog::new(file, format))
+    }
+
+    pub fn log(&self, entry: &Entry) {
+        let line = match self.format {
+            Format::Common => common(entry),
+            Format::Combined => combined(entry),
+            Format::Json => json(entry),
+        };
+        if let Ok(mut out) = self.out.lock() {
+            // a failing log must not fail the request
+            let _ = out.write_all(line.as_bytes());
+            let _ = out.write_all(b"\n");
+            let _ = out.flush();
+        }
+    }
+}
+
+impl fmt::Debug for AccessLog {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("AccessLog")
+            .field("format", &self.format)
+            .finish()
+    }
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Format, String> {
+        match s {
+            "common" => Ok(Format::Common),
+            "combined" => Ok(Format::Combined),
+            "json" => Ok(Format::Json),
+            _ => Err(format!("unknown access log format `{}`", s)),
+        }
+    }
+}
+
+// host ident authuser [date] "request line" status bytes
+fn common(entry: &Entry) -> String {
+    let client = entry.client.map(|c| c.ip().to_string()).unwrap_or_else(|| "-".to_string());
+    let bytes = if entry.bytes == 0 { "-".to_string() } else { entry.bytes.to_string() };
+    format!("{} - - [{}] \"{} {} {}\" {} {}",
+            client,
+            entry.time.format("%d/%b/%Y:%H:%M:%S %z"),
+            entry.method,
+            escape(entry.target),
+            entry.version,
+            entry.status,
+            bytes)
+}
+
+fn combined(entry: &Entry) -> String {
+    format!("{} \"{}\" \"{}\"",
+            common(entry),
+            escape(entry.referer.unwrap_or("-")),
+            escape(entry.user_agent.unwrap_or("-")))
+}
+
+fn json(entry: &Entry) -> String {
+    let string = |s: Option<&str>| match s {
+        Some(s) => format!("\"{}\"", escape(s)),
+        None => "null".to_string(),
+    };
+    let client = entry.client.map(|c| c.ip().to_string());
+    format!("{{\"time\":\"{}\",\"client\":{},\"method\":{},\"target\":{},\"protocol\":{},\"status\":{},\"bytes\":{},\"duration_ms\":{:.3},\"referer\":{},\"user_agent\":{}}}",
+            entry.time.to_rfc3339(),
+            string(client.as_deref()),
+            string(Some(entry.method)),
+            string(Some(entry.target)),
+            string(Some(entry.version)),
+            entry.status,
+            entry.bytes,
+            entry.duration.as_secs_f64() * 1000.0,
+            string(entry.referer),
+            string(entry.user_agent))
+}
+
+// escape quotes, backslashes and control characters so that a line can not be forged
+fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            c if c.is_control() => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use chrono::{Local, TimeZone};
+
+    fn entry() -> super::Entry<'static> {
+        super::Entry {
+            client: Some("127.0.0.1:53000".parse().unwrap()),
+            time: Local.with_ymd_and_hms(2020, 10, 10, 13, 55, 36).unwrap(),
+            method: "GET",
+            target: "/index.html?q=\"x\"",
+            version: "HTTP/1.1",
+            status: 200,
+            bytes: 2326,
+            duration: Duration::from_micros(1500),
+            referer: None,
+            user_agent: Some("curl/7.54.0"),
+        }
+    }
+
+    #[test]
+    fn test_common() {
+        let line = super::common(&entry());
+        assert!(line.starts_with("127.0.0.1 - - [10/Oct/2020:13:55:36 "));
+        assert!(line.ends_with("] \"GET /index.html?q=\\\"x\\\" HTTP/1.1\" 200 2326"));
+    }
+    #[test]
+    fn test_combined() {
+        let line = super::combined(&entry());
+        assert!(line.ends_with(" 200 2326 \"-\" \"curl/7.54.0\""));
+    }
+    #[test]
+    fn test_json() {
+        let line = super::json(&entry());
+        assert!(line.contains("\"client\":\"127.0.0.1\""));
+        assert!(line.contains("\"duration_ms\":1.500"));
+        assert!(line.contains("\"referer\":null"));
+        assert!(line.ends_with("\"user_agent\":\"curl/7.54.0\"}"));
+    }
+}
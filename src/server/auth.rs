@@ -0,0 +1,171 @@
+use std::fmt;
+use std::sync::Arc;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use crate::http::request::Request;
+use crate::http::response::Response;
+use crate::server::middleware::{Middleware, Next};
+use crate::server::resource::under_prefix;
+use crate::server::response::response;
+
+/// Checks a user name and password, such as an `Htpasswd` or a closure.
+pub trait Credentials: Send + Sync {
+    fn verify(&self, user: &str, password: &str) -> bool;
+}
+
+impl<F> Credentials for F
+    where F: Fn(&str, &str) -> bool + Send + Sync
+{
+    fn verify(&self, user: &str, password: &str) -> bool {
+        self(user, password)
+    }
+}
+
+/// The user an authentication middleware let in, attached to the request for handlers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuthUser(pub String);
+
+/// HTTP Basic authentication, used as a middleware.
+/// Each route is a path prefix with its realm and users, the longest matching prefix applies
+/// and paths outside every route pass unchecked.
+/// Missing or wrong credentials get `401 Unauthorized` with a `WWW-Authenticate` challenge.
+#[derive(Clone, Default)]
+pub struct BasicAuth {
+    routes: Vec<Route>,
+}
+
+#[derive(Clone)]
+struct Route {
+    prefix: String,
+    realm: String,
+    credentials: Arc<dyn Credentials>,
+}
+
+impl BasicAuth {
+    pub fn new() -> Self {
+        BasicAuth::default()
+    }
+
+    /// Require a user of `credentials` for paths under `prefix`.
+    pub fn route<C: Credentials + 'static>(self, prefix: &str, realm: &str, credentials: C) -> Self {
+        let mut routes = self.routes;
+        routes.push(Route {
+            prefix: prefix.to_string(),
+            realm: realm.to_string(),
+            credentials: Arc::new(credentials),
+        });
+        routes.sort_by_key(|route| std::cmp::Reverse(route.prefix.len()));
+        BasicAuth { routes }
+    }
+}
+
+impl Middleware for BasicAuth {
+    fn handle(&self, mut req: Request<String>, next: Next<'_>) -> Response<Vec<u8>> {
+        let route = match self.routes.iter().find(|route| under_prefix(&route.prefix, req.uri().path())) {
+            Some(route) => route,
+            None => return next.run(req),
+        };
+        let user = req.header().get("Authorization")
+            .and_then(basic_credentials)
+            .filter(|(user, password)| route.credentials.verify(user, password))
+            .map(|(user, _)| user);
+        match user {
+            Some(user) => {
+                req.extensions_mut().insert(AuthUser(user));
+                next.run(req)
+            },
+            None => {
+                let challenge = format!("Basic realm={}, charset=\"UTF-8\"", quote(&route.realm));
+                unauthorized(&[challenge])
+            },
+        }
+    }
+}
+
+impl fmt::Debug for BasicAuth {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let routes: Vec<(&str, &str)> = self.routes.iter()
+            .map(|route| (route.prefix.as_str(), route.realm.as_str()))
+            .collect();
+        f.debug_struct("BasicAuth")
+            .field("routes", &routes)
+            .finish()
+    }
+}
+
+// the user and password of `Basic <base64 of user:password>`
+fn basic_credentials(authorization: &str) -> Option<(String, String)> {
+    let (scheme, token) = authorization.trim().split_once(' ')?;
+    if !scheme.eq_ignore_ascii_case("Basic") {
+        return None;
+    }
+    let decoded = String::from_utf8(STANDARD.decode(token.trim()).ok()?).ok()?;
+    let (user, password) = decoded.split_once(':')?;
+    Some((user.to_string(), password.to_string()))
+}
+
+/// A `401 Unauthorized` response with a `WWW-Authenticate` field for each challenge.
+pub(crate) fn unauthorized(challenges: &[String]) -> Response<Vec<u8>> {
+    let extra: Vec<(String, String)> = challenges.iter()
+        .map(|challenge| ("WWW-Authenticate".to_string(), challenge.clone()))
+        .collect();
+    response(401, Some("Unauthorized"), &extra)
+        .unwrap_or_else(|_| Response::new(Vec::new()))
+}
+
+/// A quoted-string for an auth parameter.
+pub(crate) fn quote(value: &str) -> String {
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+    for c in value.chars() {
+        if c == '"' || c == '\\' {
+            quoted.push('\\');
+        }
+        quoted.push(c);
+    }
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use crate::http::request::Request;
+    use crate::http::response::Response;
+    use crate::server::middleware::{Middleware, Next};
+    use super::{AuthUser, BasicAuth};
+
+    fn send(auth: &BasicAuth, path: &str, authorization: Option<&str>) -> Response<Vec<u8>> {
+        let mut builder = Request::builder().uri_from_str(path);
+        if let Some(authorization) = authorization {
+            builder = builder.push_header("Authorization", authorization);
+        }
+        let chain: Vec<Arc<dyn Middleware>> = vec![Arc::new(auth.clone())];
+        let endpoint = |req: Request<String>| {
+            let user = req.extensions().get::<AuthUser>().map(|user| user.0.clone()).unwrap_or_default();
+            Response::new(user.into_bytes())
+        };
+        Next::new(&chain, &endpoint).run(Request::from_parts(builder.parts(), String::new()))
+    }
+
+    #[test]
+    fn test_basic_auth() {
+        let auth = BasicAuth::new()
+            .route("/admin", "Admin \"area\"", |user: &str, password: &str| user == "root" && password == "s3:cret")
+            .route("/admin/public", "Public", |_: &str, _: &str| true);
+        assert_eq!(send(&auth, "/", None).body(), b"");
+        let res = send(&auth, "/admin/users", None);
+        assert_eq!(res.status().as_u16(), 401);
+        assert_eq!(res.header().get("WWW-Authenticate"), Some("Basic realm=\"Admin \\\"area\\\"\", charset=\"UTF-8\""));
+        // root:s3:cret
+        let res = send(&auth, "/admin", Some("basic cm9vdDpzMzpjcmV0"));
+        assert_eq!(res.status().as_u16(), 200);
+        assert_eq!(res.body(), b"root");
+        assert_eq!(send(&auth, "/admin", Some("Basic cm9vdDp3cm9uZw==")).status().as_u16(), 401);
+        assert_eq!(send(&auth, "/admin", Some("Bearer cm9vdDpzMzpjcmV0")).status().as_u16(), 401);
+        assert_eq!(send(&auth, "/admin", Some("Basic !!")).status().as_u16(), 401);
+        // the longer prefix has its own realm
+        assert_eq!(send(&auth, "/admin/public/a", Some("Basic dXNlcjpwdw==")).body(), b"user");
+        assert_eq!(send(&auth, "/administrator", None).status().as_u16(), 200);
+    }
+}
@@ -0,0 +1,428 @@
+use std::cell::{Cell, RefCell};
+use std::io::Write;
+use std::net::Shutdown;
+use std::time::Instant;
+use chrono::{DateTime, Local};
+use log::debug;
+use crate::http::method::Method;
+use crate::http::multipart::{self, MultipartParser};
+use crate::http::parser::Parser;
+use crate::http::request::Request;
+use crate::http::response::Response;
+use crate::http::version::Version;
+use crate::server::access_log::Entry;
+use crate::server::context::Context;
+use crate::server::error::Error;
+use crate::server::listener::Stream;
+use crate::server::metrics::{Metrics, Sample};
+use crate::server::proxy::{Exchange, Failure, Forwarded, Proxy};
+use crate::server::reader::{Framing, ReadError, Reader};
+use crate::server::response::{build, response};
+use crate::server::vhost::VirtualHost;
+
+// label of requests which match no route, so that unknown paths do not grow the metrics
+const UNMATCHED: &str = "unmatched";
+
+/// Answer requests on `stream` until the client closes it, asks to close it,
+/// or stays idle longer than the keep-alive timeout.
+/// Connections accepted on an admin listener only get the metrics.
+pub fn handle(stream: Stream, ctx: &Context, admin: bool) -> Result<(), Error> {
+    let mut stream = match &ctx.tls {
+        Some(config) if !admin => stream.tls(config, ctx.timeouts.header_read)?,
+        _ => stream,
+    };
+    stream.set_write_timeout(Some(ctx.timeouts.write))?;
+    let mut reader = Reader::new();
+    let mut first = true;
+    let res = loop {
+        if ctx.shutdown.is_triggered() {
+            break Ok(());
+        }
+        match serve(&mut stream, &mut reader, ctx, admin, first) {
+            Ok(true) => first = false,
+            Ok(false) => break Ok(()),
+            Err(e) => break Err(e),
+        }
+    };
+    let _ = stream.shutdown(Shutdown::Both);
+    res
+}
+
+// read and answer one request, returning whether the connection stays open
+fn serve(stream: &mut Stream, reader: &mut Reader, ctx: &Context, admin: bool, first: bool) -> Result<bool, Error> {
+    // a new connection gets the header timeout to send its first request
+    let idle = if first { ctx.timeouts.header_read } else { ctx.timeouts.keep_alive };
+    let head = match reader.read_head(stream, idle, ctx.timeouts.header_read, &ctx.limits) {
+        Ok(head) => head,
+        Err(ReadError::Closed) => return Ok(false),
+        Err(ReadError::Idle) if !first => return Ok(false),
+        Err(ReadError::Idle) | Err(ReadError::Timeout) => {
+            debug!("timed out reading a request from {}", stream);
+            write_error(stream, 408, "Request Timeout")?;
+            return Ok(false);
+        },
+        Err(ReadError::Limit(exceeded)) => {
+            let (status, reason) = exceeded.status();
+            debug!("request from {} is too large: {:?}", stream, exceeded);
+            write_error(stream, status, reason)?;
+            return Ok(false);
+        },
+        Err(ReadError::Malformed) => {
+            write_error(stream, 400, "Bad Request")?;
+            return Ok(false);
+        },
+        Err(ReadError::Io(e)) => return Err(Error::from(e)),
+    };
+    let time = Local::now();
+    let start = Instant::now();
+    let mut request = match Parser::new().parse_request(&head) {
+        Ok(req) => req,
+        Err(e) => {
+            debug!("bad request from {}: {:?}", stream, e);
+            write_error(stream, 400, "Bad Request")?;
+            return Ok(false);
+        },
+    };
+    request.set_remote_addr(stream.peer_addr());
+    // a chunked body is only relayed to a proxy, other codings are not supported
+    let chunked = match request.header().get("Transfer-Encoding") {
+        None => false,
+        Some(te) if te.trim().eq_ignore_ascii_case("chunked") => true,
+        Some(_) => {
+            write_error(stream, 501, "Not Implemented")?;
+            return Ok(false);
+        },
+    };
+    // both framings at once is a request smuggling attempt
+    if chunked && request.header().get("Content-Length").is_some() {
+        write_error(stream, 400, "Bad Request")?;
+        return Ok(false);
+    }
+    let len = match content_length(&request) {
+        Some(len) => len,
+        None => {
+            write_error(stream, 400, "Bad Request")?;
+            return Ok(false);
+        },
+    };
+    let metrics = match &ctx.metrics {
+        Some(metrics) if admin || (!ctx.admin_only && request.uri().path() == ctx.metrics_path) => Some(metrics),
+        _ => None,
+    };
+    let vhost = ctx.hosts.select(&request);
+    // a route may have its own limits, checked before the body is read
+    let limits = match metrics {
+        Some(_) => &ctx.limits,
+        None => vhost.limits(request.uri().path()).unwrap_or(&ctx.limits),
+    };
+    if let Err(exceeded) = limits.check_head(&head).and_then(|_| limits.check_body(len)) {
+        let (status, reason) = exceeded.status();
+        debug!("request from {} is too large: {:?}", stream, exceeded);
+        write_error(stream, status, reason)?;
+        return Ok(false);
+    }
+    if metrics.is_none() {
+        if let Some((prefix, proxy)) = vhost.proxy_for(&request) {
+            let keep_alive = !ctx.shutdown.is_triggered() && keep_alive(&request);
+            let summary = Summary::new(&request, prefix, time, start, head.len() + len);
+            let ex = Exchange {
+                client: stream,
+                reader,
+                head: &head,
+                body: match len {
+                    _ if chunked => Framing::Chunked,
+                    0 => Framing::Empty,
+                    len => Framing::Length(len),
+                },
+                limits: *limits,
+                body_timeout: ctx.timeouts.body_read,
+                keep_alive,
+                headers: vhost.headers(),
+            };
+            return forward(ctx, vhost, proxy, request, ex, &summary);
+        }
+    }
+    if chunked {
+        write_error(stream, 501, "Not Implemented")?;
+        return Ok(false);
+    }
+    let upload = match metrics {
+        Some(_) => None,
+        None => vhost.multipart_limits(request.uri().path())
+            .zip(request.header().get("Content-Type").and_then(multipart::boundary)),
+    };
+    let body = match upload {
+        Some((limits, boundary)) => {
+            // the parts are stored as they arrive instead of the body
+            let mut parser = MultipartParser::new(&boundary, limits.clone());
+            let copied = reader.copy(stream, &mut parser, Some(len), ctx.timeouts.body_read);
+            // a write the parser failed is answered with its error
+            if copied.is_err() && !parser.failed() {
+                copied.map(|_| Vec::new())
+            } else {
+                match parser.finish() {
+                    Ok(parts) => {
+                        request.extensions_mut().insert(parts);
+                        Ok(Vec::new())
+                    },
+                    Err(e) => {
+                        debug!("rejected a multipart body from {}: {}", stream, e);
+                        let (status, reason) = e.status();
+                        write_error(stream, status, reason)?;
+                        // the rest of the body may be unread
+                        return Ok(false);
+                    },
+                }
+            }
+        },
+        None => reader.read_body(stream, len, ctx.timeouts.body_read),
+    };
+    let body = match body {
+        Ok(body) => body,
+        Err(ReadError::Timeout) => {
+            debug!("timed out reading a request body from {}", stream);
+            write_error(stream, 408, "Request Timeout")?;
+            return Ok(false);
+        },
+        Err(ReadError::Io(e)) => return Err(Error::from(e)),
+        Err(_) => return Ok(false),
+    };
+    let request_bytes = head.len() + len;
+    let request = match String::from_utf8(body) {
+        Ok(body) => request.set_body(body),
+        Err(_) => {
+            write_error(stream, 400, "Bad Request")?;
+            return Ok(false);
+        },
+    };
+    let keep_alive = !ctx.shutdown.is_triggered() && keep_alive(&request);
+    let (summary, res) = match metrics {
+        Some(metrics) => {
+            let summary = Summary::new(&request, &ctx.metrics_path, time, start, request_bytes);
+            (summary, metrics_response(metrics, &request, &ctx.metrics_path))
+        },
+        None => {
+            let route = vhost.route(&request).unwrap_or_else(|| UNMATCHED.to_string());
+            // keep what the access log needs, the request is moved into the handler
+            let summary = Summary::new(&request, &route, time, start, request_bytes);
+            (summary, vhost.dispatch(request))
+        },
+    };
+    let (status, bytes, body_bytes) = write_response(stream, res, &summary, keep_alive)?;
+    record(ctx, stream, &summary, status, bytes, body_bytes);
+    Ok(keep_alive)
+}
+
+// what is recorded of a request once it is answered
+struct Summary {
+    route: String,
+    method: String,
+    target: String,
+    version: String,
+    referer: Option<String>,
+    user_agent: Option<String>,
+    http10: bool,
+    time: DateTime<Local>,
+    start: Instant,
+    request_bytes: usize,
+}
+
+impl Summary {
+    fn new(req: &Request<String>, route: &str, time: DateTime<Local>, start: Instant, request_bytes: usize) -> Self {
+        Summary {
+            route: route.to_string(),
+            method: req.method().as_str().to_string(),
+            target: req.uri().as_str().to_string(),
+            version: req.version().format().to_string(),
+            referer: req.header().get("Referer").map(|v| v.to_string()),
+            user_agent: req.header().get("User-Agent").map(|v| v.to_string()),
+            http10: *req.version() == Version::HTTP10,
+            time,
+            start,
+            request_bytes,
+        }
+    }
+}
+
+// send a response, returning its status and the bytes sent in all and of the body
+fn write_response(stream: &mut Stream, mut res: Response<Vec<u8>>, summary: &Summary, keep_alive: bool) -> Result<(u16, usize, usize), Error> {
+    if !keep_alive {
+        res.header_mut().set("Connection", "close");
+    } else if summary.http10 {
+        res.header_mut().set("Connection", "keep-alive");
+    }
+    let mut buf = res.to_bytes().map_err(|e| Error::from(crate::http::error::Error::from(e)))?;
+    let mut body_bytes = res.body().len();
+    if summary.method == Method::HEAD.as_str() {
+        buf.truncate(buf.len() - body_bytes);
+        body_bytes = 0;
+    }
+    stream.write_all(&buf)?;
+    Ok((res.status().as_u16(), buf.len(), body_bytes))
+}
+
+// forward a request to a proxy route, its body is streamed from the client as the upstream takes it.
+// The middleware of the host may answer the request itself, but does not see the upstream response.
+fn forward(ctx: &Context, vhost: &VirtualHost, proxy: &Proxy, request: Request<String>, ex: Exchange, summary: &Summary) -> Result<bool, Error> {
+    let body_empty = ex.body == Framing::Empty;
+    let keep_alive = ex.keep_alive;
+    let ex = RefCell::new(ex);
+    let forwarded: Cell<Option<Forwarded>> = Cell::new(None);
+    let endpoint = |req: Request<String>| {
+        let (status, reason) = match proxy.forward(&req, &mut ex.borrow_mut()) {
+            Ok(done) => {
+                forwarded.set(Some(done));
+                return Response::new(Vec::new());
+            },
+            Err(Failure::BadGateway(e)) => {
+                debug!("failed to forward to {}: {}", proxy.upstream().name(), e);
+                (502, "Bad Gateway")
+            },
+            Err(Failure::GatewayTimeout) => {
+                debug!("timed out forwarding to {}", proxy.upstream().name());
+                (504, "Gateway Timeout")
+            },
+            Err(Failure::ClientTimeout) => (408, "Request Timeout"),
+            Err(Failure::TooLarge) => (413, "Payload Too Large"),
+            Err(Failure::BadRequest) => (400, "Bad Request"),
+        };
+        response(status, Some(reason), vhost.headers()).unwrap_or_else(|_| Response::new(Vec::new()))
+    };
+    let res = vhost.dispatch_with(request, &endpoint);
+    let stream = ex.into_inner().client;
+    let (keep_alive, status, bytes, body_bytes) = match forwarded.get() {
+        Some(done) => (keep_alive && !done.close, done.status, done.bytes, done.body_bytes),
+        None => {
+            // the request body is left unread, or only partly forwarded
+            let keep_alive = keep_alive && body_empty;
+            let (status, bytes, body_bytes) = write_response(stream, res, summary, keep_alive)?;
+            (keep_alive, status, bytes, body_bytes)
+        },
+    };
+    record(ctx, stream, summary, status, bytes, body_bytes);
+    Ok(keep_alive)
+}
+
+// add an answered request to the metrics and the access log
+fn record(ctx: &Context, stream: &Stream, summary: &Summary, status: u16, bytes: usize, body_bytes: usize) {
+    if let Some(metrics) = &ctx.metrics {
+        metrics.observe(&Sample {
+            route: &summary.route,
+            method: &summary.method,
+            status,
+            duration: summary.start.elapsed(),
+            request_bytes: summary.request_bytes,
+            response_bytes: bytes,
+        });
+    }
+    if let Some(log) = &ctx.access_log {
+        log.log(&Entry {
+            client: stream.peer_addr(),
+            time: summary.time,
+            method: &summary.method,
+            target: &summary.target,
+            version: &summary.version,
+            status,
+            bytes: body_bytes,
+            duration: summary.start.elapsed(),
+            referer: summary.referer.as_deref(),
+            user_agent: summary.user_agent.as_deref(),
+        });
+    }
+}
+
+// the body length from every Content-Length value, None unless they are all the same run of digits.
+// A proxy forwards this one value, so the upstream can not frame the body another way.
+fn content_length(req: &Request<String>) -> Option<usize> {
+    let mut len = None;
+    for value in req.header().get_all("Content-Length").flat_map(|value| value.split(',')) {
+        let value = value.trim();
+        if value.is_empty() || !value.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        let value = value.parse::<usize>().ok()?;
+        if len.is_some_and(|len| len != value) {
+            return None;
+        }
+        len = Some(value);
+    }
+    Some(len.unwrap_or(0))
+}
+
+// HTTP/1.1 keeps the connection unless asked to close it, HTTP/1.0 only when asked to keep it
+fn keep_alive(req: &Request<String>) -> bool {
+    let connection = req.header().get("Connection").unwrap_or_default();
+    let has = |token: &str| connection.split(',').any(|t| t.trim().eq_ignore_ascii_case(token));
+    match req.version() {
+        Version::HTTP11 => !has("close"),
+        Version::HTTP10 => has("keep-alive"),
+        _ => false,
+    }
+}
+
+/// Turn away a connection over the connection caps. This runs on the accept thread, so the 503
+/// is only sent if the socket takes it at once, and not at all to a client expecting a TLS handshake.
+pub fn reject(mut stream: Stream, tls: bool) {
+    if !tls && stream.set_nonblocking(true).is_ok() {
+        let _ = write_error(&mut stream, 503, "Service Unavailable");
+    }
+    let _ = stream.shutdown(Shutdown::Both);
+}
+
+// answer a request which could not be read, the connection is closed afterwards
+fn write_error(stream: &mut Stream, status: u16, reason: &str) -> Result<(), Error> {
+    let extra = [("Connection".to_string(), "close".to_string())];
+    let res = response(status, Some(reason), &extra)?;
+    let buf = res.to_bytes().map_err(|e| Error::from(crate::http::error::Error::from(e)))?;
+    stream.write_all(&buf)?;
+    Ok(())
+}
+
+fn metrics_response(metrics: &Metrics, req: &Request<String>, path: &str) -> Response<Vec<u8>> {
+    let res = if req.uri().path() != path {
+        response(404, Some("Not Found"), &[])
+    } else if *req.method() != Method::GET && *req.method() != Method::HEAD {
+        response(405, Some("Method Not Allowed"), &[])
+    } else {
+        build(200, "text/plain; version=0.0.4; charset=utf-8", metrics.render().into_bytes(), &[])
+    };
+    res.unwrap_or_else(|_| Response::new(Vec::new()))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::http::request::Request;
+    use crate::http::version::Version;
+
+    fn lengths(values: &[&str]) -> Request<String> {
+        let builder = values.iter().fold(Request::builder(), |builder, value| builder.push_header("Content-Length", value));
+        Request::from_parts(builder.parts(), String::new())
+    }
+
+    fn request(version: Version, connection: Option<&str>) -> Request<String> {
+        let builder = Request::builder().version(version);
+        let builder = match connection {
+            Some(c) => builder.push_header("Connection", c),
+            None => builder,
+        };
+        Request::from_parts(builder.parts(), String::new())
+    }
+
+    #[test]
+    fn test_keep_alive() {
+        assert!(super::keep_alive(&request(Version::HTTP11, None)));
+        assert!(!super::keep_alive(&request(Version::HTTP11, Some("Close"))));
+        assert!(!super::keep_alive(&request(Version::HTTP10, None)));
+        assert!(super::keep_alive(&request(Version::HTTP10, Some("keep-alive"))));
+    }
+    #[test]
+    fn test_content_length() {
+        assert_eq!(super::content_length(&lengths(&[])), Some(0));
+        assert_eq!(super::content_length(&lengths(&[" 40 "])), Some(40));
+        assert_eq!(super::content_length(&lengths(&["40", "40, 40"])), Some(40));
+        assert_eq!(super::content_length(&lengths(&["0", "40"])), None);
+        assert_eq!(super::content_length(&lengths(&["+5"])), None);
+        assert_eq!(super::content_length(&lengths(&["5,"])), None);
+        assert_eq!(super::content_length(&lengths(&["0x10"])), None);
+    }
+}
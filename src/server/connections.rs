@@ -0,0 +1,100 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use crate::server::rate_limit::canonical;
+
+/// Caps on the connections open at the same time, checked when a connection is accepted.
+#[derive(Debug, Clone, Default)]
+pub struct ConnectionLimits {
+    max: Option<usize>,
+    per_ip: Option<usize>,
+    open: Arc<Mutex<Open>>,
+}
+
+#[derive(Debug, Default)]
+struct Open {
+    total: usize,
+    by_ip: HashMap<IpAddr, usize>,
+}
+
+/// A counted connection, released when dropped.
+#[derive(Debug)]
+pub struct Permit {
+    ip: Option<IpAddr>,
+    open: Arc<Mutex<Open>>,
+}
+
+impl ConnectionLimits {
+    pub fn new(max: Option<usize>, per_ip: Option<usize>) -> Self {
+        ConnectionLimits {
+            max,
+            per_ip,
+            open: Arc::default(),
+        }
+    }
+
+    /// Count a new connection from `ip`, None when a cap is reached.
+    /// Connections without an address, such as over a Unix socket, only count against the total.
+    pub fn acquire(&self, ip: Option<IpAddr>) -> Option<Permit> {
+        let ip = ip.map(canonical);
+        let mut open = match self.open.lock() {
+            Ok(open) => open,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        if self.max.is_some_and(|max| open.total >= max) {
+            return None;
+        }
+        if let (Some(ip), Some(per_ip)) = (ip, self.per_ip) {
+            if open.by_ip.get(&ip).copied().unwrap_or(0) >= per_ip {
+                return None;
+            }
+        }
+        open.total += 1;
+        if let Some(ip) = ip {
+            *open.by_ip.entry(ip).or_insert(0) += 1;
+        }
+        Some(Permit {
+            ip,
+            open: Arc::clone(&self.open),
+        })
+    }
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        let mut open = match self.open.lock() {
+            Ok(open) => open,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        open.total -= 1;
+        if let Some(ip) = self.ip {
+            if let Some(count) = open.by_ip.get_mut(&ip) {
+                *count -= 1;
+                if *count == 0 {
+                    open.by_ip.remove(&ip);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::IpAddr;
+    use super::ConnectionLimits;
+
+    #[test]
+    fn test_acquire() {
+        let limits = ConnectionLimits::new(Some(3), Some(2));
+        let a: IpAddr = "10.0.0.1".parse().unwrap();
+        let b: IpAddr = "::ffff:10.0.0.2".parse().unwrap();
+        let first = limits.acquire(Some(a)).unwrap();
+        let _second = limits.acquire(Some(a)).unwrap();
+        assert!(limits.acquire(Some(a)).is_none());
+        let _third = limits.acquire(Some(b)).unwrap();
+        // the total is reached
+        assert!(limits.acquire(None).is_none());
+        drop(first);
+        assert!(limits.acquire(Some(a)).is_some());
+    }
+}
@@ -1,23 +1,27 @@
-use std::path::{PathBuf, Path};
-use crate::http::parser::Parser;
-use std::collections::HashMap;
-use crate::http::request::Request;
+use std::sync::Arc;
+use rustls::ServerConfig;
+use crate::server::access_log::AccessLog;
+use crate::server::connections::ConnectionLimits;
+use crate::server::limits::Limits;
+use crate::server::metrics::Metrics;
+use crate::server::reader::Timeouts;
+use crate::server::shutdown::Shutdown;
+use crate::server::vhost::VirtualHosts;
 
-// // #[derive(Debug)]
-// pub struct Context<'a> {
-//     pub root: PathBuf,
-//     handlers: &'a HashMap<String, fn(&Context, Request<String>)>
-// }
-//
-// impl <'_> Context {
-//     pub fn new(root: &str, handlers: &'static HashMap<String, fn(&Context, Request<String>)>) -> Self {
-//         Context {
-//             root: Path::new(root).to_path_buf(),
-//             handlers
-//         }
-//     }
-//
-//     pub(crate) fn get_handler(&self, path: &str) -> Option<&fn(&Context, Request<String>)> {
-//         self.handlers.get(path)
-//     }
-// }
\ No newline at end of file
+/// State shared by every connection of a running server.
+#[derive(Debug)]
+pub struct Context {
+    pub hosts: VirtualHosts,
+    pub access_log: Option<AccessLog>,
+    pub metrics: Option<Metrics>,
+    pub metrics_path: String,
+    /// Metrics are only served on the admin listeners, not at `metrics_path` of the others.
+    pub admin_only: bool,
+    pub timeouts: Timeouts,
+    pub limits: Limits,
+    pub connections: ConnectionLimits,
+    /// Keep-alive connections are closed after the current request once triggered.
+    pub shutdown: Shutdown,
+    /// TLS is terminated on the regular TCP listeners.
+    pub tls: Option<Arc<ServerConfig>>,
+}
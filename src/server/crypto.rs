@@ -0,0 +1,54 @@
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Bytes from the operating system's random source.
+pub(crate) fn random_bytes(len: usize) -> Vec<u8> {
+    let mut buf = vec![0u8; len];
+    getrandom::getrandom(&mut buf).expect("the system random source failed");
+    buf
+}
+
+pub(crate) fn hmac_sha256(key: &[u8], data: &[u8]) -> [u8; 32] {
+    let mut mac = HmacSha256::new_from_slice(key).expect("HMAC takes keys of any length");
+    mac.update(data);
+    mac.finalize().into_bytes().into()
+}
+
+/// Check a tag in constant time.
+pub(crate) fn verify_hmac_sha256(key: &[u8], data: &[u8], tag: &[u8]) -> bool {
+    let mut mac = HmacSha256::new_from_slice(key).expect("HMAC takes keys of any length");
+    mac.update(data);
+    mac.verify_slice(tag).is_ok()
+}
+
+/// URL-safe base64 without padding, fit for cookies and tokens.
+pub(crate) fn encode(bytes: &[u8]) -> String {
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+pub(crate) fn decode(src: &str) -> Option<Vec<u8>> {
+    URL_SAFE_NO_PAD.decode(src).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_hmac_sha256() {
+        // RFC 4231 test case 2
+        let tag = super::hmac_sha256(b"Jefe", b"what do ya want for nothing?");
+        let hex: String = tag.iter().map(|b| format!("{:02x}", b)).collect();
+        assert_eq!(hex, "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843");
+        assert!(super::verify_hmac_sha256(b"Jefe", b"what do ya want for nothing?", &tag));
+        assert!(!super::verify_hmac_sha256(b"Jefe", b"what do ya want for something?", &tag));
+    }
+    #[test]
+    fn test_encode() {
+        assert_eq!(super::encode(&[0xfb, 0xff]), "-_8");
+        assert_eq!(super::decode("-_8"), Some(vec![0xfb, 0xff]));
+        assert_eq!(super::decode("not base64!"), None);
+    }
+}
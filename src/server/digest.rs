@@ -0,0 +1,388 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use md5::{Digest, Md5};
+use sha2::Sha256;
+use subtle::ConstantTimeEq;
+use crate::http::request::Request;
+use crate::http::response::Response;
+use crate::http::header::params;
+use crate::server::auth::{quote, unauthorized, AuthUser};
+use crate::server::crypto::{decode, encode, hmac_sha256, random_bytes};
+use crate::server::middleware::{Middleware, Next};
+use crate::server::resource::under_prefix;
+
+// a nonce is the time it was made, random bytes and a tag over both
+const NONCE_LEN: usize = 8 + 8 + 16;
+
+/// The hash of a Digest challenge.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Algorithm {
+    Md5,
+    Sha256,
+}
+
+impl Algorithm {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Algorithm::Md5 => "MD5",
+            Algorithm::Sha256 => "SHA-256",
+        }
+    }
+
+    pub(crate) fn parse(src: &str) -> Option<Self> {
+        if src.eq_ignore_ascii_case("MD5") {
+            Some(Algorithm::Md5)
+        } else if src.eq_ignore_ascii_case("SHA-256") {
+            Some(Algorithm::Sha256)
+        } else {
+            None
+        }
+    }
+
+    /// The hash of `data` in lowercase hex.
+    pub fn hex(&self, data: &str) -> String {
+        let digest = match self {
+            Algorithm::Md5 => Md5::digest(data.as_bytes()).to_vec(),
+            Algorithm::Sha256 => Sha256::digest(data.as_bytes()).to_vec(),
+        };
+        digest.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+}
+
+/// Looks up the users of a Digest realm.
+/// A closure from a user name to its password does, or a store of precomputed hashes.
+pub trait DigestCredentials: Send + Sync {
+    /// `H(user:realm:password)` in lowercase hex, `None` for an unknown user.
+    fn ha1(&self, user: &str, realm: &str, algorithm: Algorithm) -> Option<String>;
+}
+
+impl<F> DigestCredentials for F
+    where F: Fn(&str) -> Option<String> + Send + Sync
+{
+    fn ha1(&self, user: &str, realm: &str, algorithm: Algorithm) -> Option<String> {
+        let password = self(user)?;
+        Some(algorithm.hex(&format!("{}:{}:{}", user, realm, password)))
+    }
+}
+
+/// The `response` of Digest credentials with `qop=auth`, or the RFC 2069 one without `qop`.
+pub(crate) fn response(algorithm: Algorithm, ha1: &str, nonce: &str, qop: Option<(&str, &str)>, method: &str, uri: &str) -> String {
+    let ha2 = algorithm.hex(&format!("{}:{}", method, uri));
+    match qop {
+        Some((nc, cnonce)) => algorithm.hex(&format!("{}:{}:{}:{}:auth:{}", ha1, nonce, nc, cnonce, ha2)),
+        None => algorithm.hex(&format!("{}:{}:{}", ha1, nonce, ha2)),
+    }
+}
+
+/// HTTP Digest authentication with `qop=auth`, used as a middleware.
+/// Routes work as with `BasicAuth`. A challenge is sent for each algorithm, SHA-256 first.
+/// Nonces are signed with a key made at startup and expire after `nonce_ttl`,
+/// a client answering with an expired nonce or a used nonce count is challenged again with `stale=true`.
+#[derive(Clone)]
+pub struct DigestAuth {
+    key: Vec<u8>,
+    opaque: String,
+    nonce_ttl: Duration,
+    algorithms: Vec<Algorithm>,
+    routes: Vec<Route>,
+    // the highest nonce count seen and the issue time of each nonce in use
+    counts: Arc<Mutex<HashMap<String, (u32, u64)>>>,
+}
+
+#[derive(Clone)]
+struct Route {
+    prefix: String,
+    realm: String,
+    credentials: Arc<dyn DigestCredentials>,
+}
+
+// the outcome of checking credentials
+enum Check {
+    Valid(String),
+    Stale,
+    Invalid,
+}
+
+impl DigestAuth {
+    pub fn new() -> Self {
+        DigestAuth::default()
+    }
+
+    /// Require a user of `credentials` for paths under `prefix`.
+    pub fn route<C: DigestCredentials + 'static>(self, prefix: &str, realm: &str, credentials: C) -> Self {
+        let mut routes = self.routes;
+        routes.push(Route {
+            prefix: prefix.to_string(),
+            realm: realm.to_string(),
+            credentials: Arc::new(credentials),
+        });
+        routes.sort_by_key(|route| std::cmp::Reverse(route.prefix.len()));
+        DigestAuth { routes, ..self }
+    }
+
+    /// How long a nonce may be used, 5 minutes by default.
+    pub fn nonce_ttl(self, ttl: Duration) -> Self {
+        DigestAuth {
+            nonce_ttl: ttl,
+            ..self
+        }
+    }
+
+    /// The algorithms to offer in order of preference, SHA-256 and MD5 by default.
+    pub fn algorithms(self, algorithms: &[Algorithm]) -> Self {
+        assert!(!algorithms.is_empty(), "digest authentication needs an algorithm");
+        DigestAuth {
+            algorithms: algorithms.to_vec(),
+            ..self
+        }
+    }
+
+    fn nonce(&self) -> String {
+        let mut nonce = now().to_be_bytes().to_vec();
+        nonce.extend(random_bytes(8));
+        let tag = hmac_sha256(&self.key, &nonce);
+        nonce.extend(&tag[..16]);
+        encode(&nonce)
+    }
+
+    // when a nonce made here was issued
+    fn issued(&self, nonce: &str) -> Option<u64> {
+        let bytes = decode(nonce).filter(|bytes| bytes.len() == NONCE_LEN)?;
+        let tag = hmac_sha256(&self.key, &bytes[..16]);
+        if !bool::from(tag[..16].ct_eq(&bytes[16..])) {
+            return None;
+        }
+        let mut time = [0u8; 8];
+        time.copy_from_slice(&bytes[..8]);
+        Some(u64::from_be_bytes(time))
+    }
+
+    fn challenge(&self, route: &Route, stale: bool) -> Response<Vec<u8>> {
+        let nonce = self.nonce();
+        let challenges: Vec<String> = self.algorithms.iter()
+            .map(|algorithm| {
+                let mut challenge = format!(
+                    "Digest realm={}, qop=\"auth\", algorithm={}, nonce=\"{}\", opaque=\"{}\"",
+                    quote(&route.realm), algorithm.as_str(), nonce, self.opaque,
+                );
+                if stale {
+                    challenge.push_str(", stale=true");
+                }
+                challenge
+            })
+            .collect();
+        unauthorized(&challenges)
+    }
+
+    fn check(&self, route: &Route, req: &Request<String>, authorization: &str) -> Check {
+        let (scheme, rest) = authorization.trim().split_once(' ').unwrap_or((authorization, ""));
+        if !scheme.eq_ignore_ascii_case("Digest") {
+            return Check::Invalid;
+        }
+        let params = params(rest, ',');
+        let param = |name: &str| params.iter().find(|(n, _)| n == name).map(|(_, value)| value.as_str());
+        let (user, nonce, uri, given, nc, cnonce) = match (
+            param("username"), param("nonce"), param("uri"), param("response"), param("nc"), param("cnonce"),
+        ) {
+            (Some(user), Some(nonce), Some(uri), Some(given), Some(nc), Some(cnonce)) => (user, nonce, uri, given, nc, cnonce),
+            _ => return Check::Invalid,
+        };
+        let algorithm = match Algorithm::parse(param("algorithm").unwrap_or("MD5")) {
+            Some(algorithm) if self.algorithms.contains(&algorithm) => algorithm,
+            _ => return Check::Invalid,
+        };
+        let count = match u32::from_str_radix(nc, 16) {
+            Ok(count) if nc.len() == 8 => count,
+            _ => return Check::Invalid,
+        };
+        if param("realm") != Some(route.realm.as_str())
+            || param("qop") != Some("auth")
+            || param("opaque") != Some(self.opaque.as_str())
+            || uri != req.uri().as_str()
+            || cnonce.is_empty() {
+            return Check::Invalid;
+        }
+        let issued = match self.issued(nonce) {
+            Some(issued) => issued,
+            None => return Check::Invalid,
+        };
+        let ha1 = match route.credentials.ha1(user, &route.realm, algorithm) {
+            Some(ha1) => ha1,
+            None => return Check::Invalid,
+        };
+        let expected = response(algorithm, &ha1, nonce, Some((nc, cnonce)), req.method().as_str(), uri);
+        if !bool::from(expected.as_bytes().ct_eq(given.to_ascii_lowercase().as_bytes())) {
+            return Check::Invalid;
+        }
+        let now = now();
+        let ttl = self.nonce_ttl.as_secs();
+        if now.saturating_sub(issued) > ttl {
+            return Check::Stale;
+        }
+        let mut counts = match self.counts.lock() {
+            Ok(counts) => counts,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        counts.retain(|_, (_, issued)| now.saturating_sub(*issued) <= ttl);
+        let last = counts.entry(nonce.to_string()).or_insert((0, issued));
+        // a count that was used before is a replay
+        if count <= last.0 {
+            return Check::Stale;
+        }
+        last.0 = count;
+        Check::Valid(user.to_string())
+    }
+}
+
+impl Default for DigestAuth {
+    fn default() -> Self {
+        DigestAuth {
+            key: random_bytes(32),
+            opaque: encode(&random_bytes(16)),
+            nonce_ttl: Duration::from_secs(300),
+            algorithms: vec![Algorithm::Sha256, Algorithm::Md5],
+            routes: Vec::new(),
+            counts: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+}
+
+impl Middleware for DigestAuth {
+    fn handle(&self, mut req: Request<String>, next: Next<'_>) -> Response<Vec<u8>> {
+        let route = match self.routes.iter().find(|route| under_prefix(&route.prefix, req.uri().path())) {
+            Some(route) => route,
+            None => return next.run(req),
+        };
+        let check = match req.header().get("Authorization") {
+            Some(authorization) => self.check(route, &req, authorization),
+            None => Check::Invalid,
+        };
+        match check {
+            Check::Valid(user) => {
+                req.extensions_mut().insert(AuthUser(user));
+                next.run(req)
+            },
+            Check::Stale => self.challenge(route, true),
+            Check::Invalid => self.challenge(route, false),
+        }
+    }
+}
+
+impl fmt::Debug for DigestAuth {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let routes: Vec<(&str, &str)> = self.routes.iter()
+            .map(|route| (route.prefix.as_str(), route.realm.as_str()))
+            .collect();
+        f.debug_struct("DigestAuth")
+            .field("nonce_ttl", &self.nonce_ttl)
+            .field("algorithms", &self.algorithms)
+            .field("routes", &routes)
+            .finish()
+    }
+}
+
+// seconds since the epoch
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |elapsed| elapsed.as_secs())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::time::Duration;
+    use crate::http::method::Method;
+    use crate::http::request::Request;
+    use crate::http::response::Response;
+    use crate::http::header::params;
+    use crate::server::auth::AuthUser;
+    use crate::server::middleware::{Middleware, Next};
+    use super::{Algorithm, DigestAuth};
+
+    fn send(auth: &DigestAuth, path: &str, authorization: Option<&str>) -> Response<Vec<u8>> {
+        let mut builder = Request::builder().method(Method::GET).uri_from_str(path);
+        if let Some(authorization) = authorization {
+            builder = builder.push_header("Authorization", authorization);
+        }
+        let chain: Vec<Arc<dyn Middleware>> = vec![Arc::new(auth.clone())];
+        let endpoint = |req: Request<String>| {
+            let user = req.extensions().get::<AuthUser>().map(|user| user.0.clone()).unwrap_or_default();
+            Response::new(user.into_bytes())
+        };
+        Next::new(&chain, &endpoint).run(Request::from_parts(builder.parts(), String::new()))
+    }
+
+    // the nonce and opaque of the first challenge
+    fn challenge(res: &Response<Vec<u8>>) -> (String, String) {
+        let challenge = res.header().get("WWW-Authenticate").unwrap();
+        let params = params(challenge.strip_prefix("Digest ").unwrap(), ',');
+        let param = |name: &str| params.iter().find(|(n, _)| n == name).unwrap().1.clone();
+        (param("nonce"), param("opaque"))
+    }
+
+    fn authorization(algorithm: Algorithm, nonce: &str, opaque: &str, nc: &str, password: &str) -> String {
+        let ha1 = algorithm.hex(&format!("Mufasa:http-auth@example.org:{}", password));
+        let response = super::response(algorithm, &ha1, nonce, Some((nc, "f2/wE4q74E6zIJEtWaHKaf5wv/H5QzzpXusqGemxURZJ")), "GET", "/dir/index.html");
+        format!(
+            "Digest username=\"Mufasa\", realm=\"http-auth@example.org\", uri=\"/dir/index.html\", algorithm={}, \
+            nonce=\"{}\", nc={}, cnonce=\"f2/wE4q74E6zIJEtWaHKaf5wv/H5QzzpXusqGemxURZJ\", qop=auth, response=\"{}\", opaque=\"{}\"",
+            algorithm.as_str(), nonce, nc, response, opaque,
+        )
+    }
+
+    #[test]
+    fn test_response() {
+        // RFC 7616 section 3.9.1
+        let nonce = "7ypf/xlj9XXwfDPEoM4URrv/xwf94BcCAzFZH4GiTo0v";
+        let cnonce = Some(("00000001", "f2/wE4q74E6zIJEtWaHKaf5wv/H5QzzpXusqGemxURZJ"));
+        let ha1 = Algorithm::Md5.hex("Mufasa:http-auth@example.org:Circle of Life");
+        assert_eq!(super::response(Algorithm::Md5, &ha1, nonce, cnonce, "GET", "/dir/index.html"), "8ca523f5e9506fed4657c9700eebdbec");
+        let ha1 = Algorithm::Sha256.hex("Mufasa:http-auth@example.org:Circle of Life");
+        assert_eq!(
+            super::response(Algorithm::Sha256, &ha1, nonce, cnonce, "GET", "/dir/index.html"),
+            "753927fa0e85d155564e2e272a28d1802ca10daf4496794697cf8db5856cb6c1",
+        );
+    }
+    #[test]
+    fn test_digest_auth() {
+        let auth = DigestAuth::new()
+            .route("/dir", "http-auth@example.org", |user: &str| Some(user).filter(|user| *user == "Mufasa").map(|_| "Circle of Life".to_string()));
+        assert_eq!(send(&auth, "/", None).status().as_u16(), 200);
+        let res = send(&auth, "/dir/index.html", None);
+        assert_eq!(res.status().as_u16(), 401);
+        let challenges: Vec<&str> = res.header().get_all("WWW-Authenticate").collect();
+        assert_eq!(challenges.len(), 2);
+        assert!(challenges[0].contains("algorithm=SHA-256") && challenges[1].contains("algorithm=MD5"));
+        assert!(challenges[0].contains("qop=\"auth\"") && !challenges[0].contains("stale"));
+        let (nonce, opaque) = challenge(&res);
+        for (algorithm, nc) in [(Algorithm::Sha256, "00000001"), (Algorithm::Md5, "00000002")] {
+            let res = send(&auth, "/dir/index.html", Some(&authorization(algorithm, &nonce, &opaque, nc, "Circle of Life")));
+            assert_eq!(res.status().as_u16(), 200);
+            assert_eq!(res.body(), b"Mufasa");
+        }
+        // a used nonce count is a replay
+        let res = send(&auth, "/dir/index.html", Some(&authorization(Algorithm::Md5, &nonce, &opaque, "00000002", "Circle of Life")));
+        assert!(res.header().get("WWW-Authenticate").unwrap().ends_with("stale=true"));
+        let res = send(&auth, "/dir/index.html", Some(&authorization(Algorithm::Md5, &nonce, &opaque, "00000003", "wrong")));
+        assert!(!res.header().get("WWW-Authenticate").unwrap().contains("stale"));
+        // a nonce that was not made here
+        let forged = authorization(Algorithm::Md5, "7ypf/xlj9XXwfDPEoM4URrv/xwf94BcCAzFZH4GiTo0v", &opaque, "00000001", "Circle of Life");
+        assert_eq!(send(&auth, "/dir/index.html", Some(&forged)).status().as_u16(), 401);
+    }
+    #[test]
+    fn test_stale() {
+        let auth = DigestAuth::new()
+            .nonce_ttl(Duration::from_secs(0))
+            .algorithms(&[Algorithm::Md5])
+            .route("/", "http-auth@example.org", |_: &str| Some("Circle of Life".to_string()));
+        let (nonce, opaque) = challenge(&send(&auth, "/dir/index.html", None));
+        std::thread::sleep(Duration::from_millis(1100));
+        let res = send(&auth, "/dir/index.html", Some(&authorization(Algorithm::Md5, &nonce, &opaque, "00000001", "Circle of Life")));
+        assert_eq!(res.status().as_u16(), 401);
+        assert!(res.header().get("WWW-Authenticate").unwrap().ends_with("stale=true"));
+        // SHA-256 is not offered
+        let res = send(&auth, "/dir/index.html", Some(&authorization(Algorithm::Sha256, &nonce, &opaque, "00000002", "Circle of Life")));
+        assert!(!res.header().get("WWW-Authenticate").unwrap().contains("stale"));
+    }
+}
@@ -0,0 +1,560 @@
+//! Typed arguments for handlers registered with `Server::handle`.
+//!
+//! Each argument is pulled out of the request by its `FromRequest` impl,
+//! the first one which fails answers the request with its `Rejection` instead of running the handler.
+use std::any::type_name;
+use std::fmt;
+use std::net::SocketAddr;
+use serde::de::{self, DeserializeOwned, IntoDeserializer, Visitor};
+use serde::de::value::{Error as DeError, MapDeserializer, SeqDeserializer};
+use thiserror::Error;
+use crate::http::form::{self, percent_decode};
+use crate::http::header::Header;
+use crate::http::request::Request;
+use crate::http::response::Response;
+use crate::http::status::StatusCode;
+
+/// A value built from a request, to be taken as a handler argument.
+pub trait FromRequest: Sized {
+    fn from_request(req: &Request<String>) -> Result<Self, Rejection>;
+}
+
+/// What a handler returns, turned into the response.
+pub trait IntoResponse {
+    fn into_response(self) -> Response<Vec<u8>>;
+}
+
+/// A function whose arguments all implement `FromRequest` and whose result implements `IntoResponse`.
+/// `Args` is the tuple of argument types, it only tells the impls apart.
+pub trait Endpoint<Args>: Send + Sync + 'static {
+    fn call(&self, req: Request<String>) -> Response<Vec<u8>>;
+}
+
+/// Why an argument could not be extracted, answered as a plain text response with its status.
+#[derive(Error)]
+pub struct Rejection {
+    status: StatusCode,
+    message: String,
+}
+
+/// The values of the `{name}` segments of the route a request matched, in order.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct Params(Vec<(String, String)>);
+
+/// Route captures, deserialized into a single value, a tuple in segment order or a struct by name.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Path<T>(pub T);
+
+/// The query string, deserialized into a struct or map.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Query<T>(pub T);
+
+/// An `application/x-www-form-urlencoded` body, deserialized into a struct or map.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Form<T>(pub T);
+
+/// A JSON body, and a JSON response when returned.
+#[cfg(feature = "json")]
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Json<T>(pub T);
+
+/// A value shared by all handlers of a virtual host, added with `Server::state`.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct State<T>(pub T);
+
+impl Rejection {
+    pub fn new(status: u16, message: &str) -> Self {
+        Rejection {
+            status: StatusCode(status),
+            message: message.to_string(),
+        }
+    }
+
+    pub fn status(&self) -> StatusCode {
+        self.status
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+}
+
+impl From<Rejection> for Response<Vec<u8>> {
+    fn from(rejection: Rejection) -> Response<Vec<u8>> {
+        text(rejection.status, rejection.message)
+    }
+}
+
+impl fmt::Debug for Rejection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Rejection")
+            .field("status", &self.status.as_u16())
+            .field("message", &self.message)
+            .finish()
+    }
+}
+
+impl fmt::Display for Rejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.status.as_u16(), self.message)
+    }
+}
+
+impl Params {
+    pub(crate) fn new(params: Vec<(String, String)>) -> Self {
+        Params(params)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.0.iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.0.iter().map(|(name, value)| (name.as_str(), value.as_str()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+/// Match `path` against a route `pattern`, where a `{name}` segment matches any single segment.
+/// The captured segments are percent-decoded.
+pub(crate) fn captures(pattern: &std::path::Path, path: &std::path::Path) -> Option<Params> {
+    let mut pattern = pattern.components();
+    let mut path = path.components();
+    let mut params = Vec::new();
+    loop {
+        match (pattern.next(), path.next()) {
+            (None, None) => return Some(Params::new(params)),
+            (Some(expected), Some(segment)) => {
+                let expected = expected.as_os_str().to_str()?;
+                let segment = segment.as_os_str().to_str()?;
+                match expected.strip_prefix('{').and_then(|name| name.strip_suffix('}')) {
+                    Some(name) => params.push((name.to_string(), percent_decode(segment))),
+                    None if expected == segment => {},
+                    None => return None,
+                }
+            },
+            _ => return None,
+        }
+    }
+}
+
+impl<T: DeserializeOwned> FromRequest for Path<T> {
+    fn from_request(req: &Request<String>) -> Result<Self, Rejection> {
+        let pairs = match req.extensions().get::<Params>() {
+            Some(params) => params.iter().collect(),
+            None => Vec::new(),
+        };
+        T::deserialize(Pairs(pairs))
+            .map(Path)
+            .map_err(|e| Rejection::new(400, &format!("invalid path parameters: {}", e)))
+    }
+}
+
+impl<T: DeserializeOwned> FromRequest for Query<T> {
+    fn from_request(req: &Request<String>) -> Result<Self, Rejection> {
+        // the raw query, `Uri::query` keeps only the last of repeated names
+        let query = req.uri().as_str().split('#').next().unwrap_or_default()
+            .split_once('?')
+            .map(|(_, query)| form::Form::parse(query))
+            .unwrap_or_default();
+        T::deserialize(Pairs(query.iter().collect()))
+            .map(Query)
+            .map_err(|e| Rejection::new(400, &format!("invalid query string: {}", e)))
+    }
+}
+
+impl<T: DeserializeOwned> FromRequest for Form<T> {
+    fn from_request(req: &Request<String>) -> Result<Self, Rejection> {
+        let body = req.form()
+            .ok_or_else(|| Rejection::new(415, &format!("expected a body of type {}", form::CONTENT_TYPE)))?;
+        T::deserialize(Pairs(body.iter().collect()))
+            .map(Form)
+            .map_err(|e| Rejection::new(400, &format!("invalid form body: {}", e)))
+    }
+}
+
+#[cfg(feature = "json")]
+impl<T: DeserializeOwned> FromRequest for Json<T> {
+    fn from_request(req: &Request<String>) -> Result<Self, Rejection> {
+        req.json()
+            .map(Json)
+            .map_err(|e| Rejection::new(e.status().as_u16(), &e.to_string()))
+    }
+}
+
+impl<T: Clone + Send + Sync + 'static> FromRequest for State<T> {
+    fn from_request(req: &Request<String>) -> Result<Self, Rejection> {
+        req.extensions().get::<State<T>>()
+            .cloned()
+            .ok_or_else(|| Rejection::new(500, &format!("no state of type {}", type_name::<T>())))
+    }
+}
+
+impl FromRequest for Header {
+    fn from_request(req: &Request<String>) -> Result<Self, Rejection> {
+        Ok(req.header().clone())
+    }
+}
+
+/// The client address, missing for requests over a unix socket.
+impl FromRequest for SocketAddr {
+    fn from_request(req: &Request<String>) -> Result<Self, Rejection> {
+        req.remote_addr()
+            .ok_or_else(|| Rejection::new(500, "no client address"))
+    }
+}
+
+impl FromRequest for Params {
+    fn from_request(req: &Request<String>) -> Result<Self, Rejection> {
+        Ok(req.extensions().get::<Params>().cloned().unwrap_or_default())
+    }
+}
+
+/// The body as it is.
+impl FromRequest for String {
+    fn from_request(req: &Request<String>) -> Result<Self, Rejection> {
+        Ok(req.body().clone())
+    }
+}
+
+/// `None` instead of a rejection.
+impl<T: FromRequest> FromRequest for Option<T> {
+    fn from_request(req: &Request<String>) -> Result<Self, Rejection> {
+        Ok(T::from_request(req).ok())
+    }
+}
+
+/// The rejection is handed to the handler instead of answering the request.
+impl<T: FromRequest> FromRequest for Result<T, Rejection> {
+    fn from_request(req: &Request<String>) -> Result<Self, Rejection> {
+        Ok(T::from_request(req))
+    }
+}
+
+fn text(status: StatusCode, body: String) -> Response<Vec<u8>> {
+    let mut header = Header::new();
+    header.add("Content-Type", "text/plain; charset=utf-8");
+    header.add("Content-Length", &body.len().to_string());
+    Response::builder()
+        .status(status)
+        .header(header)
+        .response(body.into_bytes())
+}
+
+impl IntoResponse for Response<Vec<u8>> {
+    fn into_response(self) -> Response<Vec<u8>> {
+        self
+    }
+}
+
+/// A `200 OK` HTML page, as the handlers of `Server::register` answer.
+impl IntoResponse for String {
+    fn into_response(self) -> Response<Vec<u8>> {
+        let mut res = text(StatusCode(200), self);
+        res.header_mut().set("Content-Type", "text/html");
+        res
+    }
+}
+
+impl IntoResponse for &'static str {
+    fn into_response(self) -> Response<Vec<u8>> {
+        self.to_string().into_response()
+    }
+}
+
+impl IntoResponse for StatusCode {
+    fn into_response(self) -> Response<Vec<u8>> {
+        text(self, String::new())
+    }
+}
+
+impl<R: IntoResponse> IntoResponse for (StatusCode, R) {
+    fn into_response(self) -> Response<Vec<u8>> {
+        let (status, res) = self;
+        let (mut parts, body) = res.into_response().into_parts();
+        parts.status = status;
+        Response::from_parts(parts, body)
+    }
+}
+
+impl IntoResponse for Rejection {
+    fn into_response(self) -> Response<Vec<u8>> {
+        Response::from(self)
+    }
+}
+
+impl<R: IntoResponse, E: IntoResponse> IntoResponse for Result<R, E> {
+    fn into_response(self) -> Response<Vec<u8>> {
+        match self {
+            Ok(res) => res.into_response(),
+            Err(e) => e.into_response(),
+        }
+    }
+}
+
+#[cfg(feature = "json")]
+impl<T: serde::Serialize> IntoResponse for Json<T> {
+    fn into_response(self) -> Response<Vec<u8>> {
+        match Response::json(&self.0) {
+            Ok(res) => res,
+            Err(e) => text(StatusCode(500), format!("failed to serialize the response: {}", e)),
+        }
+    }
+}
+
+impl<F, R> Endpoint<()> for F
+    where F: Fn() -> R + Send + Sync + 'static, R: IntoResponse {
+    fn call(&self, _req: Request<String>) -> Response<Vec<u8>> {
+        self().into_response()
+    }
+}
+
+macro_rules! endpoint {
+    ($($arg:ident),+) => {
+        impl<F, R, $($arg),+> Endpoint<($($arg,)+)> for F
+            where F: Fn($($arg),+) -> R + Send + Sync + 'static, R: IntoResponse, $($arg: FromRequest),+ {
+            #[allow(non_snake_case)]
+            fn call(&self, req: Request<String>) -> Response<Vec<u8>> {
+                $(
+                    let $arg = match $arg::from_request(&req) {
+                        Ok(value) => value,
+                        Err(rejection) => return rejection.into_response(),
+                    };
+                )+
+                self($($arg),+).into_response()
+            }
+        }
+    };
+}
+
+endpoint!(A);
+endpoint!(A, B);
+endpoint!(A, B, C);
+endpoint!(A, B, C, D);
+endpoint!(A, B, C, D, E);
+endpoint!(A, B, C, D, E, G);
+
+// name and value pairs deserialized as a map, a sequence of values or, when there is one pair, its value
+struct Pairs<'a>(Vec<(&'a str, &'a str)>);
+
+// a single value, parsed into the type asked for
+struct Value<'a>(&'a str);
+
+impl<'a> Pairs<'a> {
+    fn single(self) -> Result<Value<'a>, DeError> {
+        match self.0.as_slice() {
+            [(_, value)] => Ok(Value(value)),
+            pairs => Err(de::Error::custom(format!("expected a single value, found {}", pairs.len()))),
+        }
+    }
+}
+
+macro_rules! single {
+    ($($method:ident)*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DeError> {
+                self.single()?.$method(visitor)
+            }
+        )*
+    };
+}
+
+impl<'de, 'a> de::Deserializer<'de> for Pairs<'a> {
+    type Error = DeError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DeError> {
+        self.deserialize_map(visitor)
+    }
+
+    fn deserialize_map<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DeError> {
+        let pairs = self.0.into_iter().map(|(name, value)| (name, Value(value)));
+        let mut map = MapDeserializer::new(pairs);
+        let value = visitor.visit_map(&mut map)?;
+        map.end()?;
+        Ok(value)
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(self, _name: &'static str, _fields: &'static [&'static str], visitor: V) -> Result<V::Value, DeError> {
+        self.deserialize_map(visitor)
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DeError> {
+        let mut seq = SeqDeserializer::new(self.0.into_iter().map(|(_, value)| Value(value)));
+        let value = visitor.visit_seq(&mut seq)?;
+        seq.end()?;
+        Ok(value)
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(self, _len: usize, visitor: V) -> Result<V::Value, DeError> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(self, _name: &'static str, _len: usize, visitor: V) -> Result<V::Value, DeError> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(self, _name: &'static str, visitor: V) -> Result<V::Value, DeError> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_unit_struct<V: Visitor<'de>>(self, _name: &'static str, visitor: V) -> Result<V::Value, DeError> {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(self, name: &'static str, variants: &'static [&'static str], visitor: V) -> Result<V::Value, DeError> {
+        self.single()?.deserialize_enum(name, variants, visitor)
+    }
+
+    single! {
+        deserialize_bool deserialize_i8 deserialize_i16 deserialize_i32 deserialize_i64
+        deserialize_u8 deserialize_u16 deserialize_u32 deserialize_u64 deserialize_f32 deserialize_f64
+        deserialize_char deserialize_str deserialize_string deserialize_bytes deserialize_byte_buf
+        deserialize_option deserialize_unit deserialize_identifier deserialize_ignored_any
+    }
+}
+
+macro_rules! parse {
+    ($($method:ident => $visit:ident,)*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DeError> {
+                match self.0.parse() {
+                    Ok(value) => visitor.$visit(value),
+                    Err(_) => Err(de::Error::invalid_value(de::Unexpected::Str(self.0), &visitor)),
+                }
+            }
+        )*
+    };
+}
+
+impl<'de, 'a> de::Deserializer<'de> for Value<'a> {
+    type Error = DeError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DeError> {
+        visitor.visit_str(self.0)
+    }
+
+    // an empty value is missing
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DeError> {
+        if self.0.is_empty() {
+            visitor.visit_none()
+        } else {
+            visitor.visit_some(self)
+        }
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(self, _name: &'static str, visitor: V) -> Result<V::Value, DeError> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(self, _name: &'static str, _variants: &'static [&'static str], visitor: V) -> Result<V::Value, DeError> {
+        visitor.visit_enum(self.0.into_deserializer())
+    }
+
+    fn deserialize_unit<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DeError> {
+        visitor.visit_unit()
+    }
+
+    parse! {
+        deserialize_bool => visit_bool,
+        deserialize_i8 => visit_i8,
+        deserialize_i16 => visit_i16,
+        deserialize_i32 => visit_i32,
+        deserialize_i64 => visit_i64,
+        deserialize_u8 => visit_u8,
+        deserialize_u16 => visit_u16,
+        deserialize_u32 => visit_u32,
+        deserialize_u64 => visit_u64,
+        deserialize_f32 => visit_f32,
+        deserialize_f64 => visit_f64,
+        deserialize_char => visit_char,
+    }
+
+    serde::forward_to_deserialize_any! {
+        i128 u128 str string bytes byte_buf unit_struct seq tuple tuple_struct map struct identifier ignored_any
+    }
+}
+
+impl<'de, 'a> IntoDeserializer<'de, DeError> for Value<'a> {
+    type Deserializer = Self;
+
+    fn into_deserializer(self) -> Self {
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path as FsPath;
+    use serde::Deserialize;
+    use crate::http::request::Request;
+    use crate::http::response::Response;
+    use super::{Endpoint, Form, FromRequest, Params, Path, Query, State};
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct Search {
+        q: String,
+        page: Option<u32>,
+    }
+
+    fn request(uri: &str, params: &[(&str, &str)]) -> Request<String> {
+        let mut req = Request::from_parts(Request::builder().method(crate::http::method::Method::GET).uri_from_str(uri).parts(), String::new());
+        let params = params.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect();
+        req.extensions_mut().insert(Params::new(params));
+        req
+    }
+
+    #[test]
+    fn test_captures() {
+        let params = super::captures(FsPath::new("/users/{id}/posts/{post}"), FsPath::new("/users/7/posts/a%20b")).unwrap();
+        assert_eq!(params.iter().collect::<Vec<_>>(), [("id", "7"), ("post", "a b")]);
+        assert!(super::captures(FsPath::new("/users/{id}"), FsPath::new("/users/7/posts")).is_none());
+        assert!(super::captures(FsPath::new("/users/{id}"), FsPath::new("/groups/7")).is_none());
+        assert!(super::captures(FsPath::new("/users"), FsPath::new("/users")).unwrap().is_empty());
+    }
+    #[test]
+    fn test_extract() {
+        let req = request("/users/7/a?q=rust+lang&page=2", &[("id", "7"), ("name", "a")]);
+        assert_eq!(Path::<u32>::from_request(&request("/", &[("id", "7")])).unwrap(), Path(7));
+        assert_eq!(Path::<(u32, String)>::from_request(&req).unwrap(), Path((7, "a".to_string())));
+        let Query(search) = Query::<Search>::from_request(&req).unwrap();
+        assert_eq!(search, Search { q: "rust lang".to_string(), page: Some(2) });
+        let rejection = Path::<(u32,)>::from_request(&request("/", &[("id", "x")])).err().unwrap();
+        assert_eq!(rejection.status().as_u16(), 400);
+        let rejection = Query::<Search>::from_request(&request("/?page=1", &[])).err().unwrap();
+        assert!(rejection.message().contains("missing field `q`"));
+        assert_eq!(Form::<Search>::from_request(&req).err().unwrap().status().as_u16(), 415);
+        assert_eq!(State::<u32>::from_request(&req).err().unwrap().status().as_u16(), 500);
+    }
+    #[test]
+    fn test_endpoint() {
+        let handler = |Path((id,)): Path<(u32,)>, State(greeting): State<&'static str>| format!("{} {}", greeting, id);
+        let mut req = request("/users/7", &[("id", "7")]);
+        req.extensions_mut().insert(State("hello"));
+        let res: Response<Vec<u8>> = handler.call(req);
+        assert_eq!(res.status().as_u16(), 200);
+        assert_eq!(res.body(), b"hello 7");
+        let res = handler.call(request("/users/x", &[("id", "x")]));
+        assert_eq!(res.status().as_u16(), 400);
+        assert_eq!(res.header().get("Content-Type"), Some("text/plain; charset=utf-8"));
+    }
+    #[cfg(feature = "json")]
+    #[test]
+    fn test_json() {
+        use super::Json;
+        let handler = |Json(search): Json<Search>| Json(vec![search.q]);
+        let parts = Request::builder().push_header("Content-Type", "application/json").parts();
+        let res = handler.call(Request::from_parts(parts, r#"{"q":"rust"}"#.to_string()));
+        assert_eq!(res.body(), br#"["rust"]"#);
+        assert_eq!(res.header().get("Content-Type"), Some("application/json"));
+        assert_eq!(handler.call(request("/", &[])).status().as_u16(), 415);
+    }
+}
@@ -1,24 +1,38 @@
+use std::fmt;
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use crate::http::request::Request;
+use crate::http::error::Error as HttpError;
+use crate::http::header::http_date;
 use crate::http::method::Method;
-use std::net::{TcpStream, Shutdown};
+use crate::http::response::Response;
 use crate::server::error::Error;
-use crate::http::parser::Parser;
-use std::io::{Read, Write};
-use crate::server::{ServerError, resource};
-use crate::server::response::response;
+use crate::server::extract::{captures, Endpoint, Params};
+use crate::server::resource::Mount;
+use crate::server::response::{response, file_response};
+use log::debug;
 
 #[derive(Debug, Clone)]
 pub struct Handler<T> {
     pub path: PathBuf,
     pub method: Method,
-    pub handler: fn(Request<String>) -> T
+    pub handler: Func<T>,
+}
+
+/// A handler function: a plain one whose result is the HTML body of a `200 OK`,
+/// or one with typed arguments which builds the whole response.
+#[derive(Clone)]
+pub enum Func<T> {
+    Plain(fn(Request<String>) -> T),
+    Typed(Arc<dyn Fn(Request<String>) -> Response<Vec<u8>> + Send + Sync>),
 }
 
 #[derive(Debug, Clone)]
 pub struct Handlers<T> {
     root: PathBuf,
-    inner: Vec<Handler<T>>
+    inner: Vec<Handler<T>>,
+    mounts: Vec<Mount>,
+    headers: Vec<(String, String)>,
 }
 
 
@@ -29,12 +43,19 @@ impl Handler<String> {
         Handler {
             path,
             method,
-            handler
+            handler: Func::Plain(handler),
         }
     }
 
-    fn func(&self) -> fn(Request<String>) -> String {
-        self.handler
+    /// A handler with typed arguments, `path` may hold `{name}` segments to capture.
+    pub fn typed<E: Endpoint<Args>, Args>(root: PathBuf, path: &str, method: &str, endpoint: E) -> Self {
+        let path = root.join(Path::new(path));
+        let method = Method::from_str(method).unwrap();
+        Handler {
+            path,
+            method,
+            handler: Func::Typed(Arc::new(move |req| endpoint.call(req))),
+        }
     }
 
     fn exec(&self) {
@@ -47,59 +68,132 @@ impl Handlers<String> {
         let root = Path::new(root).to_path_buf();
         Handlers {
             root,
-            inner: Vec::new()
+            inner: Vec::new(),
+            mounts: Vec::new(),
+            headers: Vec::new(),
         }
     }
 
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
     pub fn add(&mut self, handler: Handler<String>) {
         self.inner.push(handler);
     }
 
+    pub fn mount(&mut self, mount: Mount) {
+        self.mounts.push(mount);
+        // keep the longest prefix first so that it wins on lookup
+        self.mounts.sort_by_key(|mount| std::cmp::Reverse(mount.prefix().len()));
+    }
+
+    // header added to every response
+    pub fn add_header(&mut self, name: &str, value: &str) {
+        self.headers.push((name.to_string(), value.to_string()));
+    }
+
+    pub fn routes(&self) -> Vec<String> {
+        let mut routes: Vec<String> = self.inner.iter()
+            .map(|handler| {
+                let path = handler.path.strip_prefix(&self.root).unwrap_or(&handler.path);
+                format!("{:<7} /{}", handler.method.as_str(), path.display())
+            })
+            .collect();
+        for mount in self.mounts.iter() {
+            let prefix = if mount.prefix().is_empty() { "/" } else { mount.prefix() };
+            routes.push(format!("{:<7} {} -> {}", "STATIC", prefix, mount.dir().display()));
+        }
+        routes
+    }
+
+    /// The route pattern a request path falls under: a handler path or a static mount prefix.
+    pub fn route(&self, path: &str, method: &str) -> Option<String> {
+        if let Some((handler, params)) = self.lookup(path, method) {
+            if params.is_empty() {
+                return Some(path.to_string());
+            }
+            let pattern = handler.path.strip_prefix(&self.root).unwrap_or(&handler.path);
+            return Some(format!("/{}", pattern.display().to_string().trim_start_matches('/')));
+        }
+        self.mounts.iter()
+            .find(|mount| mount.matches(path))
+            .map(|mount| if mount.prefix().is_empty() { "/".to_string() } else { mount.prefix().to_string() })
+    }
+
     pub fn find(&self, path: &str, method: &str) -> Option<&Handler<String>> {
+        self.lookup(path, method).map(|(handler, _)| handler)
+    }
+
+    /// The handler for a request and the segments its route captures.
+    /// A route without captures wins over one with them, otherwise the first registered one does.
+    pub fn lookup(&self, path: &str, method: &str) -> Option<(&Handler<String>, Params)> {
         let path = self.root.join(Path::new(path));
         let method = match Method::from_str(method) {
             Ok(m) => m,
             Err(_) => return None,
         };
-        self.inner.iter()
-            .find(|handler| handler.path == path && handler.method == method)
+        let mut handlers = self.inner.iter().filter(|handler| handler.method == method);
+        if let Some(handler) = handlers.clone().find(|handler| handler.path == path) {
+            return Some((handler, Params::default()));
+        }
+        handlers.find_map(|handler| captures(&handler.path, &path).map(|params| (handler, params)))
     }
 
-    pub fn handle(&self, mut stream: TcpStream) -> Result<(), Error> {
-        let mut data = [0u8; 256];
-        loop {
-            match stream.read(&mut data) {
-                Ok(size) => {
-                    println!("[info] receive {} bytes", size);
-                    let request = Parser::new().parse_request(&data[..size])?; // should not use unwrap
-                    let path = request.uri().path();
-                    let method = request.method();
-                    // println!("[info] path: {:?}", path);
-                    // println!("[info] method: {:?}", request.method().as_str());
-                    // if !resource::validate(self.root.to_str().unwrap(), path) {
-                    //     println!("[error] not found requested resource: {}", path);
-                    // }
-                    println!("[info] handle request");
-                    let handler = match self.find(path, method.as_str()) {
-                        Some(f) => f,
-                        None => {
-                            println!("[error] handler function is not registered for {}", path);
-                            return Err(Error::from(ServerError::new()));
-                        }
-                    };
-                    let res_data = handler.func()(request);
-                    let res = response(200, Some(&res_data))?;
-                    println!("[info] response\r\n{}", res);
-                    stream.write(res.as_bytes())?;
-                    println!("[info] write to stream");
-                    return Ok(());
-                },
-                Err(e) => {
-                    println!("[error] failed to read from stream: because of {:?}", e);
-                    stream.shutdown(Shutdown::Both).unwrap();
-                    return Err(Error::from(e));
-                }
+    // headers added to every response
+    pub fn headers(&self) -> &[(String, String)] {
+        &self.headers
+    }
+
+    pub fn dispatch(&self, request: Request<String>) -> Result<Response<Vec<u8>>, Error> {
+        let path = request.uri().path();
+        let method = request.method();
+        // if !resource::validate(self.root.to_str().unwrap(), path) {
+        //     println!("[error] not found requested resource: {}", path);
+        // }
+        debug!("{} {}", method.as_str(), path);
+        let (handler, params) = match self.lookup(path, method.as_str()) {
+            Some(found) => found,
+            None => {
+                // fall back to static files
+                let file = self.mounts.iter()
+                    .find_map(|mount| mount.resolve(path));
+                return match file {
+                    Some(file) => file_response(&file, &self.headers),
+                    None => {
+                        debug!("no handler or file for {}", path);
+                        response(404, Some("Not Found"), &self.headers)
+                    },
+                };
             }
+        };
+        let mut request = request;
+        request.extensions_mut().insert(params);
+        match &handler.handler {
+            Func::Plain(func) => {
+                let res_data = func(request);
+                response(200, Some(&res_data), &self.headers)
+            },
+            Func::Typed(func) => {
+                let mut res = func(request);
+                if res.header().get("Date").is_none() {
+                    res.header_mut().parse(&http_date())
+                        .map_err(|e| Error::from(HttpError::from(e)))?;
+                }
+                for (name, value) in self.headers.iter() {
+                    res.header_mut().add(name, value);
+                }
+                Ok(res)
+            },
+        }
+    }
+}
+
+impl<T> fmt::Debug for Func<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Func::Plain(func) => f.debug_tuple("Func::Plain").field(&(*func as *const ())).finish(),
+            Func::Typed(_) => f.write_str("Func::Typed"),
         }
     }
-}
\ No newline at end of file
+}
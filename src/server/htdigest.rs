@@ -0,0 +1,88 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use log::warn;
+use crate::server::digest::{Algorithm, DigestCredentials};
+
+/// Users of an htdigest file as written by Apache's `htdigest`, lines of `user:realm:hash`
+/// with the MD5 hash of `user:realm:password` in hex. Only MD5 challenges can be answered.
+#[derive(Clone, Default)]
+pub struct Htdigest {
+    users: HashMap<(String, String), String>,
+}
+
+impl Htdigest {
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Ok(Htdigest::parse(&fs::read_to_string(path)?))
+    }
+
+    pub fn parse(src: &str) -> Self {
+        let mut users = HashMap::new();
+        for (i, line) in src.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut fields = line.splitn(3, ':');
+            match (fields.next(), fields.next(), fields.next()) {
+                (Some(user), Some(realm), Some(hash)) if hash.len() == 32 && hash.bytes().all(|b| b.is_ascii_hexdigit()) => {
+                    users.insert((user.to_string(), realm.to_string()), hash.to_ascii_lowercase());
+                },
+                _ => warn!("htdigest line {}: no `user:realm:hash`", i + 1),
+            }
+        }
+        Htdigest { users }
+    }
+
+    pub fn contains(&self, user: &str, realm: &str) -> bool {
+        self.users.contains_key(&(user.to_string(), realm.to_string()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.users.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.users.is_empty()
+    }
+}
+
+impl DigestCredentials for Htdigest {
+    fn ha1(&self, user: &str, realm: &str, algorithm: Algorithm) -> Option<String> {
+        match algorithm {
+            Algorithm::Md5 => self.users.get(&(user.to_string(), realm.to_string())).cloned(),
+            Algorithm::Sha256 => None,
+        }
+    }
+}
+
+impl fmt::Debug for Htdigest {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Htdigest")
+            .field("users", &self.users.len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::server::digest::{Algorithm, DigestCredentials};
+    use super::Htdigest;
+
+    #[test]
+    fn test_ha1() {
+        // the example of RFC 2617, the password is `Circle Of Life`
+        let htdigest = Htdigest::parse("\
+            # users\n\
+            Mufasa:testrealm@host.com:939E7578ED9E3C518A452ACEE763BCE9\n\
+            broken:line\n");
+        assert_eq!(htdigest.len(), 1);
+        assert_eq!(htdigest.ha1("Mufasa", "testrealm@host.com", Algorithm::Md5),
+            Some(Algorithm::Md5.hex("Mufasa:testrealm@host.com:Circle Of Life")));
+        assert_eq!(htdigest.ha1("Mufasa", "other", Algorithm::Md5), None);
+        assert_eq!(htdigest.ha1("Mufasa", "testrealm@host.com", Algorithm::Sha256), None);
+        assert!(!htdigest.contains("broken", "line"));
+    }
+}
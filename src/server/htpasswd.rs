@@ -0,0 +1,230 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use log::warn;
+use md5::{Digest, Md5};
+use sha1::Sha1;
+use subtle::ConstantTimeEq;
+use crate::server::auth::Credentials;
+
+const APR1: &str = "$apr1$";
+// the alphabet of crypt(3) hashes
+const ITOA64: &[u8] = b"./0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+
+/// Users of an htpasswd file as written by Apache's `htpasswd`.
+/// bcrypt (`-B`), SHA-1 (`-s`) and APR1 MD5 (`-m`, the default) hashes are understood,
+/// lines with crypt(3) or plain text passwords are skipped.
+#[derive(Clone, Default)]
+pub struct Htpasswd {
+    users: HashMap<String, Hash>,
+    // the costliest hash of the file, checked for unknown users so that they take as long as known ones
+    dummy: Option<Hash>,
+}
+
+#[derive(Clone)]
+enum Hash {
+    Bcrypt(String),
+    Sha1(Vec<u8>),
+    Apr1 { salt: String, hash: String },
+}
+
+impl Htpasswd {
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Ok(Htpasswd::parse(&fs::read_to_string(path)?))
+    }
+
+    pub fn parse(src: &str) -> Self {
+        let mut users = HashMap::new();
+        for (i, line) in src.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (user, hash) = match line.split_once(':') {
+                Some((user, hash)) => (user, hash),
+                None => {
+                    warn!("htpasswd line {}: no `user:hash`", i + 1);
+                    continue;
+                },
+            };
+            match Hash::parse(hash) {
+                Some(hash) => {
+                    users.insert(user.to_string(), hash);
+                },
+                None => warn!("htpasswd line {}: the hash of {} is not bcrypt, SHA or APR1", i + 1, user),
+            }
+        }
+        let dummy = users.values().max_by_key(|hash| hash.cost()).cloned();
+        Htpasswd { users, dummy }
+    }
+
+    pub fn contains(&self, user: &str) -> bool {
+        self.users.contains_key(user)
+    }
+
+    pub fn len(&self) -> usize {
+        self.users.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.users.is_empty()
+    }
+}
+
+impl Credentials for Htpasswd {
+    fn verify(&self, user: &str, password: &str) -> bool {
+        match self.users.get(user) {
+            Some(hash) => hash.verify(password),
+            None => {
+                if let Some(dummy) = &self.dummy {
+                    dummy.verify(password);
+                }
+                false
+            },
+        }
+    }
+}
+
+impl fmt::Debug for Htpasswd {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Htpasswd")
+            .field("users", &self.users.len())
+            .finish()
+    }
+}
+
+impl Hash {
+    fn parse(src: &str) -> Option<Self> {
+        if src.starts_with("$2a$") || src.starts_with("$2b$") || src.starts_with("$2y$") {
+            return Some(Hash::Bcrypt(src.to_string()));
+        }
+        if let Some(digest) = src.strip_prefix("{SHA}") {
+            return STANDARD.decode(digest).ok()
+                .filter(|digest| digest.len() == 20)
+                .map(Hash::Sha1);
+        }
+        let (salt, _) = src.strip_prefix(APR1)?.split_once('$')?;
+        Some(Hash::Apr1 {
+            salt: salt.to_string(),
+            hash: src.to_string(),
+        })
+    }
+
+    fn verify(&self, password: &str) -> bool {
+        match self {
+            Hash::Bcrypt(hash) => bcrypt::verify(password, hash).unwrap_or(false),
+            Hash::Sha1(digest) => Sha1::digest(password.as_bytes()).as_slice().ct_eq(digest).into(),
+            Hash::Apr1 { salt, hash } => apr1(password.as_bytes(), salt.as_bytes()).as_bytes().ct_eq(hash.as_bytes()).into(),
+        }
+    }
+
+    // how expensive a check is: the bcrypt cost, as in `$2y$05$`, ranks above the fixed-cost hashes
+    fn cost(&self) -> u32 {
+        match self {
+            Hash::Bcrypt(hash) => hash.get(4..6).and_then(|cost| cost.parse().ok()).map_or(1, |cost: u32| cost + 1),
+            Hash::Apr1 { .. } => 1,
+            Hash::Sha1(_) => 0,
+        }
+    }
+}
+
+// the APR1 variant of the MD5-based crypt(3), as in `$apr1$<salt>$<hash>`
+fn apr1(password: &[u8], salt: &[u8]) -> String {
+    let salt = &salt[..salt.len().min(8)];
+    let mut ctx = Md5::new();
+    ctx.update(password);
+    ctx.update(APR1.as_bytes());
+    ctx.update(salt);
+    let mut alt = Md5::new();
+    alt.update(password);
+    alt.update(salt);
+    alt.update(password);
+    let alt = alt.finalize();
+    for chunk in password.chunks(16) {
+        ctx.update(&alt[..chunk.len()]);
+    }
+    let mut len = password.len();
+    while len > 0 {
+        if len & 1 == 1 {
+            ctx.update([0u8]);
+        } else {
+            ctx.update(&password[..1]);
+        }
+        len >>= 1;
+    }
+    let mut digest = ctx.finalize();
+    for i in 0..1000 {
+        let mut round = Md5::new();
+        if i & 1 == 1 {
+            round.update(password);
+        } else {
+            round.update(digest);
+        }
+        if i % 3 != 0 {
+            round.update(salt);
+        }
+        if i % 7 != 0 {
+            round.update(password);
+        }
+        if i & 1 == 1 {
+            round.update(digest);
+        } else {
+            round.update(password);
+        }
+        digest = round.finalize();
+    }
+    let mut out = format!("{}{}$", APR1, String::from_utf8_lossy(salt));
+    for (a, b, c) in [(0, 6, 12), (1, 7, 13), (2, 8, 14), (3, 9, 15), (4, 10, 5)] {
+        let value = (u32::from(digest[a]) << 16) | (u32::from(digest[b]) << 8) | u32::from(digest[c]);
+        to64(&mut out, value, 4);
+    }
+    to64(&mut out, u32::from(digest[11]), 2);
+    out
+}
+
+fn to64(out: &mut String, mut value: u32, len: usize) {
+    for _ in 0..len {
+        out.push(char::from(ITOA64[(value & 0x3f) as usize]));
+        value >>= 6;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::server::auth::Credentials;
+    use super::Htpasswd;
+
+    #[test]
+    fn test_apr1() {
+        // made with `openssl passwd -apr1`
+        assert_eq!(super::apr1(b"password", b"saltsalt"), "$apr1$saltsalt$yAAkm4libquA.ZWLHbSBq/");
+        assert_eq!(super::apr1(b"", b"ab"), "$apr1$ab$S8K6Sgp3W8c9Jb6LxgywZ.");
+    }
+    #[test]
+    fn test_verify() {
+        let htpasswd = Htpasswd::parse("\
+            # users\n\
+            apr:$apr1$saltsalt$yAAkm4libquA.ZWLHbSBq/\n\
+            sha:{SHA}W6ph5Mm5Pz8GgiULbPgzG37mj9g=\n\
+            bcrypt:$2y$05$CCCCCCCCCCCCCCCCCCCCC.E5YPO9kmyuRGyh0XouQYb4YMJKvyOeW\n\
+            plain:password\n\
+            broken\n");
+        assert_eq!(htpasswd.len(), 3);
+        assert!(htpasswd.verify("apr", "password"));
+        assert!(!htpasswd.verify("apr", "Password"));
+        assert!(htpasswd.verify("sha", "password"));
+        assert!(!htpasswd.verify("sha", "password1"));
+        assert!(htpasswd.verify("bcrypt", "U*U"));
+        assert!(!htpasswd.verify("bcrypt", "U*V"));
+        assert!(!htpasswd.contains("plain"));
+        assert!(!htpasswd.verify("nobody", "password"));
+        // unknown users are checked against the bcrypt hash, the password of its user does not let them in
+        assert!(matches!(&htpasswd.dummy, Some(super::Hash::Bcrypt(_))));
+        assert!(!htpasswd.verify("nobody", "U*U"));
+        assert!(!Htpasswd::parse("").verify("nobody", ""));
+    }
+}
@@ -0,0 +1,102 @@
+/// Request size limits, in bytes unless noted otherwise.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    pub max_request_line: usize,
+    /// Maximum number of header fields.
+    pub max_headers: usize,
+    pub max_header_bytes: usize,
+    pub max_body: usize,
+}
+
+// responses may carry many cookies, so their heads get more room than requests
+pub(crate) const RESPONSE_LIMITS: Limits = Limits {
+    max_request_line: 8 * 1024,
+    max_headers: 1000,
+    max_header_bytes: 64 * 1024,
+    max_body: usize::MAX,
+};
+
+/// Which limit a request went over.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Exceeded {
+    RequestLine,
+    Headers,
+    Body,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            max_request_line: 8 * 1024,
+            max_headers: 100,
+            max_header_bytes: 8 * 1024,
+            max_body: 1024 * 1024,
+        }
+    }
+}
+
+impl Limits {
+    /// Check a request head, complete or still being received.
+    pub fn check_head(&self, head: &[u8]) -> Result<(), Exceeded> {
+        let line = find(head, b"\r\n").unwrap_or(head.len());
+        if line > self.max_request_line {
+            return Err(Exceeded::RequestLine);
+        }
+        let headers = head.get(line + 2..).unwrap_or_default();
+        // the blank line ending a complete head is not a field
+        let headers = headers.strip_suffix(b"\r\n").unwrap_or(headers);
+        let fields = headers.windows(2).filter(|w| w == b"\r\n").count();
+        if headers.len() > self.max_header_bytes || fields > self.max_headers {
+            return Err(Exceeded::Headers);
+        }
+        Ok(())
+    }
+
+    pub fn check_body(&self, len: usize) -> Result<(), Exceeded> {
+        if len > self.max_body { Err(Exceeded::Body) } else { Ok(()) }
+    }
+}
+
+impl Exceeded {
+    pub fn status(&self) -> (u16, &'static str) {
+        match self {
+            Exceeded::RequestLine => (414, "URI Too Long"),
+            Exceeded::Headers => (431, "Request Header Fields Too Large"),
+            Exceeded::Body => (413, "Payload Too Large"),
+        }
+    }
+}
+
+pub(crate) fn find(buf: &[u8], pattern: &[u8]) -> Option<usize> {
+    buf.windows(pattern.len()).position(|w| w == pattern)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Exceeded, Limits};
+
+    fn limits() -> Limits {
+        Limits {
+            max_request_line: 20,
+            max_headers: 2,
+            max_header_bytes: 40,
+            max_body: 10,
+        }
+    }
+
+    #[test]
+    fn test_check_head() {
+        let limits = limits();
+        assert_eq!(limits.check_head(b"GET / HTTP/1.1\r\nHost: a\r\nAccept: */*\r\n\r\n"), Ok(()));
+        assert_eq!(limits.check_head(b"GET /aaaaaaaaaaaaaaaaaaaa HTTP/1.1\r\n\r\n"), Err(Exceeded::RequestLine));
+        // a partial request line is checked too
+        assert_eq!(limits.check_head(b"GET /aaaaaaaaaaaaaaaaaaaa"), Err(Exceeded::RequestLine));
+        assert_eq!(limits.check_head(b"GET / HTTP/1.1\r\nA: 1\r\nB: 2\r\nC: 3\r\n\r\n"), Err(Exceeded::Headers));
+        assert_eq!(limits.check_head(b"GET / HTTP/1.1\r\nCookie: aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa"), Err(Exceeded::Headers));
+    }
+    #[test]
+    fn test_check_body() {
+        assert_eq!(limits().check_body(10), Ok(()));
+        assert_eq!(limits().check_body(11).unwrap_err().status().0, 413);
+    }
+}
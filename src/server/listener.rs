@@ -0,0 +1,301 @@
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+#[cfg(unix)]
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use rustls::{ServerConfig, ServerConnection, StreamOwned};
+use socket2::{Domain, Protocol, Socket, Type};
+
+const BACKLOG: i32 = 1024;
+
+/// A listening socket, either TCP or a Unix domain socket.
+/// The socket file of a Unix listener is removed when the listener is dropped.
+#[derive(Debug)]
+pub enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener, PathBuf),
+}
+
+/// An accepted connection.
+#[derive(Debug)]
+pub enum Stream {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+    Tls(Box<StreamOwned<ServerConnection, TcpStream>>),
+}
+
+impl Listener {
+    pub fn accept(&self) -> io::Result<Stream> {
+        match self {
+            Listener::Tcp(l) => l.accept().map(|(s, _)| Stream::Tcp(s)),
+            #[cfg(unix)]
+            Listener::Unix(l, _) => l.accept().map(|(s, _)| Stream::Unix(s)),
+        }
+    }
+
+    /// Connect to the listener once, so that a blocked `accept` returns.
+    pub fn wake(&self) {
+        match self {
+            Listener::Tcp(l) => {
+                if let Ok(mut addr) = l.local_addr() {
+                    if addr.ip().is_unspecified() {
+                        match addr {
+                            SocketAddr::V4(_) => addr.set_ip([127, 0, 0, 1].into()),
+                            SocketAddr::V6(_) => addr.set_ip(std::net::Ipv6Addr::LOCALHOST.into()),
+                        }
+                    }
+                    let _ = TcpStream::connect(addr);
+                }
+            },
+            #[cfg(unix)]
+            Listener::Unix(_, path) => {
+                let _ = UnixStream::connect(path);
+            },
+        }
+    }
+}
+
+impl Drop for Listener {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        {
+            if let Listener::Unix(_, path) = self {
+                let _ = fs::remove_file(path);
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for Listener {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Listener::Tcp(l) => match l.local_addr() {
+                Ok(addr) => write!(f, "{}", addr),
+                Err(_) => f.write_str("tcp"),
+            },
+            #[cfg(unix)]
+            Listener::Unix(_, path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+impl Stream {
+    /// Terminate TLS on a TCP connection, the handshake must complete within `timeout`.
+    /// Unix domain sockets are left as they are.
+    pub fn tls(self, config: &Arc<ServerConfig>, timeout: Duration) -> io::Result<Stream> {
+        match self {
+            Stream::Tcp(s) => {
+                s.set_read_timeout(Some(timeout))?;
+                let conn = ServerConnection::new(Arc::clone(config)).map_err(io::Error::other)?;
+                let mut tls = StreamOwned::new(conn, s);
+                while tls.conn.is_handshaking() {
+                    tls.conn.complete_io(&mut tls.sock)?;
+                }
+                Ok(Stream::Tls(Box::new(tls)))
+            },
+            stream => Ok(stream),
+        }
+    }
+
+    pub fn is_tls(&self) -> bool {
+        matches!(self, Stream::Tls(_))
+    }
+
+    /// The client address, None for Unix domain sockets.
+    pub fn peer_addr(&self) -> Option<SocketAddr> {
+        match self {
+            Stream::Tcp(s) => s.peer_addr().ok(),
+            #[cfg(unix)]
+            Stream::Unix(_) => None,
+            Stream::Tls(s) => s.sock.peer_addr().ok(),
+        }
+    }
+
+    pub fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        match self {
+            Stream::Tcp(s) => s.set_read_timeout(timeout),
+            #[cfg(unix)]
+            Stream::Unix(s) => s.set_read_timeout(timeout),
+            Stream::Tls(s) => s.sock.set_read_timeout(timeout),
+        }
+    }
+
+    pub fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        match self {
+            Stream::Tcp(s) => s.set_write_timeout(timeout),
+            #[cfg(unix)]
+            Stream::Unix(s) => s.set_write_timeout(timeout),
+            Stream::Tls(s) => s.sock.set_write_timeout(timeout),
+        }
+    }
+
+    pub fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        match self {
+            Stream::Tcp(s) => s.set_nonblocking(nonblocking),
+            #[cfg(unix)]
+            Stream::Unix(s) => s.set_nonblocking(nonblocking),
+            Stream::Tls(s) => s.sock.set_nonblocking(nonblocking),
+        }
+    }
+
+    /// A TLS connection sends its `close_notify` alert first.
+    pub fn shutdown(&mut self, how: Shutdown) -> io::Result<()> {
+        match self {
+            Stream::Tcp(s) => s.shutdown(how),
+            #[cfg(unix)]
+            Stream::Unix(s) => s.shutdown(how),
+            Stream::Tls(s) => {
+                s.conn.send_close_notify();
+                let _ = s.flush();
+                s.sock.shutdown(how)
+            },
+        }
+    }
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Stream::Tcp(s) => s.read(buf),
+            #[cfg(unix)]
+            Stream::Unix(s) => s.read(buf),
+            Stream::Tls(s) => s.read(buf),
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Stream::Tcp(s) => s.write(buf),
+            #[cfg(unix)]
+            Stream::Unix(s) => s.write(buf),
+            Stream::Tls(s) => s.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Stream::Tcp(s) => s.flush(),
+            #[cfg(unix)]
+            Stream::Unix(s) => s.flush(),
+            Stream::Tls(s) => s.flush(),
+        }
+    }
+}
+
+impl std::fmt::Display for Stream {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.peer_addr() {
+            Some(addr) => write!(f, "{}", addr),
+            None => f.write_str("unix"),
+        }
+    }
+}
+
+/// Resolve `host:port` into the addresses to listen on.
+/// The host may be an IPv4 address, a bracketed IPv6 address or a hostname,
+/// and may be omitted (`:8080`) to listen on all IPv4 interfaces.
+pub fn resolve(host: &str) -> io::Result<Vec<SocketAddr>> {
+    let host = if host.starts_with(':') {
+        format!("0.0.0.0{}", host)
+    } else {
+        host.to_string()
+    };
+    let mut addrs: Vec<SocketAddr> = Vec::new();
+    for addr in host.to_socket_addrs()? {
+        if !addrs.contains(&addr) {
+            addrs.push(addr);
+        }
+    }
+    if addrs.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::AddrNotAvailable,
+            format!("{} does not resolve to any address", host)));
+    }
+    Ok(addrs)
+}
+
+/// Open a listening socket on `addr`.
+/// With `dual_stack`, an IPv6 socket also accepts IPv4 clients as v4-mapped addresses,
+/// otherwise it is restricted to IPv6 so that `0.0.0.0` and `[::]` can share a port.
+pub fn bind(addr: &SocketAddr, dual_stack: bool) -> io::Result<Listener> {
+    let socket = Socket::new(Domain::for_address(*addr), Type::STREAM, Some(Protocol::TCP))?;
+    if addr.is_ipv6() {
+        socket.set_only_v6(!dual_stack)?;
+    }
+    socket.set_reuse_address(true)?;
+    socket.bind(&(*addr).into())?;
+    socket.listen(BACKLOG)?;
+    Ok(Listener::Tcp(socket.into()))
+}
+
+/// Open a Unix domain socket at `path`, optionally setting its permission bits.
+/// A stale socket file left by a previous run is replaced, any other existing file is an error.
+#[cfg(unix)]
+pub fn bind_unix(path: &Path, mode: Option<u32>) -> io::Result<Listener> {
+    if let Ok(meta) = fs::symlink_metadata(path) {
+        if !meta.file_type().is_socket() {
+            return Err(io::Error::new(io::ErrorKind::AlreadyExists,
+                format!("{} exists and is not a socket", path.display())));
+        }
+        if UnixStream::connect(path).is_ok() {
+            return Err(io::Error::new(io::ErrorKind::AddrInUse,
+                format!("{} is in use by another process", path.display())));
+        }
+        fs::remove_file(path)?;
+    }
+    let listener = UnixListener::bind(path)?;
+    // wrap first so that the file is removed if setting the permissions fails
+    let listener = Listener::Unix(listener, path.to_path_buf());
+    if let Some(mode) = mode {
+        fs::set_permissions(path, fs::Permissions::from_mode(mode))?;
+    }
+    Ok(listener)
+}
+
+#[cfg(not(unix))]
+pub fn bind_unix(path: &Path, _: Option<u32>) -> io::Result<Listener> {
+    Err(io::Error::new(io::ErrorKind::Unsupported,
+        format!("{}: Unix domain sockets are not supported on this platform", path.display())))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    #[test]
+    fn test_resolve() {
+        let v4: SocketAddr = "0.0.0.0:8080".parse().unwrap();
+        let v6: SocketAddr = "[::1]:8080".parse().unwrap();
+        assert_eq!(super::resolve(":8080").unwrap(), vec![v4]);
+        assert_eq!(super::resolve("[::1]:8080").unwrap(), vec![v6]);
+        assert!(super::resolve("::1").is_err());
+    }
+    #[test]
+    fn test_bind_ipv6() {
+        let listener = match super::bind(&"[::1]:0".parse().unwrap(), false) {
+            Ok(l) => l,
+            Err(_) => return, // no IPv6 on this host
+        };
+        assert!(listener.to_string().starts_with("[::1]:"));
+    }
+    #[test]
+    fn test_bind_unix() {
+        use std::os::unix::fs::PermissionsExt;
+        let path = std::env::temp_dir().join(format!("rushttp-test-{}.sock", std::process::id()));
+        let listener = super::bind_unix(&path, Some(0o600)).unwrap();
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        // a live socket must not be replaced
+        assert!(super::bind_unix(&path, None).is_err());
+        drop(listener);
+        assert!(!path.exists());
+    }
+}
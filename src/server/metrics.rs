@@ -0,0 +1,223 @@
+use std::collections::BTreeMap;
+use std::fmt::{self, Write};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Upper bounds of the latency histogram buckets, in seconds.
+const BUCKETS: [f64; 11] = [0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+/// Request, connection and worker statistics of a server,
+/// rendered in the Prometheus text exposition format.
+#[derive(Clone, Default)]
+pub struct Metrics {
+    inner: Arc<Inner>,
+}
+
+#[derive(Default)]
+struct Inner {
+    // (route, method, status)
+    requests: Mutex<BTreeMap<(String, String, u16), u64>>,
+    // (route, method)
+    latency: Mutex<BTreeMap<(String, String), Histogram>>,
+    request_bytes: AtomicU64,
+    response_bytes: AtomicU64,
+    connections: AtomicUsize,
+    workers: AtomicUsize,
+    busy_workers: AtomicUsize,
+}
+
+#[derive(Debug, Clone, Default)]
+struct Histogram {
+    // not cumulative, one count per bucket plus the +Inf one
+    counts: [u64; BUCKETS.len() + 1],
+    sum: f64,
+    count: u64,
+}
+
+/// What is recorded about a handled request.
+#[derive(Debug, Clone)]
+pub struct Sample<'a> {
+    pub route: &'a str,
+    pub method: &'a str,
+    pub status: u16,
+    pub duration: Duration,
+    pub request_bytes: usize,
+    pub response_bytes: usize,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Metrics::default()
+    }
+
+    pub fn observe(&self, sample: &Sample) {
+        let key = (sample.route.to_string(), sample.method.to_string());
+        if let Ok(mut requests) = self.inner.requests.lock() {
+            *requests.entry((key.0.clone(), key.1.clone(), sample.status)).or_insert(0) += 1;
+        }
+        if let Ok(mut latency) = self.inner.latency.lock() {
+            latency.entry(key).or_default().observe(sample.duration.as_secs_f64());
+        }
+        self.inner.request_bytes.fetch_add(sample.request_bytes as u64, Ordering::Relaxed);
+        self.inner.response_bytes.fetch_add(sample.response_bytes as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn set_workers(&self, workers: usize) {
+        self.inner.workers.store(workers, Ordering::Relaxed);
+    }
+
+    /// Count an accepted connection until the returned guard is dropped.
+    pub(crate) fn connection(&self) -> Guard {
+        Guard::new(&self.inner, |inner| &inner.connections)
+    }
+
+    /// Count a busy worker until the returned guard is dropped.
+    pub(crate) fn busy(&self) -> Guard {
+        Guard::new(&self.inner, |inner| &inner.busy_workers)
+    }
+
+    /// Render every metric in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        // writing to a String does not fail
+        let _ = self.write(&mut out);
+        out
+    }
+
+    fn write(&self, out: &mut String) -> fmt::Result {
+        header(out, "rushttp_requests_total", "counter", "Requests handled, by route, method and status.")?;
+        if let Ok(requests) = self.inner.requests.lock() {
+            for ((route, method, status), count) in requests.iter() {
+                writeln!(out, "rushttp_requests_total{{route=\"{}\",method=\"{}\",status=\"{}\"}} {}",
+                         escape(route), escape(method), status, count)?;
+            }
+        }
+
+        let name = "rushttp_request_duration_seconds";
+        header(out, name, "histogram", "Time to handle a request, by route and method.")?;
+        if let Ok(latency) = self.inner.latency.lock() {
+            for ((route, method), histogram) in latency.iter() {
+                let labels = format!("route=\"{}\",method=\"{}\"", escape(route), escape(method));
+                let mut cumulative = 0;
+                for (i, count) in histogram.counts.iter().enumerate() {
+                    cumulative += count;
+                    let le = BUCKETS.get(i).map(|b| b.to_string()).unwrap_or_else(|| "+Inf".to_string());
+                    writeln!(out, "{}_bucket{{{},le=\"{}\"}} {}", name, labels, le, cumulative)?;
+                }
+                writeln!(out, "{}_sum{{{}}} {}", name, labels, histogram.sum)?;
+                writeln!(out, "{}_count{{{}}} {}", name, labels, histogram.count)?;
+            }
+        }
+
+        let load = |n: &AtomicU64| n.load(Ordering::Relaxed);
+        header(out, "rushttp_request_bytes_total", "counter", "Bytes received in requests.")?;
+        writeln!(out, "rushttp_request_bytes_total {}", load(&self.inner.request_bytes))?;
+        header(out, "rushttp_response_bytes_total", "counter", "Bytes sent in responses.")?;
+        writeln!(out, "rushttp_response_bytes_total {}", load(&self.inner.response_bytes))?;
+
+        let load = |n: &AtomicUsize| n.load(Ordering::Relaxed);
+        header(out, "rushttp_active_connections", "gauge", "Connections accepted and not closed yet.")?;
+        writeln!(out, "rushttp_active_connections {}", load(&self.inner.connections))?;
+        header(out, "rushttp_workers", "gauge", "Worker threads in the pool.")?;
+        writeln!(out, "rushttp_workers {}", load(&self.inner.workers))?;
+        header(out, "rushttp_busy_workers", "gauge", "Worker threads handling a connection.")?;
+        writeln!(out, "rushttp_busy_workers {}", load(&self.inner.busy_workers))
+    }
+}
+
+impl fmt::Debug for Metrics {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Metrics")
+            .field("connections", &self.inner.connections.load(Ordering::Relaxed))
+            .finish()
+    }
+}
+
+impl Histogram {
+    fn observe(&mut self, secs: f64) {
+        let i = BUCKETS.iter().position(|b| secs <= *b).unwrap_or(BUCKETS.len());
+        self.counts[i] += 1;
+        self.sum += secs;
+        self.count += 1;
+    }
+}
+
+/// Decrements a gauge when dropped, so that early returns and panics are counted too.
+pub(crate) struct Guard {
+    inner: Arc<Inner>,
+    gauge: fn(&Inner) -> &AtomicUsize,
+}
+
+impl Guard {
+    fn new(inner: &Arc<Inner>, gauge: fn(&Inner) -> &AtomicUsize) -> Self {
+        gauge(inner).fetch_add(1, Ordering::Relaxed);
+        Guard {
+            inner: Arc::clone(inner),
+            gauge,
+        }
+    }
+}
+
+impl Drop for Guard {
+    fn drop(&mut self) {
+        (self.gauge)(&self.inner).fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) -> fmt::Result {
+    writeln!(out, "# HELP {} {}", name, help)?;
+    writeln!(out, "# TYPE {} {}", name, kind)
+}
+
+// label values escape backslash, double quote and line feed
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use super::{Metrics, Sample};
+
+    fn sample(status: u16, millis: u64) -> Sample<'static> {
+        Sample {
+            route: "/",
+            method: "GET",
+            status,
+            duration: Duration::from_millis(millis),
+            request_bytes: 40,
+            response_bytes: 100,
+        }
+    }
+
+    #[test]
+    fn test_render() {
+        let metrics = Metrics::new();
+        metrics.observe(&sample(200, 3));
+        metrics.observe(&sample(200, 300));
+        metrics.observe(&sample(404, 20));
+        let out = metrics.render();
+        assert!(out.contains("# TYPE rushttp_requests_total counter\n"));
+        assert!(out.contains("rushttp_requests_total{route=\"/\",method=\"GET\",status=\"200\"} 2\n"));
+        assert!(out.contains("rushttp_requests_total{route=\"/\",method=\"GET\",status=\"404\"} 1\n"));
+        assert!(out.contains("rushttp_request_duration_seconds_bucket{route=\"/\",method=\"GET\",le=\"0.005\"} 1\n"));
+        assert!(out.contains("rushttp_request_duration_seconds_bucket{route=\"/\",method=\"GET\",le=\"0.25\"} 2\n"));
+        assert!(out.contains("rushttp_request_duration_seconds_bucket{route=\"/\",method=\"GET\",le=\"+Inf\"} 3\n"));
+        assert!(out.contains("rushttp_request_duration_seconds_count{route=\"/\",method=\"GET\"} 3\n"));
+        assert!(out.contains("rushttp_request_bytes_total 120\n"));
+        assert!(out.contains("rushttp_response_bytes_total 300\n"));
+    }
+    #[test]
+    fn test_guard() {
+        let metrics = Metrics::new();
+        let guard = metrics.connection();
+        assert!(metrics.render().contains("rushttp_active_connections 1\n"));
+        drop(guard);
+        assert!(metrics.render().contains("rushttp_active_connections 0\n"));
+    }
+    #[test]
+    fn test_escape() {
+        assert_eq!(super::escape("a\"b\\c\nd"), "a\\\"b\\\\c\\nd");
+    }
+}
@@ -0,0 +1,73 @@
+use std::sync::Arc;
+use crate::http::request::Request;
+use crate::http::response::Response;
+
+/// Code run around the handlers of a virtual host.
+/// A middleware either answers the request itself or passes it on with `next.run(req)`,
+/// and may change the response on its way back.
+pub trait Middleware: Send + Sync {
+    fn handle(&self, req: Request<String>, next: Next<'_>) -> Response<Vec<u8>>;
+}
+
+impl<F> Middleware for F
+    where F: Fn(Request<String>, Next<'_>) -> Response<Vec<u8>> + Send + Sync
+{
+    fn handle(&self, req: Request<String>, next: Next<'_>) -> Response<Vec<u8>> {
+        self(req, next)
+    }
+}
+
+/// The rest of the middleware chain, ending with the route handler.
+pub struct Next<'a> {
+    chain: &'a [Arc<dyn Middleware>],
+    endpoint: &'a dyn Fn(Request<String>) -> Response<Vec<u8>>,
+}
+
+impl<'a> Next<'a> {
+    pub fn new(chain: &'a [Arc<dyn Middleware>], endpoint: &'a dyn Fn(Request<String>) -> Response<Vec<u8>>) -> Self {
+        Next {
+            chain,
+            endpoint,
+        }
+    }
+
+    pub fn run(self, req: Request<String>) -> Response<Vec<u8>> {
+        match self.chain.split_first() {
+            Some((first, rest)) => first.handle(req, Next::new(rest, self.endpoint)),
+            None => (self.endpoint)(req),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use super::{Middleware, Next, Request, Response};
+
+    fn tag(req: Request<String>, next: Next<'_>) -> Response<Vec<u8>> {
+        let mut res = next.run(req);
+        res.header_mut().add("X-Tag", "1");
+        res
+    }
+
+    fn deny(_: Request<String>, _: Next<'_>) -> Response<Vec<u8>> {
+        Response::new(b"denied".to_vec())
+    }
+
+    #[test]
+    fn test_run() {
+        let chain: Vec<Arc<dyn Middleware>> = vec![Arc::new(tag)];
+        let endpoint = |req: Request<String>| Response::new(req.body().clone().into_bytes());
+        let res = Next::new(&chain, &endpoint).run(Request::new("ok".to_string()));
+        assert_eq!(res.body(), b"ok");
+        assert_eq!(res.header().get("X-Tag"), Some("1"));
+    }
+    #[test]
+    fn test_run_short_circuit() {
+        let chain: Vec<Arc<dyn Middleware>> = vec![Arc::new(tag), Arc::new(deny)];
+        let endpoint = |_: Request<String>| -> Response<Vec<u8>> { panic!("endpoint must not run") };
+        let res = Next::new(&chain, &endpoint).run(Request::new("ok".to_string()));
+        assert_eq!(res.body(), b"denied");
+        assert_eq!(res.header().get("X-Tag"), Some("1"));
+    }
+}
@@ -1,21 +1,35 @@
-use std::net::{Ipv4Addr, TcpListener, TcpStream, Shutdown};
-use std::collections::HashMap;
-use crate::http::request::Request;
-use std::string::ParseError;
-use std::str::FromStr;
-use std::io::Read;
-use std::{thread, fmt};
-use std::env;
-use crate::http::parser::Parser;
+use std::net::SocketAddr;
 use std::path::{Path, PathBuf};
-use std::borrow::Borrow;
+use crate::http::request::Request;
+use crate::http::multipart::Limits as MultipartLimits;
+use std::{fmt, thread};
 use crate::server::error::Error;
 // use crate::server::context::Context;
+use crate::server::extract::Endpoint;
+use crate::server::connections::ConnectionLimits;
 use thiserror::Error;
-use std::ops::Deref;
+use log::{debug, info, trace, warn};
 use std::sync::Arc;
-use crate::server::handler::{Handler, Handlers};
-use crate::http::method::Method;
+use crate::server::pool::ThreadPool;
+use crate::server::listener::{Listener, Stream};
+use crate::server::context::Context;
+pub use crate::server::access_log::{AccessLog, Format as LogFormat};
+pub use crate::server::metrics::Metrics;
+pub use crate::server::reader::Timeouts;
+pub use crate::server::limits::Limits;
+pub use crate::server::proxy::Proxy;
+pub use crate::server::upstream::{Balance, HashKey, HealthCheck, Upstream};
+pub use crate::server::rate_limit::{Key as RateLimitKey, RateLimiter};
+pub use crate::server::shutdown::Shutdown;
+pub use crate::server::middleware::{Middleware, Next};
+pub use crate::server::session::{FileStore, MemoryStore, Session, SessionStore, Sessions};
+pub use crate::server::auth::{AuthUser, BasicAuth, Credentials};
+pub use crate::server::htpasswd::Htpasswd;
+pub use crate::server::htdigest::Htdigest;
+pub use crate::server::digest::{Algorithm as DigestAlgorithm, DigestAuth, DigestCredentials};
+#[cfg(feature = "jwt")]
+pub use crate::server::jwt::{Claims, JwtAuth, JwtKey, KeyError};
+pub use crate::server::vhost::{VirtualHost, VirtualHosts};
 
 
 mod resource;
@@ -23,79 +37,354 @@ mod response;
 mod context;
 mod handler;
 mod error;
+mod pool;
+pub(crate) mod listener;
+mod middleware;
+mod vhost;
+mod connection;
+mod shutdown;
+mod access_log;
+mod metrics;
+pub(crate) mod reader;
+pub(crate) mod limits;
+mod rate_limit;
+mod connections;
+mod proxy;
+mod upstream;
+pub(crate) mod crypto;
+mod session;
+pub(crate) mod auth;
+mod htpasswd;
+mod htdigest;
+pub(crate) mod digest;
+#[cfg(feature = "jwt")]
+mod jwt;
+mod tls;
+pub mod extract;
 
 #[derive(Debug, Clone)]
 pub struct Server {
-    addr: Ipv4Addr,
-    port: usize,
-    root: PathBuf,
-    handlers: Handlers<String>,
+    addrs: Vec<SocketAddr>,
+    unix: Vec<(PathBuf, Option<u32>)>,
+    dual_stack: bool,
+    threads: usize,
+    hosts: VirtualHosts,
+    access_log: Option<AccessLog>,
+    metrics: Option<Metrics>,
+    metrics_path: String,
+    admin_addrs: Vec<SocketAddr>,
+    timeouts: Timeouts,
+    limits: Limits,
+    max_connections: Option<usize>,
+    max_connections_per_ip: Option<usize>,
+    shutdown: Shutdown,
+    tls: Option<Arc<rustls::ServerConfig>>,
 }
 
 impl Server {
     pub fn new(root: &str) -> Self {
         Server {
-            addr: Ipv4Addr::new(0,0,0,0),
-            port: 80,
-            root: Path::new(root).to_path_buf(),
-            handlers: Handlers::new(root)
+            addrs: Vec::new(),
+            unix: Vec::new(),
+            dual_stack: false,
+            threads: 4,
+            hosts: VirtualHosts::new(VirtualHost::new(root)),
+            access_log: None,
+            metrics: None,
+            metrics_path: "/metrics".to_string(),
+            admin_addrs: Vec::new(),
+            timeouts: Timeouts::default(),
+            limits: Limits::default(),
+            max_connections: None,
+            max_connections_per_ip: None,
+            shutdown: Shutdown::default(),
+            tls: None,
         }
     }
 
     pub fn register(&mut self, path: &str, method: &str, handler: fn(Request<String>) -> String) {
-        let handler = Handler::new((&self.root).to_path_buf(), path, method, handler);
-        self.handlers.add(handler);
+        self.hosts.default_mut().register(path, method, handler);
     }
 
+    /// Register a handler with typed arguments, `path` may hold `{name}` segments for `extract::Path`.
+    pub fn handle<E: Endpoint<Args>, Args>(&mut self, path: &str, method: &str, endpoint: E) {
+        self.hosts.default_mut().handle(path, method, endpoint);
+    }
 
+    /// Share `state` with the handlers of the default virtual host as `extract::State<T>`.
+    pub fn state<T: Clone + Send + Sync + 'static>(&mut self, state: T) {
+        self.hosts.default_mut().state(state);
+    }
 
-    pub fn bind(self, host: &str) -> Self {
-        let mut addr_port = host.split(":");
-        let addr = match addr_port.next() {
-            Some(a) => match a {
-                "" => self.addr,
-                _ => Ipv4Addr::from_str(a).unwrap(),
-            },
-            None => self.addr,
-        };
-        let port: usize = match addr_port.next() {
-            Some(p) => p.parse().unwrap(),
-            None => self.port,
-        };
+    /// Serve files under `dir` for request paths starting with `prefix`.
+    pub fn mount(&mut self, prefix: &str, dir: &str) {
+        self.hosts.default_mut().mount(prefix, dir);
+    }
+
+    /// Add a header to every response.
+    pub fn header(&mut self, name: &str, value: &str) {
+        self.hosts.default_mut().header(name, value);
+    }
+
+    /// Forward requests of the default virtual host for paths starting with `prefix` to an upstream.
+    pub fn proxy(&mut self, prefix: &str, proxy: Proxy) {
+        self.hosts.default_mut().proxy(prefix, proxy);
+    }
+
+    /// Add a middleware to the default virtual host.
+    pub fn middleware<M: Middleware + 'static>(&mut self, middleware: M) {
+        self.hosts.default_mut().middleware(middleware);
+    }
+
+    /// Add a name-based virtual host. Requests for unknown hosts go to the default one,
+    /// which is configured through the other methods of `Server`.
+    pub fn vhost(&mut self, host: VirtualHost) {
+        self.hosts.add(host);
+    }
+
+    /// Set the number of worker threads handling connections.
+    pub fn threads(self, threads: usize) -> Self {
         Server {
-            addr,
-            port,
-            root: self.root,
-            handlers: self.handlers
-        }
-    }
-
-    pub fn serve(&self) {
-        let addr = format!("{}:{}",&self.addr.to_string(), &self.port.to_string());
-        let listener = TcpListener::bind(addr).expect("[error] failed to bind");
-
-        // TODO refactor
-        for stream in listener.incoming() {
-            match stream {
-                Ok(stream) => {
-                    println!("New connection: {}", stream.peer_addr().unwrap());
-                    let handlers = self.handlers.clone();
-                    thread::spawn( move || {
-                        match handlers.handle(stream) {
-                            Ok(_) => {
-                                println!("[info] exec handler function");
-                            },
-                            Err(e) => {
-                                println!("[error] failed to handle: {:?}", e);
-                            },
-                        }
-                    });
-                },
-                Err(e) => {
-                    println!("[error] failed to read: {:?}", e);
-                },
+            threads,
+            ..self
+        }
+    }
+
+    /// Let IPv6 listeners accept IPv4 clients too.
+    pub fn dual_stack(self, dual_stack: bool) -> Self {
+        Server {
+            dual_stack,
+            ..self
+        }
+    }
+
+    /// Set the read, write and keep-alive timeouts of connections.
+    pub fn timeouts(self, timeouts: Timeouts) -> Self {
+        Server {
+            timeouts,
+            ..self
+        }
+    }
+
+    /// Set the request size limits.
+    pub fn limits(self, limits: Limits) -> Self {
+        Server {
+            limits,
+            ..self
+        }
+    }
+
+    /// Override the limits for request paths of the default virtual host starting with `prefix`.
+    pub fn limit(&mut self, prefix: &str, limits: Limits) {
+        self.hosts.default_mut().limit(prefix, limits);
+    }
+
+    /// Parse `multipart/form-data` uploads to paths of the default virtual host starting with `prefix`
+    /// as they arrive, see `VirtualHost::multipart`.
+    pub fn multipart(&mut self, prefix: &str, limits: MultipartLimits) {
+        self.hosts.default_mut().multipart(prefix, limits);
+    }
+
+    /// Cap the connections open at the same time, further ones get `503 Service Unavailable`.
+    pub fn max_connections(self, max: usize) -> Self {
+        Server {
+            max_connections: Some(max),
+            ..self
+        }
+    }
+
+    /// Cap the connections open at the same time from one client IP address.
+    pub fn max_connections_per_ip(self, max: usize) -> Self {
+        Server {
+            max_connections_per_ip: Some(max),
+            ..self
+        }
+    }
+
+    /// Log every request to `log`.
+    pub fn access_log(self, log: AccessLog) -> Self {
+        Server {
+            access_log: Some(log),
+            ..self
+        }
+    }
+
+    /// Collect metrics and serve them at `path` in the Prometheus text format.
+    pub fn metrics(self, path: &str) -> Self {
+        Server {
+            metrics: Some(self.metrics.unwrap_or_default()),
+            metrics_path: path.to_string(),
+            ..self
+        }
+    }
+
+    /// Terminate TLS on the TCP listeners with a PEM certificate chain and private key.
+    /// Unix domain sockets and the admin listeners stay plain.
+    pub fn tls(self, cert: &Path, key: &Path) -> Result<Self, Error> {
+        let config = tls::config(cert, key)?;
+        Ok(Server {
+            tls: Some(config),
+            ..self
+        })
+    }
+
+    /// Serve the metrics on a separate admin listener at `host` instead of the regular listeners.
+    /// Metrics are enabled at `/metrics` unless `metrics` set another path.
+    pub fn metrics_bind(self, host: &str) -> Result<Self, Error> {
+        let mut admin_addrs = self.admin_addrs;
+        for addr in listener::resolve(host)? {
+            if !admin_addrs.contains(&addr) {
+                admin_addrs.push(addr);
             }
         }
+        Ok(Server {
+            metrics: Some(self.metrics.unwrap_or_default()),
+            admin_addrs,
+            ..self
+        })
+    }
+
+    /// The metrics collected by `serve`, if enabled.
+    pub fn metrics_handle(&self) -> Option<Metrics> {
+        self.metrics.clone()
+    }
+
+    /// Describe the registered routes, one per line.
+    pub fn routes(&self) -> Vec<String> {
+        self.hosts.routes()
+    }
+
+    /// Add a listener. `host` is any `host:port` form such as `127.0.0.1:8080`,
+    /// `[::1]:8080`, `localhost:8080` or `:8080`, a hostname listens on every address it resolves to.
+    /// Without any listener the server listens on `0.0.0.0:80`.
+    pub fn bind(self, host: &str) -> Result<Self, Error> {
+        let mut addrs = self.addrs;
+        for addr in listener::resolve(host)? {
+            if !addrs.contains(&addr) {
+                addrs.push(addr);
+            }
+        }
+        Ok(Server {
+            addrs,
+            ..self
+        })
+    }
+
+    /// Add a Unix domain socket listener at `path`, with the permission bits
+    /// of the socket file set to `mode` when given. The file is removed on shutdown.
+    pub fn bind_unix(self, path: &str, mode: Option<u32>) -> Self {
+        let mut unix = self.unix;
+        unix.push((Path::new(path).to_path_buf(), mode));
+        Server {
+            unix,
+            ..self
+        }
+    }
+
+    /// A handle to stop `serve` from another thread.
+    pub fn shutdown_handle(&self) -> Shutdown {
+        self.shutdown.clone()
+    }
+
+    /// Accept connections until shut down through `shutdown_handle`.
+    pub fn serve(&self) -> Result<(), Error> {
+        let addrs = if self.addrs.is_empty() && self.unix.is_empty() {
+            vec![SocketAddr::from(([0, 0, 0, 0], 80))]
+        } else {
+            self.addrs.clone()
+        };
+        // the flag marks admin listeners
+        let mut listeners = Vec::new();
+        for addr in addrs.iter() {
+            listeners.push((listener::bind(addr, self.dual_stack)?, false));
+        }
+        for (path, mode) in self.unix.iter() {
+            listeners.push((listener::bind_unix(path, *mode)?, false));
+        }
+        for addr in self.admin_addrs.iter() {
+            listeners.push((listener::bind(addr, self.dual_stack)?, true));
+        }
+        let pool = Arc::new(ThreadPool::new(self.threads));
+        if let Some(metrics) = &self.metrics {
+            metrics.set_workers(self.threads.max(1));
+        }
+        let ctx = Arc::new(Context {
+            hosts: self.hosts.clone(),
+            access_log: self.access_log.clone(),
+            metrics: self.metrics.clone(),
+            metrics_path: self.metrics_path.clone(),
+            admin_only: !self.admin_addrs.is_empty(),
+            timeouts: self.timeouts,
+            limits: self.limits,
+            connections: ConnectionLimits::new(self.max_connections, self.max_connections_per_ip),
+            shutdown: self.shutdown.clone(),
+            tls: self.tls.clone(),
+        });
+        // the checks stop with the server
+        for upstream in self.hosts.upstreams() {
+            upstream.spawn_health_check(self.shutdown.clone());
+        }
+        let accepts: Vec<thread::JoinHandle<()>> = listeners.into_iter()
+            .map(|(listener, admin)| {
+                if admin {
+                    info!("serve metrics on {}", listener);
+                } else {
+                    info!("listen on {}", listener);
+                }
+                let listener = Arc::new(listener);
+                self.shutdown.register(Arc::clone(&listener));
+                let pool = Arc::clone(&pool);
+                let ctx = Arc::clone(&ctx);
+                let shutdown = self.shutdown.clone();
+                thread::spawn(move || accept(listener, admin, ctx, pool, shutdown))
+            })
+            .collect();
+        for accept in accepts {
+            let _ = accept.join();
+        }
+        // drops the last reference to each listener, which removes the unix socket files
+        self.shutdown.clear();
+        Ok(())
+    }
+}
+
+fn accept(listener: Arc<Listener>, admin: bool, ctx: Arc<Context>, pool: Arc<ThreadPool>, shutdown: Shutdown) {
+    while !shutdown.is_triggered() {
+        match listener.accept() {
+            Ok(stream) => {
+                if shutdown.is_triggered() {
+                    break;
+                }
+                trace!("new connection from {}", stream);
+                // the admin listeners are not capped, so that metrics can be scraped under load
+                let permit = if admin {
+                    None
+                } else {
+                    match ctx.connections.acquire(stream.peer_addr().map(|addr| addr.ip())) {
+                        Some(permit) => Some(permit),
+                        None => {
+                            debug!("too many connections, rejecting {}", stream);
+                            let tls = ctx.tls.is_some() && matches!(stream, Stream::Tcp(_));
+                            connection::reject(stream, tls);
+                            continue;
+                        },
+                    }
+                };
+                let active = ctx.metrics.as_ref().map(|m| m.connection());
+                let ctx = Arc::clone(&ctx);
+                pool.execute(move || {
+                    let _busy = ctx.metrics.as_ref().map(|m| m.busy());
+                    let _active = active;
+                    let _permit = permit;
+                    if let Err(e) = connection::handle(stream, &ctx, admin) {
+                        debug!("failed to handle connection: {:?}", e);
+                    }
+                });
+            },
+            Err(e) => {
+                warn!("failed to accept on {}: {}", listener, e);
+            },
+        }
     }
 }
 
@@ -157,6 +446,270 @@ mod tests {
     #[test]
     fn test_bind() {
         let server = super::Server::new("/static/assets/html");
-        assert_eq!(server.bind(":8080").port, 8080);
+        assert_eq!(server.bind(":8080").unwrap().addrs[0].port(), 8080);
+    }
+    #[cfg(unix)]
+    #[test]
+    fn test_serve_unix() {
+        use std::io::{Read, Write};
+        use std::os::unix::net::UnixStream;
+        let path = std::env::temp_dir().join(format!("rushttp-serve-{}.sock", std::process::id()));
+        let mut server = super::Server::new("src/static/assets/html")
+            .bind_unix(path.to_str().unwrap(), Some(0o660));
+        server.mount("/", "src/static/assets/html");
+        let shutdown = server.shutdown_handle();
+        let serve = std::thread::spawn(move || server.serve());
+        let mut stream = loop {
+            match UnixStream::connect(&path) {
+                Ok(s) => break s,
+                Err(_) => std::thread::sleep(std::time::Duration::from_millis(10)),
+            }
+        };
+        stream.write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n").unwrap();
+        let mut res = String::new();
+        stream.read_to_string(&mut res).unwrap();
+        assert!(res.starts_with("HTTP/1.1 200 OK\r\n"));
+        shutdown.trigger();
+        serve.join().unwrap().unwrap();
+        assert!(!path.exists());
+    }
+    #[cfg(unix)]
+    #[test]
+    fn test_serve_reject() {
+        use std::io::{Read, Write};
+        use std::os::unix::net::UnixStream;
+        let path = std::env::temp_dir().join(format!("rushttp-reject-{}.sock", std::process::id()));
+        let mut server = super::Server::new("src/static/assets/html")
+            .bind_unix(path.to_str().unwrap(), None)
+            .max_connections(1);
+        server.mount("/", "src/static/assets/html");
+        let shutdown = server.shutdown_handle();
+        let serve = std::thread::spawn(move || server.serve());
+        let mut first = loop {
+            match UnixStream::connect(&path) {
+                Ok(s) => break s,
+                Err(_) => std::thread::sleep(std::time::Duration::from_millis(10)),
+            }
+        };
+        // once a response arrives the first connection holds the only permit
+        first.write_all(b"HEAD / HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+        let mut buf = [0u8; 1024];
+        assert!(first.read(&mut buf).unwrap() > 0);
+        let mut second = UnixStream::connect(&path).unwrap();
+        let mut res = String::new();
+        second.read_to_string(&mut res).unwrap();
+        assert!(res.starts_with("HTTP/1.1 503 "));
+        drop(first);
+        shutdown.trigger();
+        serve.join().unwrap().unwrap();
+    }
+    #[cfg(unix)]
+    #[test]
+    fn test_serve_handle() {
+        use std::io::{Read, Write};
+        use std::net::SocketAddr;
+        use std::os::unix::net::UnixStream;
+        use super::extract::{Path, Query, State};
+        #[derive(serde::Deserialize)]
+        struct Page {
+            page: u32,
+        }
+        let path = std::env::temp_dir().join(format!("rushttp-handle-{}.sock", std::process::id()));
+        let mut server = super::Server::new("src/static/assets/html")
+            .bind_unix(path.to_str().unwrap(), None);
+        server.state(String::from("posts"));
+        server.handle("/users/{id}/posts", "GET",
+            |Path(id): Path<u32>, Query(q): Query<Page>, State(kind): State<String>, addr: Option<SocketAddr>| {
+                format!("{} of {} page {} {}", kind, id, q.page, addr.is_none())
+            });
+        let shutdown = server.shutdown_handle();
+        let serve = std::thread::spawn(move || server.serve());
+        let get = |target: &str| {
+            let mut stream = loop {
+                match UnixStream::connect(&path) {
+                    Ok(s) => break s,
+                    Err(_) => std::thread::sleep(std::time::Duration::from_millis(10)),
+                }
+            };
+            write!(stream, "GET {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n", target).unwrap();
+            let mut res = Vec::new();
+            stream.read_to_end(&mut res).unwrap();
+            String::from_utf8_lossy(&res).to_string()
+        };
+        let res = get("/users/7/posts?page=2");
+        assert!(res.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(res.ends_with("posts of 7 page 2 true"));
+        assert!(get("/users/x/posts?page=2").starts_with("HTTP/1.1 400 "));
+        assert!(get("/users/7/posts").starts_with("HTTP/1.1 400 "));
+        assert!(get("/users/7").starts_with("HTTP/1.1 404 "));
+        shutdown.trigger();
+        serve.join().unwrap().unwrap();
+    }
+    #[cfg(unix)]
+    #[test]
+    fn test_serve_proxy_chunked() {
+        use std::io::{Read, Write};
+        use std::net::TcpListener;
+        use std::os::unix::net::UnixStream;
+        use crate::server::limits::find;
+        // answers with the request it got, the chunked body with its framing
+        let upstream = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = upstream.local_addr().unwrap().to_string();
+        std::thread::spawn(move || {
+            for conn in upstream.incoming() {
+                let mut conn = conn.unwrap();
+                let mut req = Vec::new();
+                let mut buf = [0u8; 1024];
+                // up to the last chunk and the end of the trailer section
+                while find(&req, b"\r\n0\r\n").is_none() || !req.ends_with(b"\r\n\r\n") {
+                    match conn.read(&mut buf) {
+                        Ok(0) | Err(_) => break,
+                        Ok(n) => req.extend_from_slice(&buf[..n]),
+                    }
+                }
+                let _ = write!(conn, "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n", req.len());
+                let _ = conn.write_all(&req);
+            }
+        });
+        let path = std::env::temp_dir().join(format!("rushttp-proxy-{}.sock", std::process::id()));
+        let mut server = super::Server::new("src/static/assets/html")
+            .bind_unix(path.to_str().unwrap(), None);
+        server.mount("/", "src/static/assets/html");
+        server.proxy("/api", super::Proxy::new(&addr));
+        server.limit("/api/small", super::Limits { max_body: 16, ..Default::default() });
+        let shutdown = server.shutdown_handle();
+        let serve = std::thread::spawn(move || server.serve());
+        let send = |target: &str, headers: &str, body: &str| {
+            let mut stream = loop {
+                match UnixStream::connect(&path) {
+                    Ok(s) => break s,
+                    Err(_) => std::thread::sleep(std::time::Duration::from_millis(10)),
+                }
+            };
+            write!(stream, "POST {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n{}\r\n{}", target, headers, body).unwrap();
+            let mut res = Vec::new();
+            stream.read_to_end(&mut res).unwrap();
+            String::from_utf8_lossy(&res).to_string()
+        };
+        let body = "5\r\nhello\r\n6;ext=1\r\n world\r\n0\r\nX-Trailer: t\r\n\r\n";
+        let res = send("/api/echo", "Transfer-Encoding: chunked\r\n", body);
+        assert!(res.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(res.contains("POST /api/echo HTTP/1.1\r\n"));
+        assert!(res.contains("\r\nTransfer-Encoding: chunked\r\n"));
+        assert!(res.ends_with(&format!("\r\n\r\n{}", body)));
+        assert!(send("/api/small", "Transfer-Encoding: chunked\r\n", body).starts_with("HTTP/1.1 413 "));
+        assert!(send("/api/echo", "Transfer-Encoding: chunked\r\n", "ffffffffffffffff\r\nhello\r\n0\r\n\r\n").starts_with("HTTP/1.1 400 "));
+        assert!(send("/api/echo", "Transfer-Encoding: gzip, chunked\r\n", body).starts_with("HTTP/1.1 501 "));
+        assert!(send("/api/echo", "Transfer-Encoding: chunked\r\nContent-Length: 5\r\n", body).starts_with("HTTP/1.1 400 "));
+        // lengths the upstream could read another way
+        assert!(send("/api/echo", "Content-Length: 0\r\nContent-Length: 5\r\n", "hello").starts_with("HTTP/1.1 400 "));
+        assert!(send("/api/echo", "Content-Length: +5\r\n", "hello").starts_with("HTTP/1.1 400 "));
+        // only proxies take chunked bodies
+        assert!(send("/index.html", "Transfer-Encoding: chunked\r\n", body).starts_with("HTTP/1.1 501 "));
+        shutdown.trigger();
+        serve.join().unwrap().unwrap();
+    }
+    #[cfg(unix)]
+    #[test]
+    fn test_serve_multipart() {
+        use std::io::{Read, Write};
+        use std::os::unix::net::UnixStream;
+        use crate::http::multipart::Multipart;
+        use crate::http::request::Request;
+        use crate::http::response::Response;
+        use crate::server::middleware::Next;
+        let path = std::env::temp_dir().join(format!("rushttp-multipart-{}.sock", std::process::id()));
+        let mut server = super::Server::new("src/static/assets/html")
+            .bind_unix(path.to_str().unwrap(), None);
+        server.mount("/", "src/static/assets/html");
+        server.multipart("/upload", super::MultipartLimits { memory_threshold: 16, max_file_size: 1024, ..Default::default() });
+        server.middleware(|req: Request<String>, next: Next<'_>| {
+            match req.extensions().get::<Multipart>() {
+                Some(multipart) => {
+                    let file = multipart.get("file").unwrap();
+                    let summary = format!("{} {} {}", multipart.text("title").unwrap(), file.len(), file.path().is_some());
+                    Response::new(summary.into_bytes())
+                },
+                None => next.run(req),
+            }
+        });
+        let shutdown = server.shutdown_handle();
+        let serve = std::thread::spawn(move || server.serve());
+        let send = |file: &[u8]| {
+            let mut stream = loop {
+                match UnixStream::connect(&path) {
+                    Ok(s) => break s,
+                    Err(_) => std::thread::sleep(std::time::Duration::from_millis(10)),
+                }
+            };
+            let mut body = b"--b\r\nContent-Disposition: form-data; name=\"title\"\r\n\r\nphoto\r\n\
+                --b\r\nContent-Disposition: form-data; name=\"file\"; filename=\"a.bin\"\r\n\r\n".to_vec();
+            body.extend_from_slice(file);
+            body.extend_from_slice(b"\r\n--b--\r\n");
+            write!(stream, "POST /upload HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\
+                Content-Type: multipart/form-data; boundary=b\r\nContent-Length: {}\r\n\r\n", body.len()).unwrap();
+            stream.write_all(&body).unwrap();
+            let mut res = Vec::new();
+            stream.read_to_end(&mut res).unwrap();
+            String::from_utf8_lossy(&res).to_string()
+        };
+        // the file is not UTF-8, which a plain body could not be
+        let res = send(&[0xff; 100]);
+        assert!(res.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(res.ends_with("photo 100 true"));
+        assert!(send(&[0xff; 2000]).starts_with("HTTP/1.1 413 "));
+        shutdown.trigger();
+        serve.join().unwrap().unwrap();
+    }
+    #[cfg(unix)]
+    #[test]
+    fn test_serve_metrics() {
+        use std::io::{Read, Write};
+        use std::os::unix::net::UnixStream;
+        let path = std::env::temp_dir().join(format!("rushttp-metrics-{}.sock", std::process::id()));
+        let mut server = super::Server::new("src/static/assets/html")
+            .bind_unix(path.to_str().unwrap(), None)
+            .metrics("/metrics");
+        server.mount("/", "src/static/assets/html");
+        let shutdown = server.shutdown_handle();
+        let serve = std::thread::spawn(move || server.serve());
+        let get = |target: &str| {
+            let mut stream = loop {
+                match UnixStream::connect(&path) {
+                    Ok(s) => break s,
+                    Err(_) => std::thread::sleep(std::time::Duration::from_millis(10)),
+                }
+            };
+            write!(stream, "GET {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n", target).unwrap();
+            let mut res = String::new();
+            stream.read_to_string(&mut res).unwrap();
+            res
+        };
+        get("/");
+        get("/missing/page");
+        // the earlier connections are counted until their workers finish, shortly after the client sees them closed
+        let mut res = get("/metrics");
+        for _ in 0..100 {
+            if res.contains("rushttp_active_connections 1\n") {
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(10));
+            res = get("/metrics");
+        }
+        assert!(res.contains("rushttp_requests_total{route=\"/\",method=\"GET\",status=\"200\"} 1\n"));
+        assert!(res.contains("rushttp_requests_total{route=\"/\",method=\"GET\",status=\"404\"} 1\n"));
+        assert!(res.contains("rushttp_active_connections 1\n"));
+        shutdown.trigger();
+        serve.join().unwrap().unwrap();
+    }
+    #[test]
+    fn test_bind_multiple() {
+        let server = super::Server::new("/static/assets/html")
+            .bind("127.0.0.1:8080").unwrap()
+            .bind("[::]:8080").unwrap();
+        assert_eq!(server.addrs, vec![
+            "127.0.0.1:8080".parse::<super::SocketAddr>().unwrap(),
+            "[::]:8080".parse::<super::SocketAddr>().unwrap(),
+        ]);
     }
 }
\ No newline at end of file
@@ -0,0 +1,95 @@
+use std::any::Any;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use log::error;
+
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+/// A fixed number of worker threads handling connections.
+pub struct ThreadPool {
+    workers: Vec<thread::JoinHandle<()>>,
+    sender: Option<mpsc::Sender<Job>>,
+}
+
+impl ThreadPool {
+    pub fn new(size: usize) -> Self {
+        let size = size.max(1);
+        let (sender, receiver) = mpsc::channel::<Job>();
+        let receiver = Arc::new(Mutex::new(receiver));
+        let workers = (0..size).map(|id| {
+            let receiver = Arc::clone(&receiver);
+            thread::Builder::new()
+                .name(format!("rushttp-worker-{}", id))
+                .spawn(move || loop {
+                    let job = match receiver.lock() {
+                        Ok(r) => r.recv(),
+                        Err(_) => return,
+                    };
+                    match job {
+                        // a panicking job only loses its own connection, the worker keeps going
+                        Ok(job) => if let Err(panic) = panic::catch_unwind(AssertUnwindSafe(job)) {
+                            error!("worker {} recovered from a panic: {}", id, message(&*panic));
+                        },
+                        Err(_) => return, // the pool is dropped
+                    }
+                })
+                .expect("[error] failed to spawn worker thread")
+        }).collect();
+        ThreadPool {
+            workers,
+            sender: Some(sender),
+        }
+    }
+
+    pub fn execute<F: FnOnce() + Send + 'static>(&self, f: F) {
+        if let Some(sender) = &self.sender {
+            let _ = sender.send(Box::new(f));
+        }
+    }
+}
+
+fn message(panic: &(dyn Any + Send)) -> &str {
+    match panic.downcast_ref::<&str>() {
+        Some(msg) => msg,
+        None => panic.downcast_ref::<String>().map_or("unknown panic", String::as_str),
+    }
+}
+
+impl Drop for ThreadPool {
+    fn drop(&mut self) {
+        // closing the channel lets every worker finish its current job and exit
+        self.sender.take();
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    #[test]
+    fn test_execute() {
+        let count = Arc::new(AtomicUsize::new(0));
+        let pool = super::ThreadPool::new(4);
+        for _ in 0..16 {
+            let count = Arc::clone(&count);
+            pool.execute(move || { count.fetch_add(1, Ordering::SeqCst); });
+        }
+        drop(pool);
+        assert_eq!(count.load(Ordering::SeqCst), 16);
+    }
+    #[test]
+    fn test_execute_after_panic() {
+        let count = Arc::new(AtomicUsize::new(0));
+        let pool = super::ThreadPool::new(1);
+        pool.execute(|| panic!("handler failed"));
+        let done = Arc::clone(&count);
+        pool.execute(move || { done.fetch_add(1, Ordering::SeqCst); });
+        drop(pool);
+        assert_eq!(count.load(Ordering::SeqCst), 1);
+    }
+}
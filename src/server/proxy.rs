@@ -0,0 +1,417 @@
+use std::io::{self, Write};
+use std::time::Duration;
+use log::debug;
+use crate::http::parser::Parser;
+use crate::http::method::Method;
+use crate::http::request::Request;
+use crate::http::response::Response;
+use crate::http::version::Version;
+use crate::server::limits::{Limits, RESPONSE_LIMITS};
+use crate::server::listener::Stream;
+use crate::server::rate_limit::canonical;
+use crate::server::reader::{framing, Framing, ReadError, Reader};
+use crate::server::upstream::{Balance, Conn, Upstream};
+
+/// Headers which only apply to one connection and are not forwarded.
+const HOP_BY_HOP: [&str; 9] = [
+    "connection", "keep-alive", "proxy-connection", "te", "trailer",
+    "transfer-encoding", "upgrade", "proxy-authenticate", "proxy-authorization",
+];
+
+/// Forwards requests to an upstream `host:port` or to a group of them.
+/// Bodies are streamed in both directions, the response is sent to the client as it arrives.
+#[derive(Debug, Clone)]
+pub struct Proxy {
+    upstream: Upstream,
+    connect_timeout: Duration,
+    read_timeout: Duration,
+}
+
+/// A response relayed from the upstream, it is already sent to the client.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Forwarded {
+    pub status: u16,
+    /// Bytes sent to the client, head included.
+    pub bytes: usize,
+    pub body_bytes: usize,
+    /// The client connection can not be reused.
+    pub close: bool,
+}
+
+/// Why a request could not be forwarded. Nothing was sent to the client yet.
+#[derive(Debug)]
+pub enum Failure {
+    /// The upstream is unreachable or sent an invalid response, answered with 502.
+    BadGateway(io::Error),
+    /// The upstream did not answer in time, answered with 504.
+    GatewayTimeout,
+    /// The client did not send its body in time, answered with 408.
+    ClientTimeout,
+    /// A chunked request body went over the body limit, answered with 413.
+    TooLarge,
+    /// The chunked framing of the request body is invalid, answered with 400.
+    BadRequest,
+}
+
+/// The client side of a proxied request.
+pub(crate) struct Exchange<'a> {
+    pub client: &'a mut Stream,
+    pub reader: &'a mut Reader,
+    /// The request head as received, so that repeated headers are kept.
+    pub head: &'a [u8],
+    /// The request body as sent by the client: `Empty`, `Length` or `Chunked`.
+    pub body: Framing,
+    /// Limits of the route, a chunked request body is checked against them as its length is not known up front.
+    pub limits: Limits,
+    pub body_timeout: Duration,
+    pub keep_alive: bool,
+    /// Headers added to responses which do not have them.
+    pub headers: &'a [(String, String)],
+}
+
+impl Proxy {
+    pub fn new(upstream: &str) -> Self {
+        Proxy::group(Upstream::new(upstream, Balance::RoundRobin).server(upstream, 1))
+    }
+
+    /// Forward to the backends of an upstream group.
+    pub fn group(upstream: Upstream) -> Self {
+        Proxy {
+            upstream,
+            connect_timeout: Duration::from_secs(5),
+            read_timeout: Duration::from_secs(60),
+        }
+    }
+
+    pub fn connect_timeout(self, timeout: Duration) -> Self {
+        Proxy {
+            connect_timeout: timeout,
+            ..self
+        }
+    }
+
+    /// The longest wait for the upstream response or between two reads of its body.
+    pub fn read_timeout(self, timeout: Duration) -> Self {
+        Proxy {
+            read_timeout: timeout,
+            ..self
+        }
+    }
+
+    pub fn upstream(&self) -> &Upstream {
+        &self.upstream
+    }
+
+    pub(crate) fn forward(&self, req: &Request<String>, ex: &mut Exchange) -> Result<Forwarded, Failure> {
+        let keepalive = self.upstream.keeps_alive();
+        let mut conn = self.connect(req, keepalive)?;
+        // a reused connection may have been closed by the backend, which shows before any response byte.
+        // The request is sent again on a new connection unless its body is gone already.
+        let (head, res, mut reader) = loop {
+            match self.send(req, ex, &mut conn, keepalive) {
+                Ok(sent) => break sent,
+                Err(Some(Failure::BadGateway(e))) if conn.reused && ex.body == Framing::Empty => {
+                    debug!("reused connection to {} failed: {}", conn.address(), e);
+                    drop(conn);
+                    conn = self.connect(req, false)?;
+                },
+                Err(Some(failure)) => {
+                    if let Failure::BadGateway(_) | Failure::GatewayTimeout = failure {
+                        conn.failed();
+                    }
+                    return Err(failure);
+                },
+                Err(None) => return Err(Failure::ClientTimeout),
+            }
+        };
+        conn.succeeded();
+        let framing = framing(*req.method() == Method::HEAD, &res);
+        let close = !ex.keep_alive || framing == Framing::ToEnd;
+        let status = res.status().as_u16();
+        // the backend connection is kept when the response ends cleanly and the backend does not close it
+        let reusable = keepalive && framing != Framing::ToEnd && status != 101
+            && *res.version() == Version::HTTP11
+            && !res.header().get("Connection").unwrap_or_default().split(',').any(|t| t.trim().eq_ignore_ascii_case("close"));
+
+        let out = response_head(&head, framing, close, ex.headers);
+        let mut forwarded = Forwarded {
+            status,
+            bytes: out.len(),
+            body_bytes: 0,
+            close,
+        };
+        if let Err(e) = ex.client.write_all(&out) {
+            debug!("failed to relay the response head: {}", e);
+            forwarded.close = true;
+            return Ok(forwarded);
+        }
+        let copied = match framing {
+            Framing::Empty => Ok(0),
+            Framing::Length(len) => reader.copy(&mut conn.stream, ex.client, Some(len), self.read_timeout),
+            Framing::Chunked => reader.copy_chunked(&mut conn.stream, ex.client, RESPONSE_LIMITS.max_request_line, self.read_timeout),
+            Framing::ToEnd => reader.copy(&mut conn.stream, ex.client, None, self.read_timeout),
+        };
+        match copied {
+            Ok(n) => {
+                forwarded.body_bytes = n;
+                if reusable && reader.is_empty() {
+                    conn.release();
+                }
+            },
+            Err(e) => {
+                // the status is sent already, the client learns of the failure by the closed connection
+                debug!("failed to relay the response body from {}: {:?}", conn.address(), e);
+                forwarded.close = true;
+            },
+        }
+        forwarded.bytes += forwarded.body_bytes;
+        Ok(forwarded)
+    }
+
+    fn connect(&self, req: &Request<String>, reuse: bool) -> Result<Conn<'_>, Failure> {
+        let conn = self.upstream.connect(req, self.connect_timeout, reuse).map_err(|e| match is_timeout(&e) {
+            true => Failure::GatewayTimeout,
+            false => Failure::BadGateway(e),
+        })?;
+        conn.stream.set_write_timeout(Some(self.read_timeout)).map_err(Failure::BadGateway)?;
+        Ok(conn)
+    }
+
+    // send the request and read the response head, None when the client body timed out
+    fn send(&self, req: &Request<String>, ex: &mut Exchange, conn: &mut Conn, keepalive: bool) -> Result<(Vec<u8>, Response<String>, Reader), Option<Failure>> {
+        let proto = if ex.client.is_tls() { "https" } else { "http" };
+        let head = request_head(req, ex.head, conn.address(), proto, ex.body, !keepalive);
+        conn.stream.write_all(&head).map_err(|e| Some(Failure::BadGateway(e)))?;
+        let mut upstream = Capped {
+            inner: &mut conn.stream,
+            left: ex.limits.max_body,
+            exceeded: false,
+        };
+        let sent = match ex.body {
+            Framing::Length(len) => ex.reader.copy(ex.client, &mut upstream, Some(len), ex.body_timeout),
+            // relayed with its framing as it arrives
+            Framing::Chunked => ex.reader.copy_chunked(ex.client, &mut upstream, ex.limits.max_request_line, ex.body_timeout),
+            _ => Ok(0),
+        };
+        let exceeded = upstream.exceeded;
+        sent.map_err(|e| match e {
+            _ if exceeded => Some(Failure::TooLarge),
+            ReadError::Timeout => None,
+            ReadError::Malformed => Some(Failure::BadRequest),
+            ReadError::Io(e) => Some(Failure::BadGateway(e)),
+            _ => Some(Failure::BadGateway(io::ErrorKind::UnexpectedEof.into())),
+        })?;
+
+        let mut reader = Reader::new();
+        loop {
+            let head = reader.read_head(&mut conn.stream, self.read_timeout, self.read_timeout, &RESPONSE_LIMITS)
+                .map_err(|e| Some(match e {
+                    ReadError::Idle | ReadError::Timeout => Failure::GatewayTimeout,
+                    ReadError::Io(e) => Failure::BadGateway(e),
+                    ReadError::Closed => Failure::BadGateway(io::ErrorKind::UnexpectedEof.into()),
+                    ReadError::Limit(_) | ReadError::Malformed => Failure::BadGateway(invalid("invalid response head")),
+                }))?;
+            let res = Parser::new().parse_response(&head)
+                .map_err(|_| Some(Failure::BadGateway(invalid("invalid response head"))))?;
+            let status = res.status().as_u16();
+            // interim responses are not relayed, the request body is already sent
+            if !(100..200).contains(&status) || status == 101 {
+                return Ok((head, res, reader));
+            }
+        }
+    }
+}
+
+// the request head sent upstream: hop-by-hop headers removed, `Host` rewritten
+// and the client added to `X-Forwarded-For` and `Forwarded`
+fn request_head(req: &Request<String>, raw: &[u8], upstream: &str, proto: &str, body: Framing, close: bool) -> Vec<u8> {
+    let raw = String::from_utf8_lossy(raw);
+    let connection = req.header().get("Connection").unwrap_or_default();
+    let mut head = format!("{} {} HTTP/1.1\r\n", req.method().as_str(), req.uri().origin_form());
+    let mut forwarded_for = Vec::new();
+    let mut forwarded = Vec::new();
+    // the request line is rebuilt above
+    for line in raw.split("\r\n").skip(1).filter(|line| !line.is_empty()) {
+        let (name, value) = match line.split_once(':') {
+            Some((name, value)) => (name.trim(), value.trim()),
+            None => continue,
+        };
+        if is_hop_by_hop(name, connection) {
+            continue;
+        }
+        match name.to_ascii_lowercase().as_str() {
+            // expectations are answered here, the body is sent upstream right away
+            "host" | "expect" | "x-forwarded-host" | "x-forwarded-proto" => {},
+            // the framing is set below, from the length the body was read with
+            "content-length" => {},
+            "x-forwarded-for" => forwarded_for.push(value.to_string()),
+            "forwarded" => forwarded.push(value.to_string()),
+            _ => {
+                head.push_str(line);
+                head.push_str("\r\n");
+            },
+        }
+    }
+    let host = req.header().get("Host");
+    let client = req.remote_addr().map(|addr| canonical(addr.ip()));
+    if let Some(ip) = client {
+        forwarded_for.push(ip.to_string());
+    }
+    let node = match client {
+        Some(ip) if ip.is_ipv6() => format!("\"[{}]\"", ip),
+        Some(ip) => ip.to_string(),
+        None => "unknown".to_string(),
+    };
+    let mut element = format!("for={}", node);
+    if let Some(host) = host {
+        element.push_str(&format!(";host=\"{}\"", host.replace('\\', "\\\\").replace('"', "\\\"")));
+    }
+    element.push_str(&format!(";proto={}", proto));
+    forwarded.push(element);
+
+    head.push_str(&format!("Host: {}\r\n", upstream));
+    if !forwarded_for.is_empty() {
+        head.push_str(&format!("X-Forwarded-For: {}\r\n", forwarded_for.join(", ")));
+    }
+    head.push_str(&format!("Forwarded: {}\r\n", forwarded.join(", ")));
+    if let Some(host) = host {
+        head.push_str(&format!("X-Forwarded-Host: {}\r\n", host));
+    }
+    head.push_str(&format!("X-Forwarded-Proto: {}\r\n", proto));
+    match body {
+        Framing::Length(len) => head.push_str(&format!("Content-Length: {}\r\n", len)),
+        // the only coding accepted from clients, the chunks are relayed as they are
+        Framing::Chunked => head.push_str("Transfer-Encoding: chunked\r\n"),
+        _ if req.header().get("Content-Length").is_some() => head.push_str("Content-Length: 0\r\n"),
+        _ => {},
+    }
+    if close {
+        head.push_str("Connection: close\r\n");
+    }
+    head.push_str("\r\n");
+    head.into_bytes()
+}
+
+// the response head sent to the client, hop-by-hop headers replaced by our own framing
+fn response_head(raw: &[u8], framing: Framing, close: bool, extra: &[(String, String)]) -> Vec<u8> {
+    let raw = String::from_utf8_lossy(raw);
+    let mut lines = raw.split("\r\n").filter(|line| !line.is_empty());
+    // keep the status code and reason, the version is ours
+    let status = lines.next().and_then(|line| line.split_once(' ')).map(|(_, status)| status).unwrap_or("502 Bad Gateway");
+    let mut head = format!("HTTP/1.1 {}\r\n", status);
+    let fields: Vec<(&str, &str)> = lines
+        .filter_map(|line| line.split_once(':'))
+        .map(|(name, value)| (name.trim(), value.trim()))
+        .collect();
+    let connection = fields.iter()
+        .filter(|(name, _)| name.eq_ignore_ascii_case("Connection"))
+        .map(|(_, value)| *value)
+        .collect::<Vec<&str>>()
+        .join(",");
+    for (name, value) in fields.iter() {
+        if !is_hop_by_hop(name, &connection) {
+            head.push_str(&format!("{}: {}\r\n", name, value));
+        }
+    }
+    for (name, value) in extra {
+        if !fields.iter().any(|(n, _)| n.eq_ignore_ascii_case(name)) {
+            head.push_str(&format!("{}: {}\r\n", name, value));
+        }
+    }
+    if framing == Framing::Chunked {
+        head.push_str("Transfer-Encoding: chunked\r\n");
+    }
+    if close {
+        head.push_str("Connection: close\r\n");
+    }
+    head.push_str("\r\n");
+    head.into_bytes()
+}
+
+// `connection` lists further headers which only apply to the connection
+fn is_hop_by_hop(name: &str, connection: &str) -> bool {
+    HOP_BY_HOP.iter().any(|h| h.eq_ignore_ascii_case(name))
+        || connection.split(',').any(|token| token.trim().eq_ignore_ascii_case(name))
+}
+
+// the connection to the upstream, failing once more than `left` bytes are written
+struct Capped<'a> {
+    inner: &'a mut Stream,
+    left: usize,
+    exceeded: bool,
+}
+
+impl Write for Capped<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.len() > self.left {
+            self.exceeded = true;
+            return Err(io::Error::other("request body too large"));
+        }
+        let n = self.inner.write(buf)?;
+        self.left -= n;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+fn is_timeout(err: &io::Error) -> bool {
+    matches!(err.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut)
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::http::request::Request;
+    use crate::server::reader::Framing;
+
+    #[test]
+    fn test_request_head() {
+        let raw = b"GET http://example.com/api/users?page=2 HTTP/1.1\r\nHost: example.com\r\nConnection: keep-alive, X-Trace\r\nX-Trace: 1\r\nCookie: a=1\r\nCookie: b=2\r\nX-Forwarded-For: 10.0.0.1\r\n\r\n";
+        let parts = Request::builder()
+            .uri_from_str("http://example.com/api/users?page=2")
+            .push_header("Host", "example.com")
+            .push_header("Connection", "keep-alive, X-Trace")
+            .remote_addr("[::1]:50000".parse().unwrap())
+            .parts();
+        let req = Request::from_parts(parts, String::new());
+        let head = String::from_utf8(super::request_head(&req, raw, "10.0.0.2:8080", "http", Framing::Empty, true)).unwrap();
+        assert_eq!(head, "GET /api/users?page=2 HTTP/1.1\r\n\
+            Cookie: a=1\r\n\
+            Cookie: b=2\r\n\
+            Host: 10.0.0.2:8080\r\n\
+            X-Forwarded-For: 10.0.0.1, ::1\r\n\
+            Forwarded: for=\"[::1]\";host=\"example.com\";proto=http\r\n\
+            X-Forwarded-Host: example.com\r\n\
+            X-Forwarded-Proto: http\r\n\
+            Connection: close\r\n\r\n");
+        // repeated lengths are sent as one
+        let raw = b"POST /api HTTP/1.1\r\nContent-Length: 4\r\ncontent-length: 4\r\n\r\n";
+        let parts = Request::builder()
+            .uri_from_str("/api")
+            .push_header("Content-Length", "4")
+            .push_header("content-length", "4")
+            .parts();
+        let req = Request::from_parts(parts, String::new());
+        let head = String::from_utf8(super::request_head(&req, raw, "10.0.0.2:8080", "http", Framing::Length(4), false)).unwrap();
+        assert_eq!(head.matches("Content-Length").count() + head.matches("content-length").count(), 1);
+        assert!(head.ends_with("Content-Length: 4\r\n\r\n"));
+    }
+    #[test]
+    fn test_response_head() {
+        let raw = b"HTTP/1.0 404 Not Found\r\nSet-Cookie: a=1\r\nSet-Cookie: b=2\r\nKeep-Alive: timeout=5\r\nTransfer-Encoding: chunked\r\nServer: upstream\r\n\r\n";
+        let extra = [("Server".to_string(), "rushttp".to_string()), ("X-Frame-Options".to_string(), "DENY".to_string())];
+        let head = String::from_utf8(super::response_head(raw, Framing::Chunked, false, &extra)).unwrap();
+        assert_eq!(head, "HTTP/1.1 404 Not Found\r\n\
+            Set-Cookie: a=1\r\n\
+            Set-Cookie: b=2\r\n\
+            Server: upstream\r\n\
+            X-Frame-Options: DENY\r\n\
+            Transfer-Encoding: chunked\r\n\r\n");
+    }
+}
@@ -0,0 +1,202 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use crate::http::request::Request;
+use crate::http::response::Response;
+use crate::server::middleware::{Middleware, Next};
+use crate::server::response::response;
+
+// buckets are pruned at most this often
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// What requests are counted against.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Key {
+    /// The client IP address.
+    ClientIp,
+    /// The value of a header such as `X-Api-Key`, the client IP when the header is missing.
+    Header(String),
+}
+
+/// A token-bucket rate limiter, used as a middleware.
+/// Each key may make `burst` requests at once, refilled at `rate` requests per second.
+/// Requests over the limit get `429 Too Many Requests` with `Retry-After`.
+/// Requests without a key, such as over a Unix socket without the header, are not limited.
+#[derive(Clone)]
+pub struct RateLimiter {
+    rate: f64,
+    burst: f64,
+    key: Key,
+    state: Arc<Mutex<State>>,
+}
+
+struct State {
+    buckets: HashMap<String, Bucket>,
+    pruned: Instant,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl RateLimiter {
+    /// Panics unless `rate` is a finite number greater than 0.
+    pub fn new(rate: f64, burst: u32, key: Key) -> Self {
+        assert!(rate.is_finite() && rate > 0.0, "rate limit must be a finite number greater than 0, got {}", rate);
+        RateLimiter {
+            rate,
+            burst: f64::from(burst.max(1)),
+            key,
+            state: Arc::new(Mutex::new(State {
+                buckets: HashMap::new(),
+                pruned: Instant::now(),
+            })),
+        }
+    }
+
+    fn key(&self, req: &Request<String>) -> Option<String> {
+        let ip = || req.remote_addr().map(|addr| canonical(addr.ip()).to_string());
+        match &self.key {
+            Key::ClientIp => ip(),
+            Key::Header(name) => req.header().get(name).map(|v| format!("{}: {}", name, v)).or_else(ip),
+        }
+    }
+
+    /// Take a token for `key`, or tell how long until one is available.
+    pub fn check(&self, key: &str) -> Result<(), Duration> {
+        self.check_at(key, Instant::now())
+    }
+
+    fn check_at(&self, key: &str, now: Instant) -> Result<(), Duration> {
+        let mut state = match self.state.lock() {
+            Ok(state) => state,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        if now.duration_since(state.pruned) >= PRUNE_INTERVAL {
+            // a bucket refilled to the burst is the same as a missing one
+            let (rate, burst) = (self.rate, self.burst);
+            state.buckets.retain(|_, bucket| bucket.refilled(now, rate, burst) < burst);
+            state.pruned = now;
+        }
+        let bucket = state.buckets.entry(key.to_string()).or_insert(Bucket {
+            tokens: self.burst,
+            updated: now,
+        });
+        bucket.tokens = bucket.refilled(now, self.rate, self.burst);
+        bucket.updated = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            // a tiny rate gives a wait longer than a `Duration` holds
+            Err(Duration::try_from_secs_f64((1.0 - bucket.tokens) / self.rate).unwrap_or(Duration::MAX))
+        }
+    }
+}
+
+impl Middleware for RateLimiter {
+    fn handle(&self, req: Request<String>, next: Next<'_>) -> Response<Vec<u8>> {
+        let wait = match self.key(&req) {
+            Some(key) => self.check(&key),
+            None => Ok(()),
+        };
+        match wait {
+            Ok(()) => next.run(req),
+            Err(wait) => {
+                // whole seconds, rounded up so that a retry is not too early
+                let secs = wait.as_secs().saturating_add(u64::from(wait.subsec_nanos() > 0));
+                let extra = [("Retry-After".to_string(), secs.max(1).to_string())];
+                response(429, Some("Too Many Requests"), &extra)
+                    .unwrap_or_else(|_| Response::new(Vec::new()))
+            },
+        }
+    }
+}
+
+impl fmt::Debug for RateLimiter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("RateLimiter")
+            .field("rate", &self.rate)
+            .field("burst", &self.burst)
+            .field("key", &self.key)
+            .finish()
+    }
+}
+
+impl Bucket {
+    fn refilled(&self, now: Instant, rate: f64, burst: f64) -> f64 {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        (self.tokens + elapsed * rate).min(burst)
+    }
+}
+
+/// Count a v4-mapped IPv6 client of a dual-stack listener as its IPv4 address.
+pub fn canonical(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(ip),
+        ip => ip,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
+    use crate::http::request::Request;
+    use crate::http::response::Response;
+    use crate::server::middleware::{Middleware, Next};
+    use super::{Key, RateLimiter};
+
+    #[test]
+    fn test_check() {
+        let limiter = RateLimiter::new(2.0, 3, Key::ClientIp);
+        let now = Instant::now();
+        for _ in 0..3 {
+            assert!(limiter.check_at("a", now).is_ok());
+        }
+        assert_eq!(limiter.check_at("a", now), Err(Duration::from_millis(500)));
+        // other keys have their own bucket
+        assert!(limiter.check_at("b", now).is_ok());
+        assert!(limiter.check_at("a", now + Duration::from_millis(500)).is_ok());
+        assert!(limiter.check_at("a", now + Duration::from_millis(500)).is_err());
+    }
+    #[test]
+    fn test_check_tiny_rate() {
+        let limiter = RateLimiter::new(1e-20, 1, Key::ClientIp);
+        let now = Instant::now();
+        assert!(limiter.check_at("a", now).is_ok());
+        assert_eq!(limiter.check_at("a", now), Err(Duration::MAX));
+        let endpoint = |_: Request<String>| Response::new(Vec::new());
+        let chain: Vec<Arc<dyn Middleware>> = vec![Arc::new(limiter)];
+        let req = || {
+            let parts = Request::builder().remote_addr("10.0.0.1:4000".parse().unwrap()).parts();
+            Request::from_parts(parts, String::new())
+        };
+        assert_eq!(Next::new(&chain, &endpoint).run(req()).status().as_u16(), 200);
+        let res = Next::new(&chain, &endpoint).run(req());
+        assert_eq!(res.status().as_u16(), 429);
+        assert_eq!(res.header().get("Retry-After"), Some(u64::MAX.to_string().as_str()));
+    }
+    #[test]
+    #[should_panic]
+    fn test_new_zero_rate() {
+        RateLimiter::new(0.0, 1, Key::ClientIp);
+    }
+    #[test]
+    fn test_key() {
+        let limiter = RateLimiter::new(1.0, 1, Key::Header("X-Api-Key".to_string()));
+        let parts = Request::builder()
+            .remote_addr("[::ffff:10.0.0.1]:4000".parse().unwrap())
+            .push_header("X-Api-Key", "secret")
+            .parts();
+        let req = Request::from_parts(parts, String::new());
+        assert_eq!(limiter.key(&req).unwrap(), "X-Api-Key: secret");
+        let parts = Request::builder().remote_addr("[::ffff:10.0.0.1]:4000".parse().unwrap()).parts();
+        let req = Request::from_parts(parts, String::new());
+        assert_eq!(limiter.key(&req).unwrap(), "10.0.0.1");
+    }
+}
@@ -0,0 +1,369 @@
+use std::io::{self, Read, Write};
+use std::time::{Duration, Instant};
+use crate::http::response::Response;
+use crate::server::limits::{find, Exceeded, Limits};
+use crate::server::listener::Stream;
+
+const CHUNK: usize = 4096;
+
+/// Connection timeouts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Timeouts {
+    /// Total time to receive the request line and headers once the request has started.
+    pub header_read: Duration,
+    /// Longest pause while receiving the body.
+    pub body_read: Duration,
+    /// Longest pause while sending the response.
+    pub write: Duration,
+    /// How long an idle keep-alive connection waits for the next request.
+    pub keep_alive: Duration,
+}
+
+/// Why no complete request could be read.
+#[derive(Debug)]
+pub enum ReadError {
+    /// The client closed the connection between requests.
+    Closed,
+    /// No byte of a request arrived in time.
+    Idle,
+    /// A request started but did not arrive in time.
+    Timeout,
+    /// The request went over a size limit, the rest of it is not read.
+    Limit(Exceeded),
+    /// The chunked framing of a body is invalid.
+    Malformed,
+    Io(io::Error),
+}
+
+/// How the end of a message body is found.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Framing {
+    Empty,
+    Length(usize),
+    Chunked,
+    /// The body ends when the peer closes the connection.
+    ToEnd,
+}
+
+/// Reads requests from a connection.
+/// Bytes read past the end of a request are kept for the next, pipelined one.
+#[derive(Debug, Default)]
+pub struct Reader {
+    buf: Vec<u8>,
+    // no read goes past this, whatever its own timeout
+    deadline: Option<Instant>,
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Timeouts {
+            header_read: Duration::from_secs(10),
+            body_read: Duration::from_secs(30),
+            write: Duration::from_secs(30),
+            keep_alive: Duration::from_secs(5),
+        }
+    }
+}
+
+impl From<io::Error> for ReadError {
+    fn from(err: io::Error) -> ReadError {
+        ReadError::Io(err)
+    }
+}
+
+impl Reader {
+    pub fn new() -> Self {
+        Reader::default()
+    }
+
+    /// A reader which fails with `Timeout` once `deadline` has passed.
+    pub fn with_deadline(deadline: Option<Instant>) -> Self {
+        Reader {
+            buf: Vec::new(),
+            deadline,
+        }
+    }
+
+    /// Whether no bytes are buffered past what was read.
+    pub fn is_empty(&self) -> bool {
+        self.buf.is_empty()
+    }
+
+    /// Read the request line and headers, up to and including the blank line.
+    /// The first byte must arrive within `idle`, the rest within `header_read` of it.
+    pub fn read_head(&mut self, stream: &mut Stream, idle: Duration, header_read: Duration, limits: &Limits) -> Result<Vec<u8>, ReadError> {
+        let mut deadline = if self.buf.is_empty() { None } else { Some(Instant::now() + header_read) };
+        let idle_deadline = Instant::now() + idle;
+        loop {
+            if let Some(end) = find(&self.buf, b"\r\n\r\n") {
+                let head: Vec<u8> = self.buf.drain(..end + 4).collect();
+                limits.check_head(&head).map_err(ReadError::Limit)?;
+                return Ok(head);
+            }
+            limits.check_head(&self.buf).map_err(ReadError::Limit)?;
+            let started = deadline.is_some();
+            let n = self.fill(stream, deadline.unwrap_or(idle_deadline))
+                .map_err(|e| match e {
+                    ReadError::Timeout if !started => ReadError::Idle,
+                    e => e,
+                })?;
+            if n == 0 {
+                return Err(if started {
+                    ReadError::Io(io::ErrorKind::UnexpectedEof.into())
+                } else {
+                    ReadError::Closed
+                });
+            }
+            if !started {
+                deadline = Some(Instant::now() + header_read);
+            }
+        }
+    }
+
+    /// Read a body of `len` bytes, pausing no longer than `timeout` between reads.
+    pub fn read_body(&mut self, stream: &mut Stream, len: usize, timeout: Duration) -> Result<Vec<u8>, ReadError> {
+        while self.buf.len() < len {
+            if self.fill(stream, Instant::now() + timeout)? == 0 {
+                return Err(ReadError::Io(io::ErrorKind::UnexpectedEof.into()));
+            }
+        }
+        Ok(self.buf.drain(..len).collect())
+    }
+
+    /// Copy a body of `len` bytes to `to` as it arrives, or everything up to the end of the stream without `len`.
+    /// Returns the number of bytes copied.
+    pub fn copy(&mut self, from: &mut Stream, to: &mut dyn Write, len: Option<usize>, timeout: Duration) -> Result<usize, ReadError> {
+        let mut copied = 0;
+        loop {
+            let n = match len {
+                Some(len) => self.buf.len().min(len - copied),
+                None => self.buf.len(),
+            };
+            to.write_all(&self.buf[..n])?;
+            self.buf.drain(..n);
+            copied += n;
+            if len == Some(copied) {
+                return Ok(copied);
+            }
+            if self.fill(from, Instant::now() + timeout)? == 0 {
+                return match len {
+                    Some(_) => Err(ReadError::Io(io::ErrorKind::UnexpectedEof.into())),
+                    None => Ok(copied),
+                };
+            }
+        }
+    }
+
+    /// Copy a chunked body to `to` as is, up to and including the trailer section.
+    /// Chunk size and trailer lines longer than `max_line` are `Malformed`.
+    pub fn copy_chunked(&mut self, from: &mut Stream, to: &mut dyn Write, max_line: usize, timeout: Duration) -> Result<usize, ReadError> {
+        self.chunked(from, to, false, max_line, timeout)
+    }
+
+    /// Read a chunked body without its framing, the trailer section is dropped.
+    pub fn read_chunked(&mut self, from: &mut Stream, max_line: usize, timeout: Duration) -> Result<Vec<u8>, ReadError> {
+        let mut body = Vec::new();
+        self.chunked(from, &mut body, true, max_line, timeout)?;
+        Ok(body)
+    }
+
+    // returns the number of bytes read
+    fn chunked(&mut self, from: &mut Stream, to: &mut dyn Write, decode: bool, max_line: usize, timeout: Duration) -> Result<usize, ReadError> {
+        let mut copied = 0;
+        loop {
+            let line = self.line(from, max_line, timeout)?;
+            let size = line.split(|b| *b == b';').next()
+                .and_then(|size| std::str::from_utf8(size).ok())
+                .map(str::trim)
+                .filter(|size| !size.is_empty() && size.bytes().all(|b| b.is_ascii_hexdigit()))
+                .and_then(|size| usize::from_str_radix(size, 16).ok())
+                // the chunk data and its CRLF are counted together
+                .filter(|size| size.checked_add(2).is_some())
+                .ok_or(ReadError::Malformed)?;
+            if !decode {
+                to.write_all(&line)?;
+            }
+            copied += line.len();
+            if size == 0 {
+                break;
+            }
+            copied += self.copy(from, to, Some(size), timeout)?;
+            // the chunk data ends with a CRLF
+            while self.buf.len() < 2 {
+                if self.fill(from, Instant::now() + timeout)? == 0 {
+                    return Err(ReadError::Io(io::ErrorKind::UnexpectedEof.into()));
+                }
+            }
+            if self.buf[..2] != *b"\r\n" {
+                return Err(ReadError::Malformed);
+            }
+            if !decode {
+                to.write_all(b"\r\n")?;
+            }
+            self.buf.drain(..2);
+            copied += 2;
+        }
+        // trailer fields, ending with a blank line
+        loop {
+            let line = self.line(from, max_line, timeout)?;
+            if !decode {
+                to.write_all(&line)?;
+            }
+            copied += line.len();
+            if line == b"\r\n" {
+                return Ok(copied);
+            }
+        }
+    }
+
+    // take one line including its CRLF, `Malformed` when it is longer than `max` without it
+    fn line(&mut self, from: &mut Stream, max: usize, timeout: Duration) -> Result<Vec<u8>, ReadError> {
+        loop {
+            match find(&self.buf, b"\r\n") {
+                Some(end) if end <= max => return Ok(self.buf.drain(..end + 2).collect()),
+                Some(_) => return Err(ReadError::Malformed),
+                None if self.buf.len() > max + 1 => return Err(ReadError::Malformed),
+                None => {},
+            }
+            if self.fill(from, Instant::now() + timeout)? == 0 {
+                return Err(ReadError::Io(io::ErrorKind::UnexpectedEof.into()));
+            }
+        }
+    }
+
+    // read once into the buffer, failing with Timeout after `deadline`
+    fn fill(&mut self, stream: &mut Stream, deadline: Instant) -> Result<usize, ReadError> {
+        let deadline = self.deadline.map_or(deadline, |d| d.min(deadline));
+        let mut chunk = [0u8; CHUNK];
+        loop {
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(ReadError::Timeout);
+            }
+            stream.set_read_timeout(Some(deadline - now))?;
+            match stream.read(&mut chunk) {
+                Ok(n) => {
+                    self.buf.extend_from_slice(&chunk[..n]);
+                    return Ok(n);
+                },
+                // the deadline is checked again on the next round
+                Err(e) if is_timeout(&e) || e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(ReadError::Io(e)),
+            }
+        }
+    }
+}
+
+/// How the body of a response with this status and headers ends.
+pub(crate) fn framing<T>(head_request: bool, res: &Response<T>) -> Framing {
+    let status = res.status().as_u16();
+    if head_request || status == 204 || status == 304 || (100..200).contains(&status) {
+        return Framing::Empty;
+    }
+    if let Some(te) = res.header().get("Transfer-Encoding") {
+        if te.split(',').next_back().is_some_and(|coding| coding.trim().eq_ignore_ascii_case("chunked")) {
+            return Framing::Chunked;
+        }
+        return Framing::ToEnd;
+    }
+    match res.header().get("Content-Length").and_then(|len| len.trim().parse().ok()) {
+        Some(len) => Framing::Length(len),
+        None => Framing::ToEnd,
+    }
+}
+
+fn is_timeout(err: &io::Error) -> bool {
+    matches!(err.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut)
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::io::Write;
+    use std::os::unix::net::UnixStream;
+    use std::time::Duration;
+    use crate::server::listener::Stream;
+    use crate::server::limits::{Exceeded, Limits};
+    use super::{ReadError, Reader};
+
+    const SHORT: Duration = Duration::from_millis(50);
+
+    #[test]
+    fn test_read_pipelined() {
+        let (mut client, server) = UnixStream::pair().unwrap();
+        let mut stream = Stream::Unix(server);
+        client.write_all(b"POST / HTTP/1.1\r\nContent-Length: 4\r\n\r\nbodyGET / HTTP/1.1\r\n\r\n").unwrap();
+        let mut reader = Reader::new();
+        let head = reader.read_head(&mut stream, SHORT, SHORT, &Limits::default()).unwrap();
+        assert!(head.ends_with(b"Content-Length: 4\r\n\r\n"));
+        assert_eq!(reader.read_body(&mut stream, 4, SHORT).unwrap(), b"body");
+        assert_eq!(reader.read_head(&mut stream, SHORT, SHORT, &Limits::default()).unwrap(), b"GET / HTTP/1.1\r\n\r\n");
+    }
+    #[test]
+    fn test_copy_chunked() {
+        let (mut client, server) = UnixStream::pair().unwrap();
+        let mut stream = Stream::Unix(server);
+        let body = b"4\r\nWiki\r\n6;ext=1\r\npedia \r\n0\r\nExpires: never\r\n\r\n";
+        client.write_all(body).unwrap();
+        client.write_all(b"HTTP/1.1 200 OK\r\n").unwrap();
+        let mut reader = Reader::new();
+        let mut out = Vec::new();
+        assert_eq!(reader.copy_chunked(&mut stream, &mut out, 64, SHORT).unwrap(), body.len());
+        assert_eq!(out, body.to_vec());
+        // the next message stays buffered
+        let mut out = Vec::new();
+        drop(client);
+        reader.copy(&mut stream, &mut out, None, SHORT).unwrap();
+        assert_eq!(out, b"HTTP/1.1 200 OK\r\n");
+    }
+    #[test]
+    fn test_read_chunked() {
+        let (mut client, server) = UnixStream::pair().unwrap();
+        let mut stream = Stream::Unix(server);
+        client.write_all(b"4\r\nWiki\r\n6;ext=1\r\npedia \r\n0\r\nExpires: never\r\n\r\n").unwrap();
+        let mut reader = Reader::new();
+        assert_eq!(reader.read_chunked(&mut stream, 64, SHORT).unwrap(), b"Wikipedia ");
+        assert!(reader.is_empty());
+    }
+    #[test]
+    fn test_chunked_malformed() {
+        let bodies: [&[u8]; 5] = [
+            // the size of the chunk data and its CRLF overflows
+            b"ffffffffffffffff\r\nWiki\r\n0\r\n\r\n",
+            b"+4\r\nWiki\r\n0\r\n\r\n",
+            b"4\r\nWikiXY0\r\n\r\n",
+            b"4;aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa\r\nWiki\r\n0\r\n\r\n",
+            // no CRLF ever comes, the line is not buffered past the limit
+            b"0\r\nX-Trailer: aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa",
+        ];
+        for body in bodies.iter() {
+            let (mut client, server) = UnixStream::pair().unwrap();
+            let mut stream = Stream::Unix(server);
+            client.write_all(body).unwrap();
+            let mut out = Vec::new();
+            let res = Reader::new().copy_chunked(&mut stream, &mut out, 64, SHORT);
+            assert!(matches!(res, Err(ReadError::Malformed)), "{:?}", String::from_utf8_lossy(body));
+        }
+    }
+    #[test]
+    fn test_read_timeout() {
+        let (mut client, server) = UnixStream::pair().unwrap();
+        let mut stream = Stream::Unix(server);
+        let mut reader = Reader::new();
+        assert!(matches!(reader.read_head(&mut stream, SHORT, SHORT, &Limits::default()), Err(ReadError::Idle)));
+        client.write_all(b"GET / HTTP/1.1\r\nHost:").unwrap();
+        assert!(matches!(reader.read_head(&mut stream, SHORT, SHORT, &Limits::default()), Err(ReadError::Timeout)));
+        drop(client);
+        let mut reader = Reader::new();
+        assert!(matches!(reader.read_head(&mut stream, SHORT, SHORT, &Limits::default()), Err(ReadError::Closed)));
+    }
+    #[test]
+    fn test_read_limit() {
+        let (mut client, server) = UnixStream::pair().unwrap();
+        let mut stream = Stream::Unix(server);
+        let limits = Limits { max_request_line: 16, ..Limits::default() };
+        // stops at the oversized line without waiting for the rest of the head
+        client.write_all(b"GET /aaaaaaaaaaaaaaaaaaaaaaaa").unwrap();
+        let res = Reader::new().read_head(&mut stream, SHORT, SHORT, &limits);
+        assert!(matches!(res, Err(ReadError::Limit(Exceeded::RequestLine))));
+    }
+}
@@ -1,8 +1,83 @@
-use std::path::{Path, PathBuf};
+use std::path::{Path, PathBuf, Component};
 use std::fs::File;
 use crate::server::error::Error;
 use std::io::Read;
 
+/// A directory served as static files under a URL prefix.
+#[derive(Debug, Clone)]
+pub struct Mount {
+    prefix: String,
+    dir: PathBuf,
+}
+
+impl Mount {
+    pub fn new(prefix: &str, dir: &str) -> Self {
+        Mount {
+            prefix: prefix.trim_end_matches('/').to_string(),
+            dir: Path::new(dir).to_path_buf(),
+        }
+    }
+
+    pub fn prefix(&self) -> &str {
+        &self.prefix
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Whether `path` is under the mount prefix.
+    pub fn matches(&self, path: &str) -> bool {
+        under_prefix(&self.prefix, path)
+    }
+
+    /// Map a request path to a file under the mounted directory.
+    /// Paths escaping the directory are rejected, and a directory resolves to its index.html.
+    pub fn resolve(&self, path: &str) -> Option<PathBuf> {
+        if !self.matches(path) {
+            return None;
+        }
+        let rest = &path[self.prefix.len()..];
+        let mut file = self.dir.clone();
+        for component in Path::new(rest).components() {
+            match component {
+                Component::Normal(c) => file.push(c),
+                Component::RootDir | Component::CurDir => {},
+                _ => return None,
+            }
+        }
+        if file.is_dir() {
+            file.push("index.html");
+        }
+        if file.is_file() { Some(file) } else { None }
+    }
+}
+
+/// Whether `path` is `prefix` itself or below it, `/api` covers `/api/users` but not `/apis`.
+pub fn under_prefix(prefix: &str, path: &str) -> bool {
+    match path.strip_prefix(prefix.trim_end_matches('/')) {
+        Some(rest) => rest.is_empty() || rest.starts_with('/'),
+        None => false,
+    }
+}
+
+pub fn content_type(path: &Path) -> &'static str {
+    match path.extension().and_then(|e| e.to_str()) {
+        Some("html") | Some("htm") => "text/html; charset=utf-8",
+        Some("css") => "text/css",
+        Some("js") => "application/javascript",
+        Some("json") => "application/json",
+        Some("txt") => "text/plain; charset=utf-8",
+        Some("svg") => "image/svg+xml",
+        Some("png") => "image/png",
+        Some("jpg") | Some("jpeg") => "image/jpeg",
+        Some("gif") => "image/gif",
+        Some("ico") => "image/x-icon",
+        Some("pdf") => "application/pdf",
+        _ => "application/octet-stream",
+    }
+}
+
 pub fn get_path(root: &str, path: &str) -> PathBuf {
     let root = Path::new(root);
     root.join(Path::new(path))
@@ -41,4 +116,18 @@ mod tests {
         let root = cwd.join(super::Path::new("src/static/assets/html"));
         assert_eq!(super::validate(root.to_str().unwrap(), "../../../server/resource.rs"), false);
     }
+    #[test]
+    fn test_mount_resolve() {
+        let mount = super::Mount::new("/", "src/static/assets/html");
+        assert_eq!(mount.resolve("/"), Some(super::Path::new("src/static/assets/html/index.html").to_path_buf()));
+        assert_eq!(mount.resolve("/index.html"), Some(super::Path::new("src/static/assets/html/index.html").to_path_buf()));
+        assert_eq!(mount.resolve("/../../server/resource.rs"), None);
+    }
+    #[test]
+    fn test_mount_resolve_prefix() {
+        let mount = super::Mount::new("/assets/", "src/static/assets");
+        assert_eq!(mount.resolve("/assets/html/index.html"), Some(super::Path::new("src/static/assets/html/index.html").to_path_buf()));
+        assert_eq!(mount.resolve("/assetshtml/index.html"), None);
+        assert_eq!(mount.resolve("/other/index.html"), None);
+    }
 }
\ No newline at end of file
@@ -4,34 +4,38 @@ use crate::http::response::Response;
 use crate::http::status::StatusCode;
 use crate::server::resource;
 use crate::http::header::*;
+use std::fs;
+use std::path::Path;
 // use crate::server::context::Context;
 
 // call in handler function
-pub fn response(status: u16, data: Option<&str>) -> Result<String, Error> {
-    let builder = Response::builder()
-        .status(StatusCode::from_u16(status)
-            .map_err(|e| Error::from(HttpError::from(e)))?);
-    // header
+pub fn response(status: u16, data: Option<&str>, extra: &[(String, String)]) -> Result<Response<Vec<u8>>, Error> {
+    let body = data.map(|d| d.as_bytes().to_vec()).unwrap_or_default();
+    build(status, "text/html", body, extra)
+}
+
+// serve a static file as is, the body may not be valid utf-8
+pub fn file_response(path: &Path, extra: &[(String, String)]) -> Result<Response<Vec<u8>>, Error> {
+    let body = fs::read(path)?;
+    build(200, resource::content_type(path), body, extra)
+}
+
+pub fn build(status: u16, content_type: &str, body: Vec<u8>, extra: &[(String, String)]) -> Result<Response<Vec<u8>>, Error> {
     let mut header = Header::new();
-    header.parse("Content-Type: text/html")
+    header.add("Content-Type", content_type);
+    header.parse(&content_length(body.len()))
         .map_err(|e| Error::from(HttpError::from(e)))?;
-    header.parse(&match data {
-        Some(d) => content_length(d.len()),
-        None => content_length(0)
-    }).map_err(|e| Error::from(HttpError::from(e)))?;
     header.parse(&http_date())
         .map_err(|e| Error::from(HttpError::from(e)))?;
     header.parse(&accept_ranges())
         .map_err(|e| Error::from(HttpError::from(e)))?;
-
-    let builder = builder.header(header);
-    let res: Response<String> = match data {
-        Some(d) => {
-            // Response::from_parts(builder.parts(), body)
-            builder.response(d.to_string())
-        },
-        None => builder.empty_response(),
-    };
-    let buf = res.format().map_err(|e| Error::from(HttpError::from(e)))?;
-    Ok(buf)
+    for (name, value) in extra {
+        header.add(name, value);
+    }
+    let res = Response::builder()
+        .status(StatusCode::from_u16(status)
+            .map_err(|e| Error::from(HttpError::from(e)))?)
+        .header(header)
+        .response(body);
+    Ok(res)
 }
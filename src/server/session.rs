@@ -0,0 +1,629 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use aes_gcm::aead::{Aead, KeyInit, Payload};
+use aes_gcm::{Aes256Gcm, Nonce};
+use log::{error, warn};
+use serde::{Deserialize, Serialize};
+use crate::http::cookie::{self, SameSite, SetCookie};
+use crate::http::request::Request;
+use crate::http::response::Response;
+use crate::server::crypto;
+use crate::server::middleware::{Middleware, Next};
+
+const NONCE_LEN: usize = 12;
+
+// the data of a session and when it expires
+type Entry = (HashMap<String, String>, SystemTime);
+
+/// Sessions kept across requests by a cookie, used as a middleware.
+/// Without a store the data itself is kept in the cookie, signed with HMAC-SHA256 or encrypted with AES-256-GCM.
+/// With a store the cookie only holds the signed or encrypted session id.
+/// A session expires `ttl` after it was last changed.
+#[derive(Clone)]
+pub struct Sessions {
+    sign_key: [u8; 32],
+    encrypt_key: [u8; 32],
+    encrypted: bool,
+    store: Option<Arc<dyn SessionStore>>,
+    name: String,
+    path: String,
+    domain: Option<String>,
+    secure: bool,
+    same_site: SameSite,
+    ttl: Duration,
+}
+
+/// The session of a request, handlers get it with `Session::from_request`.
+/// Changes are saved when the response goes back through the middleware.
+#[derive(Debug, Clone)]
+pub struct Session {
+    state: Arc<Mutex<State>>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    // the id the session was loaded with, if it is kept in a store
+    id: Option<String>,
+    data: HashMap<String, String>,
+    changed: bool,
+    renew: bool,
+    destroyed: bool,
+}
+
+/// Where the data of sessions is kept when it is not in the cookie.
+pub trait SessionStore: Send + Sync {
+    /// The data of a session which has not expired.
+    fn load(&self, id: &str) -> io::Result<Option<HashMap<String, String>>>;
+    fn save(&self, id: &str, data: &HashMap<String, String>, expires: SystemTime) -> io::Result<()>;
+    fn destroy(&self, id: &str) -> io::Result<()>;
+}
+
+/// Sessions kept in memory, lost on restart. Expired ones are dropped as others are saved.
+#[derive(Default)]
+pub struct MemoryStore {
+    sessions: Mutex<HashMap<String, Entry>>,
+}
+
+/// Sessions kept as one file each in a directory.
+/// Expired files are only removed as they are loaded or by `prune`.
+#[derive(Debug, Clone)]
+pub struct FileStore {
+    dir: PathBuf,
+}
+
+// a session as it is kept in a cookie or a file
+#[derive(Serialize, Deserialize)]
+struct Stored {
+    expires: u64,
+    data: HashMap<String, String>,
+}
+
+impl Sessions {
+    /// `secret` must be at least 32 random bytes, the signing and encryption keys are derived from it.
+    pub fn new(secret: &[u8]) -> io::Result<Self> {
+        if secret.len() < 32 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "a session secret needs at least 32 bytes"));
+        }
+        Ok(Sessions {
+            sign_key: crypto::hmac_sha256(secret, b"rushttp session signing"),
+            encrypt_key: crypto::hmac_sha256(secret, b"rushttp session encryption"),
+            encrypted: false,
+            store: None,
+            name: "session".to_string(),
+            path: "/".to_string(),
+            domain: None,
+            secure: false,
+            same_site: SameSite::Lax,
+            ttl: Duration::from_secs(24 * 60 * 60),
+        })
+    }
+
+    /// Encrypt the cookie instead of only signing it, so that clients cannot read it.
+    pub fn encrypted(self, encrypted: bool) -> Self {
+        Sessions {
+            encrypted,
+            ..self
+        }
+    }
+
+    /// Keep the data in `store`, the cookie then only holds the session id.
+    pub fn store<S: SessionStore + 'static>(self, store: S) -> Self {
+        Sessions {
+            store: Some(Arc::new(store)),
+            ..self
+        }
+    }
+
+    pub fn cookie_name(self, name: &str) -> Self {
+        Sessions {
+            name: name.to_string(),
+            ..self
+        }
+    }
+
+    pub fn path(self, path: &str) -> Self {
+        Sessions {
+            path: path.to_string(),
+            ..self
+        }
+    }
+
+    pub fn domain(self, domain: &str) -> Self {
+        Sessions {
+            domain: Some(domain.to_string()),
+            ..self
+        }
+    }
+
+    /// Only send the cookie over https.
+    pub fn secure(self, secure: bool) -> Self {
+        Sessions {
+            secure,
+            ..self
+        }
+    }
+
+    pub fn same_site(self, same_site: SameSite) -> Self {
+        Sessions {
+            same_site,
+            ..self
+        }
+    }
+
+    /// How long a session lasts after it was last changed.
+    pub fn ttl(self, ttl: Duration) -> Self {
+        Sessions {
+            ttl,
+            ..self
+        }
+    }
+
+    // the session of the request cookie, a new one when it is missing, forged or expired
+    fn load<T>(&self, req: &Request<T>) -> State {
+        let value = match cookie::get(req.header(), &self.name) {
+            Some(value) => value,
+            None => return State::default(),
+        };
+        let plain = match self.open(&value) {
+            Some(plain) => plain,
+            None => return State::default(),
+        };
+        match &self.store {
+            Some(store) => {
+                let id = String::from_utf8_lossy(&plain).to_string();
+                match store.load(&id) {
+                    Ok(Some(data)) => State {
+                        id: Some(id),
+                        data,
+                        ..State::default()
+                    },
+                    Ok(None) => State::default(),
+                    Err(e) => {
+                        error!("failed to load session: {}", e);
+                        State::default()
+                    },
+                }
+            },
+            None => {
+                let stored = std::str::from_utf8(&plain).ok()
+                    .and_then(|plain| toml::from_str::<Stored>(plain).ok());
+                match stored {
+                    Some(stored) if UNIX_EPOCH + Duration::from_secs(stored.expires) > SystemTime::now() => State {
+                        data: stored.data,
+                        ..State::default()
+                    },
+                    _ => State::default(),
+                }
+            },
+        }
+    }
+
+    // save a changed session and set its cookie on the response
+    fn commit(&self, state: &mut State, res: &mut Response<Vec<u8>>) {
+        if let (Some(store), Some(id)) = (&self.store, &state.id) {
+            if state.destroyed || state.renew {
+                if let Err(e) = store.destroy(id) {
+                    error!("failed to destroy session: {}", e);
+                }
+            }
+        }
+        if state.destroyed {
+            let mut removal = SetCookie::remove(&self.name).path(&self.path);
+            if let Some(domain) = &self.domain {
+                removal = removal.domain(domain);
+            }
+            res.header_mut().add("Set-Cookie", &removal.to_string());
+            return;
+        }
+        if !state.changed && !state.renew {
+            return;
+        }
+        let expires = SystemTime::now() + self.ttl;
+        let plain = match &self.store {
+            Some(store) => {
+                let id = match (&state.id, state.renew) {
+                    (Some(id), false) => id.clone(),
+                    _ => crypto::encode(&crypto::random_bytes(32)),
+                };
+                if let Err(e) = store.save(&id, &state.data, expires) {
+                    error!("failed to save session: {}", e);
+                    return;
+                }
+                id.into_bytes()
+            },
+            None => {
+                let stored = Stored {
+                    expires: expires.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs()),
+                    data: state.data.clone(),
+                };
+                match toml::to_string(&stored) {
+                    Ok(plain) => plain.into_bytes(),
+                    Err(e) => {
+                        error!("failed to encode session: {}", e);
+                        return;
+                    },
+                }
+            },
+        };
+        let value = self.seal(&plain);
+        if value.len() > 4000 {
+            warn!("session cookie of {} bytes may be refused by browsers", value.len());
+        }
+        let mut cookie = SetCookie::new(&self.name, &value)
+            .path(&self.path)
+            .max_age(self.ttl.as_secs() as i64)
+            .http_only(true)
+            .secure(self.secure)
+            .same_site(self.same_site);
+        if let Some(domain) = &self.domain {
+            cookie = cookie.domain(domain);
+        }
+        res.header_mut().add("Set-Cookie", &cookie.to_string());
+    }
+
+    // the cookie value for `plain`, the cookie name is bound to it so that values cannot be swapped
+    fn seal(&self, plain: &[u8]) -> String {
+        if self.encrypted {
+            let cipher = Aes256Gcm::new(&self.encrypt_key.into());
+            let nonce = crypto::random_bytes(NONCE_LEN);
+            let payload = Payload {
+                msg: plain,
+                aad: self.name.as_bytes(),
+            };
+            let mut sealed = nonce.clone();
+            sealed.extend(cipher.encrypt(Nonce::from_slice(&nonce), payload).expect("AES-GCM encrypts any cookie"));
+            crypto::encode(&sealed)
+        } else {
+            let value = crypto::encode(plain);
+            let tag = crypto::hmac_sha256(&self.sign_key, format!("{}={}", self.name, value).as_bytes());
+            format!("{}.{}", value, crypto::encode(&tag))
+        }
+    }
+
+    fn open(&self, value: &str) -> Option<Vec<u8>> {
+        if self.encrypted {
+            let sealed = crypto::decode(value)?;
+            if sealed.len() < NONCE_LEN {
+                return None;
+            }
+            let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+            let cipher = Aes256Gcm::new(&self.encrypt_key.into());
+            let payload = Payload {
+                msg: ciphertext,
+                aad: self.name.as_bytes(),
+            };
+            cipher.decrypt(Nonce::from_slice(nonce), payload).ok()
+        } else {
+            let (value, tag) = value.rsplit_once('.')?;
+            let tag = crypto::decode(tag)?;
+            if !crypto::verify_hmac_sha256(&self.sign_key, format!("{}={}", self.name, value).as_bytes(), &tag) {
+                return None;
+            }
+            crypto::decode(value)
+        }
+    }
+}
+
+impl Middleware for Sessions {
+    fn handle(&self, mut req: Request<String>, next: Next<'_>) -> Response<Vec<u8>> {
+        let session = Session {
+            state: Arc::new(Mutex::new(self.load(&req))),
+        };
+        req.extensions_mut().insert(session.clone());
+        let mut res = next.run(req);
+        self.commit(&mut session.lock(), &mut res);
+        res
+    }
+}
+
+impl fmt::Debug for Sessions {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Sessions")
+            .field("encrypted", &self.encrypted)
+            .field("store", &self.store.is_some())
+            .field("name", &self.name)
+            .field("path", &self.path)
+            .field("domain", &self.domain)
+            .field("secure", &self.secure)
+            .field("same_site", &self.same_site)
+            .field("ttl", &self.ttl)
+            .finish()
+    }
+}
+
+impl Session {
+    /// The session the `Sessions` middleware attached to a request.
+    pub fn from_request<T>(req: &Request<T>) -> Option<Session> {
+        req.extensions().get::<Session>().cloned()
+    }
+
+    pub fn get(&self, key: &str) -> Option<String> {
+        self.lock().data.get(key).cloned()
+    }
+
+    /// Set a value, which also starts over a destroyed session.
+    pub fn insert(&self, key: &str, value: &str) {
+        let mut state = self.lock();
+        if state.destroyed {
+            state.destroyed = false;
+            state.renew = true;
+        }
+        state.data.insert(key.to_string(), value.to_string());
+        state.changed = true;
+    }
+
+    pub fn remove(&self, key: &str) -> Option<String> {
+        let mut state = self.lock();
+        state.changed = true;
+        state.data.remove(key)
+    }
+
+    pub fn clear(&self) {
+        let mut state = self.lock();
+        state.changed = true;
+        state.data.clear();
+    }
+
+    /// Give the session a new id, keeping its data.
+    /// Call this when the privileges of a session change, such as on login,
+    /// so that an id someone learned before is of no use.
+    pub fn renew(&self) {
+        self.lock().renew = true;
+    }
+
+    /// End the session: its data is dropped, removed from the store and the cookie is deleted.
+    pub fn destroy(&self) {
+        let mut state = self.lock();
+        state.data.clear();
+        state.destroyed = true;
+        state.renew = false;
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        match self.state.lock() {
+            Ok(state) => state,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        MemoryStore::default()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, HashMap<String, Entry>> {
+        match self.sessions.lock() {
+            Ok(sessions) => sessions,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+}
+
+impl SessionStore for MemoryStore {
+    fn load(&self, id: &str) -> io::Result<Option<HashMap<String, String>>> {
+        let now = SystemTime::now();
+        Ok(self.lock().get(id)
+            .filter(|(_, expires)| *expires > now)
+            .map(|(data, _)| data.clone()))
+    }
+
+    fn save(&self, id: &str, data: &HashMap<String, String>, expires: SystemTime) -> io::Result<()> {
+        let now = SystemTime::now();
+        let mut sessions = self.lock();
+        sessions.retain(|_, (_, expires)| *expires > now);
+        sessions.insert(id.to_string(), (data.clone(), expires));
+        Ok(())
+    }
+
+    fn destroy(&self, id: &str) -> io::Result<()> {
+        self.lock().remove(id);
+        Ok(())
+    }
+}
+
+impl fmt::Debug for MemoryStore {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("MemoryStore")
+            .field("sessions", &self.lock().len())
+            .finish()
+    }
+}
+
+impl FileStore {
+    /// Keep sessions in `dir`, which is created if missing.
+    pub fn new<P: AsRef<Path>>(dir: P) -> io::Result<Self> {
+        fs::create_dir_all(dir.as_ref())?;
+        Ok(FileStore {
+            dir: dir.as_ref().to_path_buf(),
+        })
+    }
+
+    /// Remove the files of expired sessions, returning how many were removed.
+    pub fn prune(&self) -> io::Result<usize> {
+        let mut removed = 0;
+        for entry in fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|ext| ext == "session") && read(&path)?.is_none() {
+                fs::remove_file(&path)?;
+                removed += 1;
+            }
+        }
+        Ok(removed)
+    }
+
+    fn file(&self, id: &str) -> io::Result<PathBuf> {
+        // ids are base64url, anything else could name a file outside the directory
+        if id.is_empty() || !id.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_') {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "invalid session id"));
+        }
+        Ok(self.dir.join(format!("{}.session", id)))
+    }
+}
+
+impl SessionStore for FileStore {
+    fn load(&self, id: &str) -> io::Result<Option<HashMap<String, String>>> {
+        let path = self.file(id)?;
+        match read(&path) {
+            Ok(Some(data)) => Ok(Some(data)),
+            Ok(None) => {
+                fs::remove_file(&path)?;
+                Ok(None)
+            },
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn save(&self, id: &str, data: &HashMap<String, String>, expires: SystemTime) -> io::Result<()> {
+        let path = self.file(id)?;
+        let stored = Stored {
+            expires: expires.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs()),
+            data: data.clone(),
+        };
+        let contents = toml::to_string(&stored)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        // written aside and renamed so that a concurrent load never sees half a file
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, contents)?;
+        fs::rename(&tmp, &path)
+    }
+
+    fn destroy(&self, id: &str) -> io::Result<()> {
+        match fs::remove_file(self.file(id)?) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+}
+
+// the data of a session file, None once it expired
+fn read(path: &Path) -> io::Result<Option<HashMap<String, String>>> {
+    let contents = fs::read_to_string(path)?;
+    let stored: Stored = toml::from_str(&contents)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    if UNIX_EPOCH + Duration::from_secs(stored.expires) <= SystemTime::now() {
+        return Ok(None);
+    }
+    Ok(Some(stored.data))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::Arc;
+    use std::time::{Duration, SystemTime};
+    use crate::http::cookie::SetCookie;
+    use crate::http::request::Request;
+    use crate::http::response::Response;
+    use crate::server::middleware::{Middleware, Next};
+    use super::{FileStore, MemoryStore, Session, SessionStore, Sessions};
+
+    const SECRET: &[u8] = b"0123456789abcdef0123456789abcdef";
+
+    // a handler counting visits, `/login` renews the session and `/logout` destroys it
+    fn visit(req: Request<String>) -> Response<Vec<u8>> {
+        let session = Session::from_request(&req).unwrap();
+        match req.uri().path() {
+            "/login" => {
+                session.renew();
+                session.insert("user", "alice");
+            },
+            "/logout" => session.destroy(),
+            "/look" => {},
+            _ => {
+                let visits = session.get("visits").map_or(0, |v| v.parse::<u32>().unwrap());
+                session.insert("visits", &(visits + 1).to_string());
+            },
+        }
+        let body = format!("{}:{}", session.get("visits").unwrap_or_default(), session.get("user").unwrap_or_default());
+        Response::new(body.into_bytes())
+    }
+
+    // send a request through the middleware with `cookie`, returning the body and the new cookie value
+    fn send(sessions: &Sessions, path: &str, cookie: Option<&str>) -> (String, Option<SetCookie>) {
+        let mut builder = Request::builder().uri_from_str(path);
+        if let Some(cookie) = cookie {
+            builder = builder.push_header("Cookie", &format!("session={}", cookie));
+        }
+        let chain: Vec<Arc<dyn Middleware>> = vec![Arc::new(sessions.clone())];
+        let res = Next::new(&chain, &visit).run(Request::from_parts(builder.parts(), String::new()));
+        let set = res.header().get("Set-Cookie").and_then(SetCookie::parse);
+        (String::from_utf8(res.body().clone()).unwrap(), set)
+    }
+
+    #[test]
+    fn test_short_secret() {
+        assert!(Sessions::new(b"too short").is_err());
+    }
+    #[test]
+    fn test_cookie_sessions() {
+        for sessions in [Sessions::new(SECRET).unwrap(), Sessions::new(SECRET).unwrap().encrypted(true)] {
+            let (body, set) = send(&sessions, "/", None);
+            assert_eq!(body, "1:");
+            let set = set.unwrap();
+            assert!(set.is_http_only());
+            let (body, set) = send(&sessions, "/", Some(set.value()));
+            assert_eq!(body, "2:");
+            let cookie = set.unwrap().value().to_string();
+            // an unchanged session sets no cookie
+            assert_eq!(send(&sessions, "/look", Some(&cookie)), ("2:".to_string(), None));
+            // a forged or foreign cookie starts a new session
+            let forged = format!("{}x", &cookie[..cookie.len() - 1]);
+            assert_eq!(send(&sessions, "/", Some(&forged)).0, "1:");
+            let other = Sessions::new(b"another secret of at least 32 bytes").unwrap().encrypted(sessions.encrypted);
+            assert_eq!(send(&other, "/", Some(&cookie)).0, "1:");
+            let (_, removal) = send(&sessions, "/logout", Some(&cookie));
+            assert_eq!(removal.unwrap().get_max_age(), Some(0));
+        }
+    }
+    #[test]
+    fn test_store_sessions() {
+        let sessions = Sessions::new(SECRET).unwrap().store(MemoryStore::new());
+        let (_, set) = send(&sessions, "/", None);
+        let first = set.unwrap().value().to_string();
+        let (body, set) = send(&sessions, "/", Some(&first));
+        assert_eq!(body, "2:");
+        // the id stays while the session is only changed
+        assert_eq!(set.unwrap().value(), first);
+        // a login moves the data to a new id and drops the old one
+        let (body, set) = send(&sessions, "/login", Some(&first));
+        assert_eq!(body, "2:alice");
+        let second = set.unwrap().value().to_string();
+        assert_ne!(second, first);
+        assert_eq!(send(&sessions, "/look", Some(&first)).0, ":");
+        assert_eq!(send(&sessions, "/look", Some(&second)).0, "2:alice");
+        send(&sessions, "/logout", Some(&second));
+        assert_eq!(send(&sessions, "/look", Some(&second)).0, ":");
+    }
+    #[test]
+    fn test_expiry() {
+        let sessions = Sessions::new(SECRET).unwrap().ttl(Duration::from_secs(0));
+        let (_, set) = send(&sessions, "/", None);
+        assert_eq!(send(&sessions, "/", Some(set.unwrap().value())).0, "1:");
+
+        let store = MemoryStore::new();
+        let data: HashMap<String, String> = vec![("a".to_string(), "1".to_string())].into_iter().collect();
+        store.save("old", &data, SystemTime::now() - Duration::from_secs(1)).unwrap();
+        assert_eq!(store.load("old").unwrap(), None);
+    }
+    #[test]
+    fn test_file_store() {
+        let dir = std::env::temp_dir().join(format!("rushttp-sessions-{}", std::process::id()));
+        let store = FileStore::new(&dir).unwrap();
+        let data: HashMap<String, String> = vec![("user".to_string(), "a \"quoted\"\nname".to_string())].into_iter().collect();
+        store.save("abc_-1", &data, SystemTime::now() + Duration::from_secs(60)).unwrap();
+        store.save("gone", &data, SystemTime::now() - Duration::from_secs(1)).unwrap();
+        assert_eq!(store.load("abc_-1").unwrap(), Some(data));
+        assert!(store.load("../abc_-1").is_err());
+        assert_eq!(store.prune().unwrap(), 1);
+        store.destroy("abc_-1").unwrap();
+        assert_eq!(store.load("abc_-1").unwrap(), None);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,53 @@
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use crate::server::listener::Listener;
+
+/// A handle to stop a running server.
+/// The listeners stop accepting, connections in progress are finished
+/// and Unix socket files are removed before `Server::serve` returns.
+#[derive(Clone, Default)]
+pub struct Shutdown {
+    inner: Arc<Inner>,
+}
+
+#[derive(Default)]
+struct Inner {
+    triggered: AtomicBool,
+    listeners: Mutex<Vec<Arc<Listener>>>,
+}
+
+impl Shutdown {
+    pub fn trigger(&self) {
+        self.inner.triggered.store(true, Ordering::SeqCst);
+        if let Ok(listeners) = self.inner.listeners.lock() {
+            for listener in listeners.iter() {
+                listener.wake();
+            }
+        }
+    }
+
+    pub fn is_triggered(&self) -> bool {
+        self.inner.triggered.load(Ordering::SeqCst)
+    }
+
+    pub(crate) fn register(&self, listener: Arc<Listener>) {
+        if let Ok(mut listeners) = self.inner.listeners.lock() {
+            listeners.push(listener);
+        }
+    }
+
+    pub(crate) fn clear(&self) {
+        if let Ok(mut listeners) = self.inner.listeners.lock() {
+            listeners.clear();
+        }
+    }
+}
+
+impl fmt::Debug for Shutdown {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Shutdown")
+            .field("triggered", &self.is_triggered())
+            .finish()
+    }
+}
@@ -0,0 +1,603 @@
+use std::fmt;
+use std::io::{self, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant};
+use log::{info, warn};
+use crate::http::parser::Parser;
+use crate::http::request::Request;
+use crate::server::limits::Limits;
+use crate::server::listener::Stream;
+use crate::server::rate_limit::canonical;
+use crate::server::reader::Reader;
+use crate::server::shutdown::Shutdown;
+
+// points of each backend on the hash ring, per unit of weight
+const RING_POINTS: usize = 160;
+// keeps the hash ring of a backend at a few thousand points
+const MAX_WEIGHT: u32 = 100;
+// idle connections are dropped before the usual upstream keep-alive timeout of 5s closes them
+const IDLE_TIMEOUT: Duration = Duration::from_secs(4);
+// health check threads look at the shutdown flag this often
+const CHECK_STEP: Duration = Duration::from_millis(200);
+
+/// How a backend is chosen for a request.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Balance {
+    /// Each backend in turn.
+    RoundRobin,
+    /// The backend with the fewest requests in progress relative to its weight.
+    LeastConnections,
+    /// Each backend in turn, in proportion to its weight.
+    Weighted,
+    /// The same backend for the same key while it is available.
+    Hash(HashKey),
+}
+
+/// What consistent hashing is keyed by.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HashKey {
+    ClientIp,
+    /// The value of a header, the client IP when the header is missing.
+    Header(String),
+}
+
+/// A periodic `GET` of `path` on every backend, answers other than 2xx and 3xx fail it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HealthCheck {
+    pub path: String,
+    pub interval: Duration,
+    pub timeout: Duration,
+    /// Passed checks in a row which bring a backend back.
+    pub passes: u32,
+    /// Failed checks in a row which take a backend out.
+    pub fails: u32,
+}
+
+/// A named group of backends which share the requests of proxy routes.
+/// Backends failing `max_fails` requests in a row are ejected for `fail_timeout`,
+/// and when every backend is down they are all tried rather than failing outright.
+/// Clones share the backends and their state.
+#[derive(Clone)]
+pub struct Upstream {
+    name: String,
+    balance: Balance,
+    backends: Vec<Arc<Backend>>,
+    health_check: Option<HealthCheck>,
+    keepalive: usize,
+    max_fails: u32,
+    fail_timeout: Duration,
+    // (point, backend index) sorted by point
+    ring: Arc<Vec<(u64, usize)>>,
+    state: Arc<State>,
+}
+
+struct State {
+    next: AtomicUsize,
+    // current weights of the smooth weighted round robin
+    weights: Mutex<Vec<i64>>,
+}
+
+struct Backend {
+    address: String,
+    weight: u32,
+    active: AtomicUsize,
+    healthy: AtomicBool,
+    health: Mutex<Health>,
+    // (stream, idle since)
+    idle: Mutex<Vec<(TcpStream, Instant)>>,
+}
+
+#[derive(Default)]
+struct Health {
+    // consecutive health check results
+    passes: u32,
+    fails: u32,
+    // consecutive failed requests
+    failed: u32,
+    ejected_until: Option<Instant>,
+}
+
+/// A connection to a backend, counted as in progress until dropped.
+pub(crate) struct Conn<'a> {
+    upstream: &'a Upstream,
+    backend: &'a Backend,
+    pub stream: Stream,
+    /// Taken from the idle connections, the backend may have closed it meanwhile.
+    pub reused: bool,
+}
+
+impl Default for HealthCheck {
+    fn default() -> Self {
+        HealthCheck {
+            path: "/".to_string(),
+            interval: Duration::from_secs(5),
+            timeout: Duration::from_secs(2),
+            passes: 2,
+            fails: 3,
+        }
+    }
+}
+
+impl Upstream {
+    pub fn new(name: &str, balance: Balance) -> Self {
+        Upstream {
+            name: name.to_string(),
+            balance,
+            backends: Vec::new(),
+            health_check: None,
+            keepalive: 8,
+            max_fails: 1,
+            fail_timeout: Duration::from_secs(10),
+            ring: Arc::default(),
+            state: Arc::new(State::new(0)),
+        }
+    }
+
+    /// Add a backend at `host:port`. Weights only matter to weighted balancing methods,
+    /// they are clamped to 1..=100.
+    pub fn server(self, address: &str, weight: u32) -> Self {
+        let mut backends = self.backends;
+        backends.push(Arc::new(Backend::new(address, weight.clamp(1, MAX_WEIGHT))));
+        Upstream {
+            ring: Arc::new(ring(&backends)),
+            state: Arc::new(State::new(backends.len())),
+            backends,
+            ..self
+        }
+    }
+
+    pub fn health_check(self, check: HealthCheck) -> Self {
+        Upstream {
+            health_check: Some(check),
+            ..self
+        }
+    }
+
+    /// Idle connections kept open to each backend for later requests, 0 closes every connection.
+    pub fn keepalive(self, idle: usize) -> Self {
+        Upstream {
+            keepalive: idle,
+            ..self
+        }
+    }
+
+    /// Eject a backend for `fail_timeout` after `max_fails` failed requests in a row, 0 never ejects.
+    pub fn max_fails(self, max_fails: u32, fail_timeout: Duration) -> Self {
+        Upstream {
+            max_fails,
+            fail_timeout,
+            ..self
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Addresses of the backends.
+    pub fn servers(&self) -> Vec<&str> {
+        self.backends.iter().map(|b| b.address.as_str()).collect()
+    }
+
+    /// Whether the backend at `address` takes requests.
+    pub fn is_available(&self, address: &str) -> bool {
+        let now = Instant::now();
+        self.backends.iter().any(|b| b.address == address && b.is_available(now))
+    }
+
+    /// The address of the backend a request would go to.
+    pub fn select(&self, req: &Request<String>) -> Option<&str> {
+        self.pick(req, &[]).map(|i| self.backends[i].address.as_str())
+    }
+
+    // the backends tried already are skipped
+    fn pick(&self, req: &Request<String>, tried: &[usize]) -> Option<usize> {
+        let now = Instant::now();
+        let mut candidates: Vec<usize> = (0..self.backends.len())
+            .filter(|i| !tried.contains(i) && self.backends[*i].is_available(now))
+            .collect();
+        if candidates.is_empty() && (0..self.backends.len()).all(|i| !self.backends[i].is_available(now)) {
+            candidates = (0..self.backends.len()).filter(|i| !tried.contains(i)).collect();
+        }
+        if candidates.is_empty() {
+            return None;
+        }
+        let next = || self.state.next.fetch_add(1, Ordering::Relaxed);
+        let index = match &self.balance {
+            Balance::RoundRobin => candidates[next() % candidates.len()],
+            Balance::LeastConnections => {
+                // fewest in progress per weight, ties go round
+                let start = next();
+                let load = |i: usize| {
+                    let b = &self.backends[i];
+                    b.active.load(Ordering::Relaxed) as f64 / f64::from(b.weight)
+                };
+                (0..candidates.len())
+                    .map(|k| candidates[(start + k) % candidates.len()])
+                    .fold(None, |best: Option<usize>, i| match best {
+                        Some(b) if load(b) <= load(i) => Some(b),
+                        _ => Some(i),
+                    })?
+            },
+            Balance::Weighted => {
+                let mut weights = lock(&self.state.weights);
+                let total: i64 = candidates.iter().map(|i| i64::from(self.backends[*i].weight)).sum();
+                let mut best = candidates[0];
+                for i in candidates.iter().copied() {
+                    weights[i] += i64::from(self.backends[i].weight);
+                    if weights[i] > weights[best] {
+                        best = i;
+                    }
+                }
+                weights[best] -= total;
+                best
+            },
+            Balance::Hash(key) => {
+                let point = hash(hash_key(req, key).as_bytes());
+                let start = self.ring.partition_point(|(p, _)| *p < point);
+                (0..self.ring.len())
+                    .map(|k| self.ring[(start + k) % self.ring.len()].1)
+                    .find(|i| candidates.contains(i))?
+            },
+        };
+        Some(index)
+    }
+
+    /// Connect to a backend for `req`, trying the others when connecting fails.
+    pub(crate) fn connect(&self, req: &Request<String>, timeout: Duration, reuse: bool) -> io::Result<Conn<'_>> {
+        let mut tried = Vec::new();
+        let mut last = io::Error::new(io::ErrorKind::NotFound, format!("upstream {} has no servers", self.name));
+        while let Some(index) = self.pick(req, &tried) {
+            tried.push(index);
+            let backend = &self.backends[index];
+            if reuse {
+                if let Some(stream) = backend.take_idle() {
+                    return Ok(Conn::new(self, backend, stream, true));
+                }
+            }
+            match backend.connect(timeout) {
+                Ok(stream) => return Ok(Conn::new(self, backend, stream, false)),
+                Err(e) => {
+                    warn!("upstream {}: failed to connect to {}: {}", self.name, backend.address, e);
+                    self.failed(backend);
+                    last = e;
+                },
+            }
+        }
+        Err(last)
+    }
+
+    fn failed(&self, backend: &Backend) {
+        let mut health = lock(&backend.health);
+        health.failed += 1;
+        if self.max_fails > 0 && health.failed >= self.max_fails {
+            if health.ejected_until.is_none() {
+                warn!("upstream {}: ejecting {} for {:?}", self.name, backend.address, self.fail_timeout);
+            }
+            health.ejected_until = Some(Instant::now() + self.fail_timeout);
+            health.failed = 0;
+        }
+    }
+
+    /// Run the health checks until `shutdown` is triggered, does nothing without a health check.
+    pub fn spawn_health_check(&self, shutdown: Shutdown) -> Option<thread::JoinHandle<()>> {
+        let check = self.health_check.clone()?;
+        let upstream = self.clone();
+        Some(thread::spawn(move || {
+            while !shutdown.is_triggered() {
+                for backend in upstream.backends.iter() {
+                    upstream.check(backend, &check);
+                }
+                let next = Instant::now() + check.interval;
+                while !shutdown.is_triggered() && Instant::now() < next {
+                    thread::sleep(CHECK_STEP.min(next.saturating_duration_since(Instant::now())));
+                }
+            }
+        }))
+    }
+
+    fn check(&self, backend: &Backend, check: &HealthCheck) {
+        let passed = probe(&backend.address, check);
+        let mut health = lock(&backend.health);
+        if passed {
+            health.passes += 1;
+            health.fails = 0;
+            if !backend.healthy.load(Ordering::Relaxed) && health.passes >= check.passes {
+                info!("upstream {}: {} is healthy", self.name, backend.address);
+                backend.healthy.store(true, Ordering::Relaxed);
+                // a passing check also ends a passive ejection
+                health.ejected_until = None;
+            }
+        } else {
+            health.fails += 1;
+            health.passes = 0;
+            if backend.healthy.load(Ordering::Relaxed) && health.fails >= check.fails {
+                warn!("upstream {}: {} is unhealthy", self.name, backend.address);
+                backend.healthy.store(false, Ordering::Relaxed);
+                drop(health);
+                lock(&backend.idle).clear();
+            }
+        }
+    }
+
+    pub(crate) fn keeps_alive(&self) -> bool {
+        self.keepalive > 0
+    }
+
+    pub(crate) fn is_same(&self, other: &Upstream) -> bool {
+        Arc::ptr_eq(&self.state, &other.state)
+    }
+}
+
+impl fmt::Debug for Upstream {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Upstream")
+            .field("name", &self.name)
+            .field("balance", &self.balance)
+            .field("servers", &self.servers())
+            .field("health_check", &self.health_check)
+            .field("keepalive", &self.keepalive)
+            .field("max_fails", &self.max_fails)
+            .field("fail_timeout", &self.fail_timeout)
+            .finish()
+    }
+}
+
+impl State {
+    fn new(backends: usize) -> Self {
+        State {
+            next: AtomicUsize::new(0),
+            weights: Mutex::new(vec![0; backends]),
+        }
+    }
+}
+
+impl Backend {
+    fn new(address: &str, weight: u32) -> Self {
+        Backend {
+            address: address.to_string(),
+            weight,
+            active: AtomicUsize::new(0),
+            healthy: AtomicBool::new(true),
+            health: Mutex::default(),
+            idle: Mutex::default(),
+        }
+    }
+
+    fn is_available(&self, now: Instant) -> bool {
+        self.healthy.load(Ordering::Relaxed)
+            && lock(&self.health).ejected_until.is_none_or(|until| now >= until)
+    }
+
+    fn connect(&self, timeout: Duration) -> io::Result<TcpStream> {
+        connect(&self.address, timeout)
+    }
+
+    fn take_idle(&self) -> Option<TcpStream> {
+        let mut idle = lock(&self.idle);
+        while let Some((stream, since)) = idle.pop() {
+            if since.elapsed() < IDLE_TIMEOUT {
+                return Some(stream);
+            }
+        }
+        None
+    }
+}
+
+impl<'a> Conn<'a> {
+    fn new(upstream: &'a Upstream, backend: &'a Backend, stream: TcpStream, reused: bool) -> Self {
+        backend.active.fetch_add(1, Ordering::Relaxed);
+        Conn {
+            upstream,
+            backend,
+            stream: Stream::Tcp(stream),
+            reused,
+        }
+    }
+
+    pub fn address(&self) -> &str {
+        &self.backend.address
+    }
+
+    /// The backend answered, which ends a run of failures.
+    pub fn succeeded(&self) {
+        lock(&self.backend.health).failed = 0;
+    }
+
+    /// The backend failed the request, it may be ejected.
+    pub fn failed(&self) {
+        self.upstream.failed(self.backend);
+    }
+
+    /// Keep the connection for a later request if there is room.
+    pub fn release(self) {
+        if let Stream::Tcp(stream) = &self.stream {
+            let mut idle = lock(&self.backend.idle);
+            if idle.len() < self.upstream.keepalive {
+                if let Ok(stream) = stream.try_clone() {
+                    idle.push((stream, Instant::now()));
+                }
+            }
+        }
+    }
+}
+
+impl Drop for Conn<'_> {
+    fn drop(&mut self) {
+        self.backend.active.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+pub(crate) fn connect(address: &str, timeout: Duration) -> io::Result<TcpStream> {
+    let mut last = io::Error::new(io::ErrorKind::AddrNotAvailable,
+        format!("{} does not resolve to any address", address));
+    for addr in address.to_socket_addrs()? {
+        match TcpStream::connect_timeout(&addr, timeout) {
+            Ok(stream) => return Ok(stream),
+            Err(e) => last = e,
+        }
+    }
+    Err(last)
+}
+
+// one health check request, passed by a 2xx or 3xx answer
+fn probe(address: &str, check: &HealthCheck) -> bool {
+    let stream = match connect(address, check.timeout) {
+        Ok(stream) => stream,
+        Err(_) => return false,
+    };
+    let mut stream = Stream::Tcp(stream);
+    let req = format!("GET {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: rushttp-health-check\r\nConnection: close\r\n\r\n", check.path, address);
+    if stream.set_write_timeout(Some(check.timeout)).and_then(|_| stream.write_all(req.as_bytes())).is_err() {
+        return false;
+    }
+    let head = match Reader::new().read_head(&mut stream, check.timeout, check.timeout, &Limits::default()) {
+        Ok(head) => head,
+        Err(_) => return false,
+    };
+    match Parser::new().parse_response(&head) {
+        Ok(res) => (200..400).contains(&res.status().as_u16()),
+        Err(_) => false,
+    }
+}
+
+fn ring(backends: &[Arc<Backend>]) -> Vec<(u64, usize)> {
+    let mut ring: Vec<(u64, usize)> = backends.iter().enumerate()
+        .flat_map(|(i, b)| {
+            (0..RING_POINTS * b.weight as usize).map(move |k| (hash(format!("{}-{}", b.address, k).as_bytes()), i))
+        })
+        .collect();
+    ring.sort_unstable();
+    ring
+}
+
+fn hash_key(req: &Request<String>, key: &HashKey) -> String {
+    let ip = || req.remote_addr().map(|addr| canonical(addr.ip()).to_string()).unwrap_or_default();
+    match key {
+        HashKey::ClientIp => ip(),
+        HashKey::Header(name) => req.header().get(name).map(|v| v.to_string()).unwrap_or_else(ip),
+    }
+}
+
+// stable across runs and builds, unlike the std hasher
+fn hash(data: &[u8]) -> u64 {
+    let fnv = data.iter().fold(0xcbf2_9ce4_8422_2325, |hash, b| (hash ^ u64::from(*b)).wrapping_mul(0x0100_0000_01b3));
+    // FNV barely changes the high bits for keys which only differ at the end, such as addresses
+    let mut x = fnv ^ (fnv >> 33);
+    x = x.wrapping_mul(0xff51_afd7_ed55_8ccd);
+    x ^= x >> 33;
+    x = x.wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+    x ^ (x >> 33)
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    match mutex.lock() {
+        Ok(guard) => guard,
+        Err(poisoned) => poisoned.into_inner(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::thread;
+    use std::time::Duration;
+    use crate::http::request::Request;
+    use super::{Balance, HashKey, HealthCheck, Upstream};
+
+    fn request(ip: &str) -> Request<String> {
+        let ip: std::net::IpAddr = ip.parse().unwrap();
+        let parts = Request::builder().remote_addr((ip, 5000).into()).parts();
+        Request::from_parts(parts, String::new())
+    }
+
+    fn picks(upstream: &Upstream, n: usize) -> Vec<String> {
+        (0..n).map(|_| upstream.select(&request("10.0.0.1")).unwrap().to_string()).collect()
+    }
+
+    #[test]
+    fn test_round_robin() {
+        let upstream = Upstream::new("api", Balance::RoundRobin)
+            .server("a:80", 1)
+            .server("b:80", 5);
+        assert_eq!(picks(&upstream, 4), ["a:80", "b:80", "a:80", "b:80"]);
+    }
+    #[test]
+    fn test_weighted() {
+        let upstream = Upstream::new("api", Balance::Weighted)
+            .server("a:80", 5)
+            .server("b:80", 1)
+            .server("c:80", 1);
+        // smooth: the heavy backend is spread out
+        assert_eq!(picks(&upstream, 7), ["a:80", "a:80", "b:80", "a:80", "c:80", "a:80", "a:80"]);
+    }
+    #[test]
+    fn test_hash() {
+        let upstream = Upstream::new("api", Balance::Hash(HashKey::ClientIp))
+            .server("a:80", 1)
+            .server("b:80", 1)
+            .server("c:80", 1);
+        let first = upstream.select(&request("10.0.0.7")).unwrap().to_string();
+        assert_eq!(upstream.select(&request("10.0.0.7")).unwrap(), first);
+        assert_eq!(upstream.select(&request("::ffff:10.0.0.7")).unwrap(), first);
+        // an ejected backend only moves its own keys
+        upstream.failed(upstream.backends.iter().find(|b| b.address == first).unwrap());
+        let moved = upstream.select(&request("10.0.0.7")).unwrap();
+        assert_ne!(moved, first);
+        let spread: std::collections::HashSet<String> = (0..50)
+            .map(|i| upstream.select(&request(&format!("10.0.1.{}", i))).unwrap().to_string())
+            .collect();
+        assert!(!spread.contains(&first));
+        assert_eq!(spread.len(), 2);        // the ring does not grow with huge weights
+        let heavy = Upstream::new("api", Balance::Hash(HashKey::ClientIp)).server("a:80", u32::MAX);
+        assert_eq!(heavy.ring.len(), super::RING_POINTS * 100);
+    }
+    #[test]
+    fn test_least_connections() {
+        let upstream = Upstream::new("api", Balance::LeastConnections)
+            .server("a:80", 1)
+            .server("b:80", 1);
+        upstream.backends[0].active.store(2, std::sync::atomic::Ordering::Relaxed);
+        assert_eq!(picks(&upstream, 3), ["b:80", "b:80", "b:80"]);
+    }
+    #[test]
+    fn test_passive_ejection() {
+        let upstream = Upstream::new("api", Balance::RoundRobin)
+            .server("a:80", 1)
+            .server("b:80", 1)
+            .max_fails(2, Duration::from_secs(60));
+        upstream.failed(&upstream.backends[0]);
+        assert!(upstream.is_available("a:80"));
+        upstream.failed(&upstream.backends[0]);
+        assert!(!upstream.is_available("a:80"));
+        assert_eq!(picks(&upstream, 2), ["b:80", "b:80"]);
+        // with every backend down they are all tried
+        upstream.failed(&upstream.backends[1]);
+        upstream.failed(&upstream.backends[1]);
+        assert_eq!(picks(&upstream, 2).len(), 2);
+    }
+    #[test]
+    fn test_health_check() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        thread::spawn(move || {
+            for (i, stream) in listener.incoming().enumerate() {
+                let mut stream = stream.unwrap();
+                let mut buf = [0u8; 1024];
+                let _ = stream.read(&mut buf);
+                let status = if i == 0 { "503 Service Unavailable" } else { "200 OK" };
+                let _ = write!(stream, "HTTP/1.1 {}\r\nContent-Length: 0\r\n\r\n", status);
+            }
+        });
+        let check = HealthCheck { path: "/health".to_string(), passes: 1, fails: 1, ..HealthCheck::default() };
+        let upstream = Upstream::new("api", Balance::RoundRobin).server(&address, 1);
+        upstream.check(&upstream.backends[0], &check);
+        assert!(!upstream.is_available(&address));
+        upstream.check(&upstream.backends[0], &check);
+        assert!(upstream.is_available(&address));
+    }
+}
@@ -0,0 +1,347 @@
+use std::fmt;
+use std::sync::Arc;
+use log::error;
+use crate::http::multipart;
+use crate::http::request::Request;
+use crate::http::response::Response;
+use crate::server::extract::{Endpoint, State};
+use crate::server::handler::{Handler, Handlers};
+use crate::server::limits::Limits;
+use crate::server::middleware::{Middleware, Next};
+use crate::server::proxy::Proxy;
+use crate::server::upstream::Upstream;
+use crate::server::resource::{under_prefix, Mount};
+use crate::server::response::response;
+
+/// A site with its own root, routes and middleware, selected by the request host.
+/// Names are exact host names such as `example.com` or wildcards such as `*.example.com`,
+/// which match any subdomain but not `example.com` itself.
+#[derive(Clone)]
+pub struct VirtualHost {
+    names: Vec<String>,
+    handlers: Handlers<String>,
+    middleware: Vec<Arc<dyn Middleware>>,
+    // longest prefix first
+    limits: Vec<(String, Limits)>,
+    // longest prefix first
+    multipart: Vec<(String, multipart::Limits)>,
+    // longest prefix first
+    proxies: Vec<(String, Proxy)>,
+}
+
+/// The virtual hosts of a server, with the default one answering unknown hosts.
+#[derive(Debug, Clone)]
+pub struct VirtualHosts {
+    default: VirtualHost,
+    hosts: Vec<VirtualHost>,
+}
+
+impl VirtualHost {
+    pub fn new(root: &str) -> Self {
+        VirtualHost {
+            names: Vec::new(),
+            handlers: Handlers::new(root),
+            middleware: Vec::new(),
+            limits: Vec::new(),
+            multipart: Vec::new(),
+            proxies: Vec::new(),
+        }
+    }
+
+    /// Add a host name this virtual host answers to.
+    pub fn name(self, name: &str) -> Self {
+        let mut names = self.names;
+        names.push(name.trim_end_matches('.').to_ascii_lowercase());
+        VirtualHost {
+            names,
+            ..self
+        }
+    }
+
+    pub fn names(&self) -> &[String] {
+        &self.names
+    }
+
+    pub fn register(&mut self, path: &str, method: &str, handler: fn(Request<String>) -> String) {
+        let handler = Handler::new(self.handlers.root().to_path_buf(), path, method, handler);
+        self.handlers.add(handler);
+    }
+
+    /// Register a handler with typed arguments, see `extract`.
+    pub fn handle<E: Endpoint<Args>, Args>(&mut self, path: &str, method: &str, endpoint: E) {
+        let handler = Handler::typed(self.handlers.root().to_path_buf(), path, method, endpoint);
+        self.handlers.add(handler);
+    }
+
+    /// Share `state` with the handlers, which take it as a `State<T>` argument.
+    pub fn state<T: Clone + Send + Sync + 'static>(&mut self, state: T) {
+        self.middleware(move |mut req: Request<String>, next: Next<'_>| {
+            req.extensions_mut().insert(State(state.clone()));
+            next.run(req)
+        });
+    }
+
+    pub fn mount(&mut self, prefix: &str, dir: &str) {
+        self.handlers.mount(Mount::new(prefix, dir));
+    }
+
+    pub fn header(&mut self, name: &str, value: &str) {
+        self.handlers.add_header(name, value);
+    }
+
+    /// Append a middleware, they run in the order they are added.
+    pub fn middleware<M: Middleware + 'static>(&mut self, middleware: M) {
+        self.middleware.push(Arc::new(middleware));
+    }
+
+    /// Forward requests for paths starting with `prefix` to an upstream.
+    /// Registered handlers take precedence, static files come after.
+    pub fn proxy(&mut self, prefix: &str, proxy: Proxy) {
+        let prefix = if prefix == "/" { prefix } else { prefix.trim_end_matches('/') };
+        self.proxies.push((prefix.to_string(), proxy));
+        self.proxies.sort_by_key(|(prefix, _)| std::cmp::Reverse(prefix.len()));
+    }
+
+    /// The proxy route of a request with its prefix, if it is not handled by a registered handler.
+    pub fn proxy_for(&self, req: &Request<String>) -> Option<(&str, &Proxy)> {
+        let path = req.uri().path();
+        if self.handlers.find(path, req.method().as_str()).is_some() {
+            return None;
+        }
+        self.proxies.iter()
+            .find(|(prefix, _)| under_prefix(prefix, path))
+            .map(|(prefix, proxy)| (prefix.as_str(), proxy))
+    }
+
+    // headers added to every response
+    pub(crate) fn headers(&self) -> &[(String, String)] {
+        self.handlers.headers()
+    }
+
+    /// Apply `limits` instead of the server ones to request paths starting with `prefix`.
+    /// The request line and headers are read before the route is known, so their limits
+    /// can only be tightened here, the body limit can also be raised.
+    pub fn limit(&mut self, prefix: &str, limits: Limits) {
+        self.limits.push((prefix.to_string(), limits));
+        self.limits.sort_by_key(|(prefix, _)| std::cmp::Reverse(prefix.len()));
+    }
+
+    /// The limits for a request path, if its route overrides them.
+    pub fn limits(&self, path: &str) -> Option<&Limits> {
+        self.limits.iter()
+            .find(|(prefix, _)| under_prefix(prefix, path))
+            .map(|(_, limits)| limits)
+    }
+
+    /// Parse `multipart/form-data` bodies of request paths starting with `prefix` as they arrive,
+    /// large files going to temporary files, instead of reading them into the request body.
+    /// The parts are attached to the request as a `Multipart` and the body is left empty.
+    /// The body limit of the route still applies, raise it with `limit` for large uploads.
+    pub fn multipart(&mut self, prefix: &str, limits: multipart::Limits) {
+        self.multipart.push((prefix.to_string(), limits));
+        self.multipart.sort_by_key(|(prefix, _)| std::cmp::Reverse(prefix.len()));
+    }
+
+    /// The multipart limits for a request path, if its route parses uploads.
+    pub fn multipart_limits(&self, path: &str) -> Option<&multipart::Limits> {
+        self.multipart.iter()
+            .find(|(prefix, _)| under_prefix(prefix, path))
+            .map(|(_, limits)| limits)
+    }
+
+    pub fn routes(&self) -> Vec<String> {
+        let mut routes = self.handlers.routes();
+        for (prefix, proxy) in self.proxies.iter() {
+            routes.push(format!("{:<7} {} -> {}", "PROXY", prefix, proxy.upstream().name()));
+        }
+        routes
+    }
+
+    // how well `host` matches, exact names beat wildcards and longer wildcards beat shorter ones
+    fn score(&self, host: &str) -> Option<usize> {
+        self.names.iter()
+            .filter_map(|name| match name.strip_prefix('*') {
+                Some(suffix) => {
+                    if host.len() > suffix.len() && host.ends_with(suffix) { Some(suffix.len()) } else { None }
+                },
+                None => if name == host { Some(usize::MAX) } else { None },
+            })
+            .max()
+    }
+
+    /// The route a request is handled by, used to label metrics.
+    pub fn route(&self, req: &Request<String>) -> Option<String> {
+        match self.proxy_for(req) {
+            Some((prefix, _)) => Some(prefix.to_string()),
+            None => self.handlers.route(req.uri().path(), req.method().as_str()),
+        }
+    }
+
+    pub fn dispatch(&self, req: Request<String>) -> Response<Vec<u8>> {
+        let endpoint = |req: Request<String>| {
+            match self.handlers.dispatch(req) {
+                Ok(res) => res,
+                Err(e) => {
+                    error!("failed to handle: {:?}", e);
+                    internal_server_error(self.handlers.headers())
+                },
+            }
+        };
+        self.dispatch_with(req, &endpoint)
+    }
+
+    /// Run the middleware chain with `endpoint` in place of the handlers.
+    pub fn dispatch_with(&self, req: Request<String>, endpoint: &dyn Fn(Request<String>) -> Response<Vec<u8>>) -> Response<Vec<u8>> {
+        Next::new(&self.middleware, endpoint).run(req)
+    }
+}
+
+impl fmt::Debug for VirtualHost {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("VirtualHost")
+            .field("names", &self.names)
+            .field("handlers", &self.handlers)
+            .field("middleware", &self.middleware.len())
+            .field("limits", &self.limits)
+            .field("multipart", &self.multipart)
+            .field("proxies", &self.proxies)
+            .finish()
+    }
+}
+
+impl VirtualHosts {
+    pub fn new(default: VirtualHost) -> Self {
+        VirtualHosts {
+            default,
+            hosts: Vec::new(),
+        }
+    }
+
+    pub fn add(&mut self, host: VirtualHost) {
+        self.hosts.push(host);
+    }
+
+    pub fn default_mut(&mut self) -> &mut VirtualHost {
+        &mut self.default
+    }
+
+    /// Describe the routes of every virtual host, one per line.
+    pub fn routes(&self) -> Vec<String> {
+        let mut routes = self.default.routes();
+        for vhost in self.hosts.iter() {
+            let names = vhost.names.join(",");
+            routes.extend(vhost.routes().into_iter().map(|route| format!("{} {}", names, route)));
+        }
+        routes
+    }
+
+    /// The upstream groups of every proxy route, each once.
+    pub fn upstreams(&self) -> Vec<&Upstream> {
+        let mut upstreams: Vec<&Upstream> = Vec::new();
+        let proxies = self.default.proxies.iter().chain(self.hosts.iter().flat_map(|vhost| vhost.proxies.iter()));
+        for (_, proxy) in proxies {
+            if !upstreams.iter().any(|u| u.is_same(proxy.upstream())) {
+                upstreams.push(proxy.upstream());
+            }
+        }
+        upstreams
+    }
+
+    /// Pick the virtual host for a request.
+    /// The authority of an absolute-form target wins over the `Host` header.
+    pub fn select(&self, req: &Request<String>) -> &VirtualHost {
+        let host = match req.uri().host() {
+            Some(h) if !h.is_empty() => Some(h),
+            _ => req.header().get("Host"),
+        };
+        let host = match host {
+            Some(h) => normalize(h),
+            None => return &self.default,
+        };
+        self.hosts.iter()
+            .filter_map(|vhost| vhost.score(&host).map(|score| (score, vhost)))
+            // the first registered host wins a tie
+            .fold(None, |best: Option<(usize, &VirtualHost)>, (score, vhost)| match best {
+                Some((s, _)) if s >= score => best,
+                _ => Some((score, vhost)),
+            })
+            .map(|(_, vhost)| vhost)
+            .unwrap_or(&self.default)
+    }
+}
+
+// strip the port and the trailing dot, host names are case-insensitive
+fn normalize(host: &str) -> String {
+    let host = host.trim();
+    let host = if host.starts_with('[') {
+        match host.find(']') {
+            Some(end) => &host[..=end],
+            None => host,
+        }
+    } else {
+        match host.rsplit_once(':') {
+            Some((name, port)) if port.bytes().all(|b| b.is_ascii_digit()) => name,
+            _ => host,
+        }
+    };
+    host.trim_end_matches('.').to_ascii_lowercase()
+}
+
+fn internal_server_error(headers: &[(String, String)]) -> Response<Vec<u8>> {
+    response(500, Some("Internal Server Error"), headers)
+        .unwrap_or_else(|_| Response::new(Vec::new()))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::http::request::Request;
+
+    fn request(host: &str) -> Request<String> {
+        let parts = Request::builder().push_header("Host", host).parts();
+        Request::from_parts(parts, String::new())
+    }
+
+    fn hosts() -> super::VirtualHosts {
+        let mut hosts = super::VirtualHosts::new(super::VirtualHost::new("/default"));
+        hosts.add(super::VirtualHost::new("/example").name("example.com").name("www.example.com"));
+        hosts.add(super::VirtualHost::new("/wildcard").name("*.example.com"));
+        hosts.add(super::VirtualHost::new("/api").name("*.api.example.com"));
+        hosts
+    }
+
+    #[test]
+    fn test_select() {
+        let hosts = hosts();
+        assert_eq!(hosts.select(&request("example.com")).names()[0], "example.com");
+        assert_eq!(hosts.select(&request("WWW.Example.com:8080")).names()[0], "example.com");
+        assert_eq!(hosts.select(&request("blog.example.com")).names()[0], "*.example.com");
+        assert_eq!(hosts.select(&request("v1.api.example.com")).names()[0], "*.api.example.com");
+        assert!(hosts.select(&request("example.org")).names().is_empty());
+    }
+    #[test]
+    fn test_select_absolute_form() {
+        let hosts = hosts();
+        let parts = Request::builder()
+            .uri_from_str("http://blog.example.com/index.html")
+            .push_header("Host", "example.org")
+            .parts();
+        let req = Request::from_parts(parts, String::new());
+        assert_eq!(hosts.select(&req).names()[0], "*.example.com");
+    }
+    #[test]
+    fn test_limits() {
+        let mut vhost = super::VirtualHost::new("/default");
+        let upload = super::Limits { max_body: 100 * 1024 * 1024, ..super::Limits::default() };
+        vhost.limit("/upload", upload);
+        vhost.limit("/upload/small", super::Limits::default());
+        assert_eq!(vhost.limits("/upload/file.bin"), Some(&upload));
+        assert_eq!(vhost.limits("/upload/small/a"), Some(&super::Limits::default()));
+        assert_eq!(vhost.limits("/uploads"), None);
+    }
+    #[test]
+    fn test_normalize() {
+        assert_eq!(super::normalize("Example.COM."), "example.com");
+        assert_eq!(super::normalize("example.com:80"), "example.com");
+        assert_eq!(super::normalize("[::1]:8080"), "[::1]");
+    }
+}
@@ -16,30 +16,37 @@ pub struct UserInfo {
 
 impl Authority {
     pub fn new(uri: &str) -> Option<Self> {
-        let user_info = UserInfo::new(uri);
-        let re_host = Regex::new("(//|@)([0-9a-z]{0,62}\\.)+[a-z]+").unwrap();
-        let re_port = Regex::new(":\\d{1,5}").unwrap();
-        let host = match re_host.captures(uri) {
-            Some(caps) => {
-                let mut h = caps.index(0 as usize);
-                match h.chars().next().unwrap() {
-                    '/' => h[2..].to_string(),
-                    '@' => h[1..].to_string(),
-                    _ => return None,
-                }
-            },
-            None => return None,
+        // only an absolute uri has an authority, `//` in a path does not start one
+        let (scheme, rest) = uri.split_once("://")?;
+        if scheme.is_empty() || !scheme.chars().all(|c| c.is_ascii_alphanumeric() || "+-.".contains(c)) {
+            return None;
+        }
+        let end = rest.find(['/', '?', '#']).unwrap_or(rest.len());
+        let authority = &rest[..end];
+        let (user_info, host_port) = match authority.rsplit_once('@') {
+            Some((info, host_port)) => (UserInfo::new(&format!("{}@", info)), host_port),
+            None => (None, authority),
+        };
+        // an IPv6 host keeps its brackets, so that `host:port` stays unambiguous
+        let (host, port) = if host_port.starts_with('[') {
+            let close = host_port.find(']')?;
+            (&host_port[..=close], host_port[close + 1..].strip_prefix(':'))
+        } else {
+            match host_port.split_once(':') {
+                Some((host, port)) => (host, Some(port)),
+                None => (host_port, None),
+            }
         };
-        let port: Option<usize> = match re_port.captures(uri) {
-            Some(caps) => match caps.index(0)[1..].parse() {
-                Ok(p) => Some(p),
-                _ => None,
-            },
+        if host.is_empty() {
+            return None;
+        }
+        let port = match port {
+            Some(port) => Some(port.parse().ok()?),
             None => None,
         };
         Some(Authority{
             user_info,
-            host,
+            host: host.to_ascii_lowercase(),
             port,
         })
     }
@@ -162,6 +169,16 @@ mod tests {
         }));
     }
     #[test]
+    fn test_new_authority_with_address() {
+        let authority = super::Authority::new("http://127.0.0.1:8080/index.html?a=b").unwrap();
+        assert_eq!((authority.host(), authority.port()), ("127.0.0.1", Some(8080)));
+        let authority = super::Authority::new("http://[::1]:8080").unwrap();
+        assert_eq!((authority.host(), authority.port()), ("[::1]", Some(8080)));
+        let authority = super::Authority::new("http://LocalHost/a").unwrap();
+        assert_eq!((authority.host(), authority.port()), ("localhost", None));
+        assert_eq!(super::Authority::new("http://localhost:http/"), None);
+    }
+    #[test]
     fn test_new_authority_none() {
         let uri = "/hoge/fuga/index.html";
         assert_eq!(super::Authority::new(uri), None);
@@ -1,6 +1,7 @@
 use std::collections::HashMap;
 use regex::Regex;
 use std::ops::Index;
+use crate::http::form::Form;
 
 #[derive(Debug, Eq, PartialEq)]
 pub struct Query {
@@ -15,9 +16,10 @@ impl Query {
             Some(caps) => caps.index(0 as usize).to_string(),
             None => return None,
         };
-        for key_value in query_string[1..].split('&') {
-            let q: Vec<&str> = key_value.split('=').collect();
-            queries.insert(q[0].to_string(), q[1].to_string());
+        // the fragment is not part of the query, of repeated keys the last one is kept
+        let query_string = query_string[1..].split('#').next().unwrap_or_default();
+        for (key, value) in Form::parse(query_string).iter() {
+            queries.insert(key.to_string(), value.to_string());
         }
         Some(Query{query: queries})
     }
@@ -43,6 +45,14 @@ mod tests {
         assert_eq!(super::Query::new(uri), Some(super::Query{query: wanted}));
     }
     #[test]
+    fn test_new_query_decoded() {
+        let uri = "/search?q=rust+http&flag&lang=%E6%97%A5#top";
+        let query = super::Query::new(uri).unwrap().query;
+        assert_eq!(query.get("q").map(String::as_str), Some("rust http"));
+        assert_eq!(query.get("flag").map(String::as_str), Some(""));
+        assert_eq!(query.get("lang").map(String::as_str), Some("日"));
+    }
+    #[test]
     fn test_new_empy_query() {
         let uri = "http://terassy.net/hoge/fuga/index.html";
         assert_eq!(super::Query::new(uri), None);
@@ -11,6 +11,7 @@ use std::fmt;
 
 #[derive(Debug, Eq, PartialEq)]
 pub struct Uri {
+    raw: String,
     scheme: Option<Scheme>,
     authority: Option<Authority>,
     path_and_query: Option<PathAndQuery>,
@@ -31,12 +32,80 @@ impl Uri {
         let authority = Authority::new(uri);
         let path_and_query = PathAndQuery::new(uri);
         Some(Uri {
+            raw: uri.to_string(),
             scheme,
             authority,
             path_and_query
         })
     }
 
+    /// The uri as it was given.
+    pub fn as_str(&self) -> &str {
+        &self.raw
+    }
+
+    /// The path and query as sent in a request line, `/` when an absolute uri has no path.
+    pub fn origin_form(&self) -> String {
+        let rest = match self.raw.split_once("://") {
+            Some((_, rest)) => rest.find(['/', '?']).map(|i| &rest[i..]).unwrap_or(""),
+            // already a path, or `*`
+            None => return self.raw.clone(),
+        };
+        // the fragment is not sent
+        let rest = rest.split('#').next().unwrap_or_default();
+        match rest.starts_with('/') {
+            true => rest.to_string(),
+            false => format!("/{}", rest),
+        }
+    }
+
+    /// Resolve a reference such as a `Location` header against this absolute uri.
+    pub fn join(&self, reference: &str) -> Option<Uri> {
+        let reference = reference.trim();
+        let reference = reference.split('#').next().unwrap_or_default();
+        if Authority::new(reference).is_some() || Scheme::new(reference).is_some() {
+            return Uri::new(reference);
+        }
+        let (scheme, rest) = self.raw.split_once("://")?;
+        if let Some(network) = reference.strip_prefix("//") {
+            return Uri::new(&format!("{}://{}", scheme, network));
+        }
+        let rest = rest.split('#').next().unwrap_or_default();
+        let end = rest.find(['/', '?']).unwrap_or(rest.len());
+        let (authority, path_query) = rest.split_at(end);
+        let (path, query) = match path_query.split_once('?') {
+            Some((path, query)) => (path, Some(query)),
+            None => (path_query, None),
+        };
+        let target = if reference.is_empty() {
+            match query {
+                Some(query) => format!("{}?{}", path, query),
+                None => path.to_string(),
+            }
+        } else if reference.starts_with('?') {
+            format!("{}{}", path, reference)
+        } else {
+            let (ref_path, ref_query) = match reference.split_once('?') {
+                Some((p, q)) => (p, Some(q)),
+                None => (reference, None),
+            };
+            let merged = if ref_path.starts_with('/') {
+                ref_path.to_string()
+            } else {
+                // relative to the directory of the base path
+                let dir = path.rfind('/').map(|i| &path[..=i]).unwrap_or("/");
+                format!("{}{}", dir, ref_path)
+            };
+            let merged = remove_dot_segments(&merged);
+            match ref_query {
+                Some(q) => format!("{}?{}", merged, q),
+                None => merged,
+            }
+        };
+        let target = if target.starts_with('/') { target } else { format!("/{}", target) };
+        Uri::new(&format!("{}://{}{}", scheme, authority, target))
+    }
+
     pub fn scheme(&self) -> Option<String> {
         match self.scheme {
             Some(s) => s.protocol.to_string().ok(),
@@ -97,6 +166,29 @@ impl Uri {
     }
 }
 
+// RFC 3986 5.2.4
+fn remove_dot_segments(path: &str) -> String {
+    let mut out: Vec<&str> = Vec::new();
+    let segments: Vec<&str> = path.split('/').collect();
+    for (i, segment) in segments.iter().enumerate() {
+        let last = i == segments.len() - 1;
+        match *segment {
+            "." => if last { out.push("") },
+            ".." => {
+                if out.len() > 1 {
+                    out.pop();
+                }
+                if last {
+                    out.push("");
+                }
+            },
+            s => out.push(s),
+        }
+    }
+    let joined = out.join("/");
+    if joined.starts_with('/') { joined } else { format!("/{}", joined) }
+}
+
 impl PathAndQuery {
     fn new(uri: &str) -> Option<PathAndQuery> {
         Some(PathAndQuery{
@@ -113,13 +205,14 @@ impl PathAndQuery {
     }
 
     fn query(&self) -> Option<HashMap<String, String>> {
-        self.query()
+        self.query.as_ref().map(|q| q.query.clone())
     }
 }
 
 impl Default for Uri {
     fn default() -> Self {
         Uri {
+            raw: "".to_string(),
             scheme: None,
             authority: None,
             path_and_query: None
@@ -159,6 +252,32 @@ mod tests {
         assert_eq!(res.unwrap().path_and_query.unwrap().path.unwrap(), super::Path{path: "/hogehoge/fuugafuga/index.php".to_string()});
     }
     #[test]
+    fn test_as_str() {
+        let uri = "/hoge/fuga/index.html?query=test";
+        assert_eq!(super::Uri::new(uri).unwrap().as_str(), uri);
+    }
+    #[test]
+    fn test_origin_form() {
+        assert_eq!(super::Uri::new("/a?b=c").unwrap().origin_form(), "/a?b=c");
+        assert_eq!(super::Uri::new("http://example.com/a").unwrap().origin_form(), "/a");
+        assert_eq!(super::Uri::new("http://example.com").unwrap().origin_form(), "/");
+        assert_eq!(super::Uri::new("http://example.com?a=b").unwrap().origin_form(), "/?a=b");
+    }
+    #[test]
+    fn test_join() {
+        let base = super::Uri::new("http://example.com:8080/a/b/c?q=1").unwrap();
+        let join = |r: &str| base.join(r).unwrap().as_str().to_string();
+        assert_eq!(join("https://other.example/x"), "https://other.example/x");
+        assert_eq!(join("//other.example/x"), "http://other.example/x");
+        assert_eq!(join("/x?y=2"), "http://example.com:8080/x?y=2");
+        assert_eq!(join("d"), "http://example.com:8080/a/b/d");
+        assert_eq!(join("../d/./e"), "http://example.com:8080/a/d/e");
+        assert_eq!(join("../../../../d"), "http://example.com:8080/d");
+        assert_eq!(join("?z=3"), "http://example.com:8080/a/b/c?z=3");
+        assert_eq!(join("./"), "http://example.com:8080/a/b/");
+        assert_eq!(join(""), "http://example.com:8080/a/b/c?q=1");
+    }
+    #[test]
     fn test_new_uri_from_path() {
         let uri = "/hoge/fuga/index.html";
         let res = super::Uri::new(uri);